@@ -0,0 +1,263 @@
+use crate::help;
+use lsp_types::{CompletionItem, CompletionItemKind};
+use rms_check::{
+    ArgType, Atom, AtomKind, ByteIndex, Compatibility, Nesting, ParseState, Parser, RMSFile,
+    SourceLocation, TokenContext, TokenType, TOKENS,
+};
+
+/// What kind of word is expected at the cursor.
+#[derive(Debug, Clone, Copy)]
+enum Expected {
+    /// A command, attribute, section or flow keyword.
+    Command,
+    /// An argument of the given type.
+    Argument(ArgType),
+    /// Something we cannot complete, like the name of a new `#const`.
+    Nothing,
+}
+
+/// Where the cursor is, structurally.
+struct Context<'a> {
+    /// The command that opened the block the cursor is in.
+    block: Option<&'a str>,
+    /// The section the cursor is in.
+    section: Option<&'a str>,
+    /// The innermost `if` or `start_random` statement the cursor is in.
+    flow: Option<&'a Nesting<'a>>,
+}
+
+impl Context<'_> {
+    fn allows(&self, token: &TokenType, context: &TokenContext) -> bool {
+        let in_section = |section: &Option<&str>| section.is_none() || *section == self.section;
+        match context {
+            TokenContext::Flow => self.allows_flow(token.name),
+            TokenContext::Section => self.block.is_none(),
+            TokenContext::Command(section) | TokenContext::TopLevelAttribute(section) => {
+                self.block.is_none() && in_section(section)
+            }
+            TokenContext::Attribute(block) => match self.block {
+                Some(current) => block.is_none_or(|block| block == current),
+                None => false,
+            },
+            TokenContext::AnyOf(contexts) => {
+                contexts.iter().any(|context| self.allows(token, context))
+            }
+        }
+    }
+
+    fn allows_flow(&self, name: &str) -> bool {
+        match name {
+            "elseif" | "else" | "endif" => matches!(
+                self.flow,
+                Some(Nesting::If(_)) | Some(Nesting::ElseIf(_)) | Some(Nesting::Else(_))
+            ),
+            "percent_chance" | "end_random" => matches!(
+                self.flow,
+                Some(Nesting::StartRandom(_)) | Some(Nesting::PercentChance(_))
+            ),
+            // Includes are only used at the top of builtin maps.
+            "#include" | "#include_drs" => self.block.is_none(),
+            _ => true,
+        }
+    }
+}
+
+/// Find the command that opened the block at the end of the atoms, if any.
+fn current_block<'a, 'b: 'a>(atoms: impl Iterator<Item = &'a Atom<'b>>) -> Option<&'b str> {
+    let mut last_command = None;
+    let mut blocks = vec![];
+    for atom in atoms {
+        match &atom.kind {
+            AtomKind::Command { name, .. } => last_command = Some(name.value),
+            AtomKind::OpenBlock { .. } => blocks.push(last_command.unwrap_or("")),
+            AtomKind::CloseBlock { .. } => {
+                blocks.pop();
+            }
+            _ => (),
+        }
+    }
+    blocks.pop()
+}
+
+fn argument_type(command: &str, index: usize) -> Expected {
+    TOKENS
+        .get(command)
+        .and_then(|token| *token.arg_type(index as u8))
+        .map_or(Expected::Nothing, Expected::Argument)
+}
+
+/// Work out what is being typed at the end of `atoms`. Returns the start of the partially typed
+/// word and what kind of word it should be.
+fn expected_at(atoms: &[Atom<'_>], position: ByteIndex) -> (ByteIndex, Expected) {
+    let last = match atoms.last() {
+        Some(last) => last,
+        None => return (position, Expected::Command),
+    };
+
+    // The cursor is at the end of a word that is being typed.
+    if last.location.end() == position {
+        return match &last.kind {
+            AtomKind::Other { value } => (value.location.start(), Expected::Command),
+            AtomKind::Command { name, arguments } => match arguments.last() {
+                Some(arg) => (
+                    arg.location.start(),
+                    argument_type(name.value, arguments.len() - 1),
+                ),
+                None => (name.location.start(), Expected::Command),
+            },
+            AtomKind::If { condition, .. } | AtomKind::ElseIf { condition, .. } => (
+                condition.location.start(),
+                Expected::Argument(ArgType::OptionalToken),
+            ),
+            AtomKind::PercentChance { chance, .. } => {
+                (chance.location.start(), Expected::Argument(ArgType::Number))
+            }
+            AtomKind::Section { name } => (name.location.start(), Expected::Command),
+            AtomKind::Else { head }
+            | AtomKind::EndIf { head }
+            | AtomKind::StartRandom { head }
+            | AtomKind::EndRandom { head } => (head.location.start(), Expected::Command),
+            AtomKind::OpenBlock { .. } | AtomKind::CloseBlock { .. } => {
+                (position, Expected::Command)
+            }
+            _ => (position, Expected::Nothing),
+        };
+    }
+
+    // The cursor is after some whitespace.
+    let expected = match &last.kind {
+        AtomKind::Other { value } => match value.value {
+            "if" | "elseif" => Expected::Argument(ArgType::OptionalToken),
+            "percent_chance" => Expected::Argument(ArgType::Number),
+            "#const" | "#define" | "#undefine" => Expected::Nothing,
+            _ => Expected::Command,
+        },
+        AtomKind::Command { name, arguments } => match argument_type(name.value, arguments.len()) {
+            Expected::Nothing => Expected::Command,
+            expected => expected,
+        },
+        AtomKind::Const { value: None, .. } => Expected::Nothing,
+        AtomKind::Comment { close: None, .. } => Expected::Nothing,
+        _ => Expected::Command,
+    };
+    (position, expected)
+}
+
+fn command_kind(context: &TokenContext) -> CompletionItemKind {
+    match context {
+        TokenContext::Flow => CompletionItemKind::KEYWORD,
+        TokenContext::Section => CompletionItemKind::MODULE,
+        TokenContext::Command(_) => CompletionItemKind::FUNCTION,
+        TokenContext::TopLevelAttribute(_) | TokenContext::Attribute(_) => {
+            CompletionItemKind::PROPERTY
+        }
+        TokenContext::AnyOf(contexts) => contexts
+            .first()
+            .map_or(CompletionItemKind::PROPERTY, command_kind),
+    }
+}
+
+fn command_completions(context: &Context<'_>) -> Vec<CompletionItem> {
+    TOKENS
+        .values()
+        .filter(|token| context.allows(token, token.context()))
+        .map(|token| {
+            let signature = help::get_signature(token.name);
+            CompletionItem {
+                label: token.name.to_string(),
+                kind: Some(command_kind(token.context())),
+                detail: signature.map(|signature| signature.label.clone()),
+                documentation: signature.and_then(|signature| signature.documentation.clone()),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Offer the `#const` names that are in scope.
+///
+/// This does not look at what the argument is for: `ArgType::Token` does not say whether a command
+/// expects an object, a terrain or some other kind of constant, so every `#const` is offered.
+fn const_completions(state: &ParseState<'_>) -> Vec<CompletionItem> {
+    state
+        .consts()
+        .filter(|name| {
+            state
+                .get_const(name)
+                .is_none_or(|definition| state.is_in_scope(definition))
+        })
+        .map(|name| {
+            let detail = match state.resolve_const(name) {
+                Some(value) => format!("#const {} {}", name, value),
+                None => format!("#const {}", name),
+            };
+            CompletionItem {
+                label: name.to_string(),
+                kind: Some(CompletionItemKind::CONSTANT),
+                detail: Some(detail),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+fn define_completions(state: &ParseState<'_>) -> Vec<CompletionItem> {
+    state
+        .defines()
+        .filter(|name| {
+            state
+                .get_define(name)
+                .is_none_or(|definition| state.is_in_scope(definition))
+        })
+        .map(|name| CompletionItem {
+            label: name.to_string(),
+            kind: Some(CompletionItemKind::CONSTANT),
+            detail: Some(format!("#define {}", name)),
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Find completions at the given position. Returns the location of the partially typed word, which
+/// should be replaced by the completion, and the completion items.
+pub fn find_completions(
+    file: &RMSFile<'_>,
+    compatibility: Compatibility,
+    position: ByteIndex,
+) -> (SourceLocation, Vec<CompletionItem>) {
+    let source = &file.main_source()[..usize::from(position)];
+    let atoms = Parser::new(file.file_id(), source)
+        .map(|(atom, _)| atom)
+        .collect::<Vec<_>>();
+    let (start, expected) = expected_at(&atoms, position);
+    let word = SourceLocation::new(file.file_id(), start..position);
+
+    let (state, _) = file.parse_to(file.file_id(), start, compatibility);
+    let items = match expected {
+        Expected::Command => {
+            let context = Context {
+                block: current_block(atoms.iter().filter(|atom| atom.location.end() <= start)),
+                section: state
+                    .current_section
+                    .as_ref()
+                    .and_then(|atom| match &atom.kind {
+                        AtomKind::Section { name } => Some(name.value),
+                        _ => None,
+                    }),
+                flow: state
+                    .nesting
+                    .iter()
+                    .rev()
+                    .find(|nesting| !matches!(nesting, Nesting::Brace(_))),
+            };
+            command_completions(&context)
+        }
+        Expected::Argument(ArgType::Token) | Expected::Argument(ArgType::Number) => {
+            const_completions(&state)
+        }
+        Expected::Argument(ArgType::OptionalToken) => define_completions(&state),
+        Expected::Argument(_) | Expected::Nothing => vec![],
+    };
+
+    (word, items)
+}
@@ -1,35 +1,40 @@
 use lsp_types::{FoldingRange, FoldingRangeKind};
-use rms_check::{AtomKind, ByteIndex, Parser, RMSFile};
+use rms_check::{
+    AtomKind, BlockNode, Branch, ByteIndex, Node, PositionEncoding, RMSFile, SyntaxTree, Token,
+    Trivia,
+};
+use std::collections::VecDeque;
 use std::ops::{Bound, RangeBounds};
 
 #[derive(Debug)]
 pub struct FoldingRanges<'a> {
     file: &'a RMSFile<'a>,
-    parser: Parser<'a>,
-    waiting_folds: Vec<AtomKind<'a>>,
-    queued: Vec<FoldingRange>,
+    encoding: PositionEncoding,
+    queued: VecDeque<FoldingRange>,
 }
 
 impl<'a> FoldingRanges<'a> {
-    pub fn new(file: &'a RMSFile<'a>) -> Self {
-        let parser = Parser::new(file.file_id(), file.main_source());
-        Self {
+    pub fn new(file: &'a RMSFile<'a>, encoding: PositionEncoding) -> Self {
+        let tree = SyntaxTree::parse(file.file_id(), file.main_source());
+        let mut folder = Self {
             file,
-            parser,
-            waiting_folds: vec![],
-            queued: vec![],
-        }
+            encoding,
+            queued: VecDeque::new(),
+        };
+        folder.visit_nodes(&tree.nodes);
+        folder.visit_trivia(&tree.trailing_trivia);
+        folder
     }
 
     fn line(&self, index: ByteIndex) -> u32 {
         self.file
-            .get_location(self.file.file_id(), index)
+            .get_location(self.file.file_id(), index, self.encoding)
             .unwrap()
             .0
     }
 
     fn push(&mut self, range: FoldingRange) {
-        self.queued.push(range);
+        self.queued.push_back(range);
     }
 
     fn fold_lines(&mut self, range: impl RangeBounds<ByteIndex>, kind: Option<FoldingRangeKind>) {
@@ -57,18 +62,24 @@ impl<'a> FoldingRanges<'a> {
     fn fold(&mut self, range: impl RangeBounds<ByteIndex>, kind: Option<FoldingRangeKind>) {
         let (start_line, start_character) = match range.start_bound() {
             Bound::Unbounded => (0u32, 0u32),
-            Bound::Included(index) => self.file.get_location(self.file.file_id(), *index).unwrap(),
+            Bound::Included(index) => self
+                .file
+                .get_location(self.file.file_id(), *index, self.encoding)
+                .unwrap(),
             Bound::Excluded(index) => self
                 .file
-                .get_location(self.file.file_id(), *index + 1)
+                .get_location(self.file.file_id(), *index + 1, self.encoding)
                 .unwrap(),
         };
         let (end_line, end_character) = match range.end_bound() {
             Bound::Unbounded => (0u32, 0u32),
-            Bound::Included(index) => self.file.get_location(self.file.file_id(), *index).unwrap(),
+            Bound::Included(index) => self
+                .file
+                .get_location(self.file.file_id(), *index, self.encoding)
+                .unwrap(),
             Bound::Excluded(index) => self
                 .file
-                .get_location(self.file.file_id(), *index - 1)
+                .get_location(self.file.file_id(), *index - 1, self.encoding)
                 .unwrap_or((0, 0)),
         };
         self.push(FoldingRange {
@@ -79,78 +90,103 @@ impl<'a> FoldingRanges<'a> {
             kind,
         });
     }
-}
 
-impl Iterator for FoldingRanges<'_> {
-    type Item = FoldingRange;
-    fn next(&mut self) -> Option<Self::Item> {
-        if !self.queued.is_empty() {
-            return Some(self.queued.remove(0));
+    fn visit_trivia(&mut self, trivia: &[Trivia<'_>]) {
+        for item in trivia {
+            if let Trivia::Comment(_, atom) = item {
+                if let AtomKind::Comment {
+                    open,
+                    close: Some(close),
+                    ..
+                } = atom.kind
+                {
+                    self.fold_lines(
+                        open.location.start()..=close.location.start(),
+                        Some(FoldingRangeKind::Comment),
+                    );
+                }
+            }
         }
+    }
 
-        let atom = match self.parser.next() {
-            Some((atom, _)) => atom,
-            _ => return None,
-        };
-        match atom.kind {
-            AtomKind::Comment {
-                open,
-                close: Some(close),
-                ..
-            } => {
-                self.fold_lines(
-                    open.location.start()..=close.location.start(),
-                    Some(FoldingRangeKind::Comment),
-                );
-            }
-            AtomKind::OpenBlock { .. } => self.waiting_folds.push(atom.kind),
-            AtomKind::CloseBlock { head: end } => {
-                if let Some(AtomKind::OpenBlock { head: start }) = self.waiting_folds.pop() {
-                    self.fold(start.location.end()..end.location.start(), None);
-                }
+    fn visit_token(&mut self, token: &Token<'_>) {
+        self.visit_trivia(&token.trivia);
+    }
+
+    fn visit_nodes(&mut self, nodes: &[Node<'_>]) {
+        for node in nodes {
+            self.visit_node(node);
+        }
+    }
+
+    fn visit_block(&mut self, block: &BlockNode<'_>) {
+        self.visit_token(&block.open);
+        self.visit_nodes(&block.children);
+        if let Some(close) = &block.close {
+            self.visit_token(close);
+            self.fold(block.open.location().end()..close.location().start(), None);
+        }
+    }
+
+    /// Fold each branch up to the start of the next branch, and the last branch up to the end
+    /// token. If the end token is missing, the last branch is not folded.
+    fn visit_branches(&mut self, branches: &[Branch<'_>], end: Option<&Token<'_>>) {
+        for (index, branch) in branches.iter().enumerate() {
+            self.visit_token(&branch.head);
+            self.visit_nodes(&branch.children);
+            let start = branch.head.location().start();
+            if let Some(next) = branches.get(index + 1) {
+                self.fold_lines(start..next.head.location().start(), None);
+            } else if let Some(end) = end {
+                self.fold_lines(start..end.location().start(), None);
             }
-            AtomKind::If { .. } => self.waiting_folds.push(atom.kind),
-            AtomKind::ElseIf { head: end, .. } | AtomKind::Else { head: end } => {
-                let start = match self.waiting_folds.pop() {
-                    Some(AtomKind::If { head, .. }) | Some(AtomKind::ElseIf { head, .. }) => head,
-                    _ => return self.next(),
-                };
-                self.fold_lines(start.location.start()..end.location.start(), None);
-                self.waiting_folds.push(atom.kind);
+        }
+    }
+
+    fn visit_node(&mut self, node: &Node<'_>) {
+        match node {
+            Node::Section(section) => {
+                self.visit_token(&section.header);
+                self.visit_nodes(&section.children);
             }
-            AtomKind::EndIf { head: end } => match self.waiting_folds.pop() {
-                Some(AtomKind::If { head: start, .. })
-                | Some(AtomKind::ElseIf { head: start, .. })
-                | Some(AtomKind::Else { head: start }) => {
-                    self.fold_lines(start.location.start()..=end.location.start(), None);
+            Node::Command(command) => {
+                self.visit_token(&command.command);
+                if let Some(block) = &command.block {
+                    self.visit_block(block);
                 }
-                _ => (),
-            },
-            AtomKind::StartRandom { .. } => self.waiting_folds.push(atom.kind),
-            AtomKind::PercentChance { head: end, .. } => {
-                if let Some(AtomKind::PercentChance { head: start, .. }) = self.waiting_folds.last()
-                {
-                    let start = start.location.start();
-                    self.fold_lines(start..end.location.start(), None);
-                    self.waiting_folds.pop();
-                }
-                self.waiting_folds.push(atom.kind);
             }
-            AtomKind::EndRandom { head: end } => {
-                if let Some(AtomKind::PercentChance { head: start, .. }) = self.waiting_folds.last()
-                {
-                    let start = start.location.start();
-                    self.fold_lines(start..end.location.start(), None);
-                    self.waiting_folds.pop();
+            Node::Block(block) => self.visit_block(block),
+            Node::Condition(condition) => {
+                // The last branch includes the `endif` line.
+                let (last, rest) = condition.branches.split_last().unwrap();
+                self.visit_branches(rest, Some(&last.head));
+                self.visit_token(&last.head);
+                self.visit_nodes(&last.children);
+                if let Some(end) = &condition.end {
+                    self.visit_token(end);
+                    self.fold_lines(last.head.location().start()..=end.location().start(), None);
                 }
-                if let Some(AtomKind::StartRandom { head: start }) = self.waiting_folds.last() {
-                    let start = start.location.start();
-                    self.fold_lines(start..=end.location.start(), None);
-                    self.waiting_folds.pop();
+            }
+            Node::Random(random) => {
+                self.visit_token(&random.start);
+                self.visit_nodes(&random.children);
+                self.visit_branches(&random.branches, random.end.as_ref());
+                if let Some(end) = &random.end {
+                    self.visit_token(end);
+                    self.fold_lines(
+                        random.start.location().start()..=end.location().start(),
+                        None,
+                    );
                 }
             }
-            _ => (),
+            Node::Atom(token) => self.visit_token(token),
         }
-        self.next()
+    }
+}
+
+impl Iterator for FoldingRanges<'_> {
+    type Item = FoldingRange;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.queued.pop_front()
     }
 }
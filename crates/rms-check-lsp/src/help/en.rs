@@ -236,4 +236,16 @@ lazy_static! {
 
         s.collect()
     };
+
+    pub static ref SECTIONS: HashMap<&'static str, &'static str> = {
+        let mut m = HashMap::new();
+        m.insert("<PLAYER_SETUP>", "Configure how players are positioned on the map, and set game-wide properties.");
+        m.insert("<LAND_GENERATION>", "Create the base terrain, player lands and other lands. Lands are placed before anything else, and determine the broad layout of the map.");
+        m.insert("<ELEVATION_GENERATION>", "Create hills on top of the lands.");
+        m.insert("<CLIFF_GENERATION>", "Create cliffs. Cliffs avoid player lands and are placed after elevation.");
+        m.insert("<TERRAIN_GENERATION>", "Create terrain patches, like forests and lakes, on top of the lands.");
+        m.insert("<CONNECTION_GENERATION>", "Create connections between lands, like shallows through water or roads between players.");
+        m.insert("<OBJECTS_GENERATION>", "Place objects, like town centers, resources, animals and gaia units.");
+        m
+    };
 }
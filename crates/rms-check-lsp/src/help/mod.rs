@@ -75,10 +75,15 @@ impl SignatureBuilder {
 }
 
 /// Get the language server SignatureInformation for a given command name.
-fn get_signature(command_name: &str) -> Option<&SignatureInformation> {
+pub fn get_signature(command_name: &str) -> Option<&SignatureInformation> {
     en::SIGNATURES.get(command_name)
 }
 
+/// Get the description of a section, like `<PLAYER_SETUP>`.
+pub fn get_section_description(section_name: &str) -> Option<&'static str> {
+    en::SECTIONS.get(section_name).copied()
+}
+
 pub fn find_signature_help(file: &RMSFile<'_>, position: ByteIndex) -> Option<SignatureHelp> {
     let parser = Parser::new(file.file_id(), file.main_source());
     for (atom, _) in parser {
@@ -0,0 +1,208 @@
+use crate::help;
+use lsp_types::{Documentation, ParameterLabel, SignatureInformation};
+use rms_check::{
+    AtomKind, ByteIndex, Compatibility, ParseState, Parser, PositionEncoding, RMSFile,
+    SourceLocation, Word,
+};
+
+/// Render the documentation for a command as markdown.
+fn signature_markdown(signature: &SignatureInformation) -> String {
+    let mut markdown = format!("```rms\n{}\n```", signature.label);
+    if let Some(Documentation::String(description)) = &signature.documentation {
+        markdown += &format!("\n\n{}", description);
+    }
+    let parameters = signature.parameters.iter().flatten();
+    for (index, parameter) in parameters.enumerate() {
+        if index == 0 {
+            markdown += "\n";
+        }
+        let label = match &parameter.label {
+            ParameterLabel::Simple(label) => label,
+            ParameterLabel::LabelOffsets(_) => continue,
+        };
+        match &parameter.documentation {
+            Some(Documentation::String(doc)) if !doc.is_empty() => {
+                markdown += &format!("\n- `{}`: {}", label, doc);
+            }
+            _ => markdown += &format!("\n- `{}`", label),
+        }
+    }
+    markdown
+}
+
+/// Describe where something was defined.
+fn definition_place(file: &RMSFile<'_>, location: SourceLocation) -> String {
+    let line = file
+        .get_location(location.file(), location.start(), PositionEncoding::Utf8)
+        .map_or(0, |(line, _)| line + 1);
+    if location.file() == file.file_id() {
+        format!("Defined on line {}.", line)
+    } else {
+        format!(
+            "Defined in `{}` on line {}.",
+            file.name(location.file()),
+            line
+        )
+    }
+}
+
+/// Show the value and definition of a `#const` or `#define` name.
+fn token_markdown(state: &ParseState<'_>, name: &str) -> Option<String> {
+    if let Some(definition) = state.get_const(name) {
+        let value = definition.value().map_or("", |value| value.value);
+        let mut markdown = format!("```rms\n#const {} {}\n```", name, value);
+        if let Some(resolved) = state.resolve_const(name) {
+            if resolved.to_string() != value {
+                markdown += &format!("\n\nValue: `{}`", resolved);
+            }
+        }
+        markdown += &format!("\n\n{}", definition_place(state.rms, definition.location()));
+        return Some(markdown);
+    }
+    if state.is_builtin_const(name) {
+        return Some(match state.resolve_builtin_const(name) {
+            Some(value) => format!(
+                "```rms\n#const {} {}\n```\n\nBuiltin constant.",
+                name, value
+            ),
+            None => format!("```rms\n#const {}\n```\n\nBuiltin constant.", name),
+        });
+    }
+    if let Some(definition) = state.get_define(name) {
+        return Some(format!(
+            "```rms\n#define {}\n```\n\n{}",
+            name,
+            definition_place(state.rms, definition.location())
+        ));
+    }
+    if state.has_define(name) {
+        return Some(format!("```rms\n#define {}\n```\n\nBuiltin define.", name));
+    }
+    None
+}
+
+/// Summarise a section: what it is for, and which blocks it contains.
+fn section_markdown(file: &RMSFile<'_>, section: &Word<'_>) -> String {
+    let mut markdown = format!("```rms\n{}\n```", section.value);
+    if let Some(description) = help::get_section_description(section.value) {
+        markdown += &format!("\n\n{}", description);
+    }
+
+    let mut counts: Vec<(&str, usize)> = vec![];
+    let atoms = Parser::new(file.file_id(), file.main_source())
+        .map(|(atom, _)| atom)
+        .skip_while(|atom| atom.location.start() <= section.location.start())
+        .take_while(|atom| !matches!(atom.kind, AtomKind::Section { .. }));
+    for atom in atoms {
+        if let AtomKind::Command { name, .. } = atom.kind {
+            if name.value.starts_with("create_") || name.value.starts_with("connect_") {
+                match counts
+                    .iter_mut()
+                    .find(|(command, _)| *command == name.value)
+                {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((name.value, 1)),
+                }
+            }
+        }
+    }
+    if !counts.is_empty() {
+        let summary = counts
+            .iter()
+            .map(|(command, count)| format!("{} × `{}`", count, command))
+            .collect::<Vec<_>>()
+            .join(", ");
+        markdown += &format!("\n\nContains {}.", summary);
+    }
+    markdown
+}
+
+/// Find hover documentation for the word at the given position. Returns the location of the
+/// hovered word and markdown contents.
+pub fn find_hover(
+    file: &RMSFile<'_>,
+    compatibility: Compatibility,
+    position: ByteIndex,
+) -> Option<(SourceLocation, String)> {
+    let (state, atom) = file.parse_to(file.file_id(), position, compatibility);
+    let atom = atom?;
+    let is_hovered = |word: &Word<'_>| word.location.range().contains(&position);
+    let command_help = |word: &Word<'_>| {
+        help::get_signature(word.value)
+            .map(|signature| (word.location, signature_markdown(signature)))
+    };
+    let token_help = |word: &Word<'_>| {
+        token_markdown(&state, word.value).map(|markdown| (word.location, markdown))
+    };
+
+    match &atom.kind {
+        AtomKind::Section { name } => Some((name.location, section_markdown(file, name))),
+        AtomKind::Command { name, arguments } => {
+            if is_hovered(name) {
+                command_help(name)
+            } else {
+                arguments
+                    .iter()
+                    .find(|arg| is_hovered(arg))
+                    .and_then(token_help)
+            }
+        }
+        AtomKind::Const { head, name, value } => {
+            if is_hovered(head) {
+                command_help(head)
+            } else if is_hovered(name) {
+                let value = value.map_or("", |value| value.value);
+                Some((
+                    name.location,
+                    format!("```rms\n#const {} {}\n```", name.value, value),
+                ))
+            } else {
+                None
+            }
+        }
+        AtomKind::Define { head, name } => {
+            if is_hovered(head) {
+                command_help(head)
+            } else if is_hovered(name) {
+                Some((
+                    name.location,
+                    format!("```rms\n#define {}\n```", name.value),
+                ))
+            } else {
+                None
+            }
+        }
+        AtomKind::Undefine { head, name } => {
+            if is_hovered(head) {
+                command_help(head)
+            } else if is_hovered(name) {
+                token_help(name)
+            } else {
+                None
+            }
+        }
+        AtomKind::If { head, condition } | AtomKind::ElseIf { head, condition } => {
+            if is_hovered(head) {
+                command_help(head)
+            } else if is_hovered(condition) {
+                token_help(condition)
+            } else {
+                None
+            }
+        }
+        AtomKind::PercentChance { head, chance } => {
+            if is_hovered(head) {
+                command_help(head)
+            } else if is_hovered(chance) {
+                token_help(chance)
+            } else {
+                None
+            }
+        }
+        AtomKind::Else { head }
+        | AtomKind::EndIf { head }
+        | AtomKind::StartRandom { head }
+        | AtomKind::EndRandom { head } => command_help(head),
+        _ => None,
+    }
+}
@@ -0,0 +1,145 @@
+use crate::references::is_token_argument;
+use lsp_types::{Position, Range, TextDocumentIdentifier};
+use rms_check::{
+    AtomKind, ByteIndex, Compatibility, ParseState, RMSFile, RandomTree, SourceLocation, Word,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// `lsp-types` does not have inlay hints yet, so these mirror the Language Server Protocol 3.17
+// types that the server uses.
+
+/// Parameters for the `textDocument/inlayHint` request.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHintParams {
+    /// The document to get hints for.
+    pub text_document: TextDocumentIdentifier,
+    /// The visible part of the document.
+    pub range: Range,
+}
+
+/// A hint shown inline in the editor.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHint {
+    position: Position,
+    label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tooltip: Option<String>,
+    padding_left: bool,
+}
+
+/// Format a probability from 0 to 1 as a percentage, with at most two decimals.
+fn format_percent(probability: f64) -> String {
+    let percent = format!("{:.2}", probability * 100.0);
+    let percent = percent.trim_end_matches('0').trim_end_matches('.');
+    format!("{}%", percent)
+}
+
+/// Collects inlay hints inside a range of the main script.
+struct HintCollector<F>
+where
+    F: Fn(SourceLocation) -> Option<lsp_types::Range>,
+{
+    range: std::ops::Range<ByteIndex>,
+    to_range: F,
+    hints: Vec<InlayHint>,
+}
+
+impl<F> HintCollector<F>
+where
+    F: Fn(SourceLocation) -> Option<lsp_types::Range>,
+{
+    /// Add a hint right after `location`, if it ends inside the requested range.
+    fn push(&mut self, location: SourceLocation, label: String, tooltip: Option<String>) {
+        let end = location.end();
+        if end < self.range.start || end > self.range.end {
+            return;
+        }
+        if let Some(range) = (self.to_range)(location) {
+            self.hints.push(InlayHint {
+                position: range.end,
+                label,
+                tooltip,
+                padding_left: true,
+            });
+        }
+    }
+
+    /// Show the value of a `#const` name.
+    fn push_const(&mut self, state: &ParseState<'_>, word: &Word<'_>) {
+        if word.value.parse::<i32>().is_ok() {
+            return;
+        }
+        if let Some(value) = state.resolve_const(word.value) {
+            self.push(word.location, format!("⟵ {}", value), None);
+        }
+    }
+}
+
+/// Get inlay hints for a range of the main script: the values of `#const` names, and how likely
+/// each `percent_chance` branch is to be taken.
+pub fn inlay_hints(
+    file: &RMSFile<'_>,
+    compatibility: Compatibility,
+    range: std::ops::Range<ByteIndex>,
+    to_range: impl Fn(SourceLocation) -> Option<lsp_types::Range>,
+) -> Vec<InlayHint> {
+    let mut collector = HintCollector {
+        range,
+        to_range,
+        hints: vec![],
+    };
+
+    // `#const` values can change throughout the script, so chances are resolved where they are
+    // used.
+    let mut chances = HashMap::new();
+    file.visit_atoms(file.file_id(), compatibility, |state, atom| {
+        if let AtomKind::PercentChance { chance, .. } = &atom.kind {
+            if let Some(value) = state.resolve_number(chance.value) {
+                chances.insert(chance.location.start(), value);
+            }
+        }
+        if atom.location.end() < collector.range.start
+            || atom.location.start() > collector.range.end
+        {
+            return;
+        }
+        match &atom.kind {
+            AtomKind::Command { name, arguments } => {
+                for (index, arg) in arguments.iter().enumerate() {
+                    if is_token_argument(name.value, index) {
+                        collector.push_const(state, arg);
+                    }
+                }
+            }
+            AtomKind::Const {
+                value: Some(value), ..
+            } => collector.push_const(state, value),
+            AtomKind::PercentChance { chance, .. } => collector.push_const(state, chance),
+            _ => (),
+        }
+    });
+
+    let tree =
+        RandomTree::from_file_with(file, |word| chances.get(&word.location.start()).copied());
+    tree.visit_outcomes(&mut |_statement, outcome| {
+        if let Some(probability) = outcome.probability {
+            collector.push(
+                outcome.location,
+                format!("{} overall", format_percent(probability)),
+                Some(
+                    "The chance that this branch is taken, including surrounding `start_random` \
+                     statements."
+                        .to_string(),
+                ),
+            );
+        }
+    });
+
+    collector
+        .hints
+        .sort_by_key(|hint| (hint.position.line, hint.position.character));
+    collector.hints
+}
@@ -10,27 +10,42 @@
 
 use jsonrpc_core::{ErrorCode, IoHandler, Params};
 use lsp_types::{
-    CodeAction, CodeActionKind, CodeActionParams, CodeActionProviderCapability, Diagnostic,
-    DiagnosticRelatedInformation, DiagnosticSeverity, DidChangeTextDocumentParams,
-    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentFormattingParams, FoldingRange,
-    FoldingRangeParams, FoldingRangeProviderCapability, GotoDefinitionParams, InitializeParams,
-    InitializeResult, InitializedParams, Location, MessageType, NumberOrString, OneOf, Position,
-    PublishDiagnosticsParams, ServerCapabilities, ServerInfo, ShowMessageParams,
+    CodeAction, CodeActionKind, CodeActionParams, CodeActionProviderCapability, CompletionOptions,
+    CompletionParams, CompletionResponse, CompletionTextEdit, Diagnostic,
+    DiagnosticRelatedInformation, DiagnosticSeverity, DidChangeConfigurationParams,
+    DidChangeTextDocumentParams, DidChangeWatchedFilesParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DidSaveTextDocumentParams, DocumentFormattingParams,
+    DocumentSymbolParams, DocumentSymbolResponse, FoldingRange, FoldingRangeParams,
+    FoldingRangeProviderCapability, GotoDefinitionParams, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InitializeParams, InitializeResult, InitializedParams, Location,
+    MarkupContent, MarkupKind, MessageType, NumberOrString, OneOf, Position, PrepareRenameResponse,
+    PublishDiagnosticsParams, ReferenceParams, RenameOptions, RenameParams,
+    SemanticTokensFullOptions, SemanticTokensOptions, SemanticTokensParams,
+    SemanticTokensRangeParams, SemanticTokensRangeResult, SemanticTokensResult,
+    SemanticTokensServerCapabilities, ServerCapabilities, ServerInfo, ShowMessageParams,
     SignatureHelpOptions, TextDocumentItem, TextDocumentPositionParams, TextDocumentSyncCapability,
-    TextDocumentSyncKind, TextEdit, Url, WorkDoneProgressOptions, WorkspaceEdit,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions, TextEdit, Url,
+    WorkDoneProgressOptions, WorkspaceEdit,
 };
-use multisplice::Multisplice;
 use rms_check::{
-    AtomKind, ByteIndex, Compatibility, FileId, FormatOptions, RMSCheck, RMSFile, Severity,
-    SourceLocation,
+    to_chardet_string, AtomKind, ByteIndex, CheckCache, Compatibility, Config, FileId,
+    FormatOptions, PositionEncoding, RMSCheck, RMSFile, Severity, SourceLocation,
 };
 use serde_json::{self, json};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
+mod completion;
 mod folds;
 mod help;
+mod hover;
+mod inlay_hints;
+mod references;
+mod semantic_tokens;
+mod symbols;
 
 type RpcResult = jsonrpc_core::Result<serde_json::Value>;
 
@@ -50,48 +65,243 @@ fn out_of_range() -> jsonrpc_core::Error {
     internal_error("Range conversion between rms-check and the Language Server Protocol failed. This is a bug.")
 }
 
+/// Convert an rms-check location to an LSP range, with columns counted in the given encoding.
+fn to_lsp_range(
+    file: &RMSFile<'_>,
+    location: SourceLocation,
+    encoding: PositionEncoding,
+) -> Option<lsp_types::Range> {
+    let start = file.get_location(location.file(), location.start(), encoding)?;
+    let end = file.get_location(location.file(), location.end(), encoding)?;
+    Some(lsp_types::Range {
+        start: Position {
+            line: start.0,
+            character: start.1,
+        },
+        end: Position {
+            line: end.0,
+            character: end.1,
+        },
+    })
+}
+
+/// Convert an LSP range to an rms-check location, with columns counted in the given encoding.
+fn to_source_location(
+    rms: &RMSFile<'_>,
+    file: FileId,
+    range: lsp_types::Range,
+    encoding: PositionEncoding,
+) -> Option<SourceLocation> {
+    let start = rms.get_byte_index(file, range.start.line, range.start.character, encoding)?;
+    let end = rms.get_byte_index(file, range.end.line, range.end.character, encoding)?;
+    Some(SourceLocation::new(file, start..end))
+}
+
 struct Document {
     version: i32,
     // Can be 'static because we'll only pass in owned data.
     file: RMSFile<'static>,
     diagnostics: Vec<rms_check::Diagnostic>,
+    /// The compatibility in effect for this document, after applying the configuration file and
+    /// `Compatibility:` header comments.
+    compatibility: Compatibility,
+    /// Checker states from the last check, so only the edited part is linted again.
+    cache: CheckCache,
+    /// How the editor counts columns.
+    encoding: PositionEncoding,
 }
 
 impl Document {
-    fn new(file: RMSFile<'static>, version: i32) -> Self {
+    fn new(file: RMSFile<'static>, version: i32, encoding: PositionEncoding) -> Self {
         Self {
             version,
             file,
             diagnostics: vec![],
+            compatibility: Compatibility::default(),
+            cache: CheckCache::new(),
+            encoding,
         }
     }
 
     fn to_lsp_range(&self, location: SourceLocation) -> Option<lsp_types::Range> {
-        let start = self.file.get_location(location.file(), location.start())?;
-        let end = self.file.get_location(location.file(), location.end())?;
-        Some(lsp_types::Range {
-            start: Position {
-                line: start.0,
-                character: start.1,
-            },
-            end: Position {
-                line: end.0,
-                character: end.1,
-            },
-        })
+        to_lsp_range(&self.file, location, self.encoding)
+    }
+
+    fn to_byte_index(&self, file: FileId, position: Position) -> Option<ByteIndex> {
+        self.file
+            .get_byte_index(file, position.line, position.character, self.encoding)
     }
 
     fn to_source_location(&self, file: FileId, range: lsp_types::Range) -> Option<SourceLocation> {
-        let start = self
-            .file
-            .get_byte_index(file, range.start.line, range.start.character)?;
-        let end = self
-            .file
-            .get_byte_index(file, range.end.line, range.end.character)?;
-        Some(SourceLocation::new(file, start..end))
+        to_source_location(&self.file, file, range, self.encoding)
+    }
+}
+
+/// The scripts that share `#const` and `#define` names with a document: all the scripts of its
+/// ZR@ map if it is in an unpacked ZR@ map directory, or only the document itself.
+struct Scripts {
+    file: RMSFile<'static>,
+    /// The URIs of the scripts.
+    uris: HashMap<FileId, Url>,
+    /// The ID of the document in `file`.
+    document: FileId,
+    compatibility: Compatibility,
+    encoding: PositionEncoding,
+}
+
+impl Scripts {
+    /// Get the scripts for a document, using the text of open documents over the saved files.
+    fn for_document(uri: &Url, documents: &HashMap<Url, Document>) -> Option<Self> {
+        let doc = documents.get(uri)?;
+        let single = || {
+            let file = RMSFile::from_string(uri, doc.file.main_source().to_string());
+            Self {
+                uris: HashMap::from([(file.file_id(), uri.clone())]),
+                document: file.file_id(),
+                file,
+                compatibility: doc.compatibility,
+                encoding: doc.encoding,
+            }
+        };
+
+        let path = match uri.to_file_path() {
+            Ok(path) => path,
+            Err(()) => return Some(single()),
+        };
+        let (dir, name) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => (dir, name.to_string_lossy().to_string()),
+            _ => return Some(single()),
+        };
+        let is_map_dir = dir
+            .file_name()
+            .is_some_and(|dir_name| dir_name.to_string_lossy().starts_with("ZR@"));
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) if is_map_dir => entries,
+            _ => return Some(single()),
+        };
+
+        let mut files = vec![(name.clone(), Cow::Owned(doc.file.main_source().to_string()))];
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            let file_name = match path.file_name() {
+                Some(file_name) => file_name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if file_name == name {
+                continue;
+            }
+            let source = match Url::from_file_path(&path)
+                .ok()
+                .and_then(|uri| documents.get(&uri))
+            {
+                Some(open) => open.file.main_source().to_string(),
+                None => match std::fs::read(&path) {
+                    Ok(bytes) => to_chardet_string(bytes),
+                    Err(_) => continue,
+                },
+            };
+            files.push((file_name, Cow::Owned(source)));
+        }
+
+        let file = RMSFile::from_zip_rms_files(files);
+        let document = match file.find_file_id(&name) {
+            Some(document) => document,
+            // Not an `.rms` or `.inc` file.
+            None => return Some(single()),
+        };
+        let uris = file
+            .script_files()
+            .map(|id| Url::from_file_path(dir.join(file.name(id))).map(|uri| (id, uri)))
+            .collect::<Result<HashMap<_, _>, _>>()
+            .ok()?;
+        Some(Self {
+            file,
+            uris,
+            document,
+            compatibility: doc.compatibility,
+            encoding: doc.encoding,
+        })
+    }
+
+    fn to_lsp_range(&self, location: SourceLocation) -> Option<lsp_types::Range> {
+        to_lsp_range(&self.file, location, self.encoding)
+    }
+
+    fn to_lsp_location(&self, location: SourceLocation) -> Result<Location, jsonrpc_core::Error> {
+        Ok(Location {
+            uri: self
+                .uris
+                .get(&location.file())
+                .cloned()
+                .ok_or_else(out_of_range)?,
+            range: self.to_lsp_range(location).ok_or_else(out_of_range)?,
+        })
     }
 }
 
+/// Parse a compatibility name from the `defaultCompatibility` setting. This accepts the names
+/// used in `Compatibility:` header comments, as well as the short names used by the VS Code
+/// extension.
+fn parse_compatibility(name: &str) -> Option<Compatibility> {
+    match name {
+        "up14" => Some(Compatibility::UserPatch14),
+        "up15" => Some(Compatibility::UserPatch15),
+        "all" => Some(Compatibility::All),
+        name => name.parse().ok(),
+    }
+}
+
+/// Read the default compatibility from the initialization options or workspace settings. The
+/// setting can be given directly, or inside an `rmsCheck` section.
+fn compatibility_setting(settings: &serde_json::Value) -> Result<Option<Compatibility>, String> {
+    let settings = settings.get("rmsCheck").unwrap_or(settings);
+    match settings
+        .get("defaultCompatibility")
+        .and_then(|value| value.as_str())
+    {
+        Some(name) => parse_compatibility(name)
+            .map(Some)
+            .ok_or_else(|| format!("Unknown compatibility setting `{}`", name)),
+        None => Ok(None),
+    }
+}
+
+/// Pick a position encoding from the ones the client supports, in the client's order of
+/// preference. Clients that do not list any only support UTF-16.
+fn negotiate_position_encoding(params: &serde_json::Value) -> PositionEncoding {
+    params
+        .pointer("/capabilities/general/positionEncodings")
+        .and_then(|encodings| encodings.as_array())
+        .and_then(|encodings| {
+            encodings
+                .iter()
+                .find_map(|encoding| match encoding.as_str()? {
+                    "utf-8" => Some(PositionEncoding::Utf8),
+                    "utf-16" => Some(PositionEncoding::Utf16),
+                    "utf-32" => Some(PositionEncoding::Utf32),
+                    _ => None,
+                })
+        })
+        .unwrap_or(PositionEncoding::Utf16)
+}
+
+/// Get the name of a position encoding in the Language Server Protocol.
+const fn position_encoding_name(encoding: PositionEncoding) -> &'static str {
+    match encoding {
+        PositionEncoding::Utf8 => "utf-8",
+        PositionEncoding::Utf16 => "utf-16",
+        PositionEncoding::Utf32 => "utf-32",
+    }
+}
+
+/// A `#const` or `#define` name under the cursor, and all uses of that name.
+struct FoundReferences {
+    scripts: Scripts,
+    /// The location of the name under the cursor.
+    location: SourceLocation,
+    kind: references::NameKind,
+    references: Vec<references::Reference>,
+}
+
 /// Sync state holder, so only the outer layer has to deal with Arcs.
 struct Inner<Emit>
 where
@@ -99,12 +309,31 @@ where
 {
     emit: Emit,
     documents: HashMap<Url, Document>,
+    /// The compatibility to use for documents that do not configure one.
+    default_compatibility: Compatibility,
+    /// How the editor counts columns, as negotiated during initialization.
+    position_encoding: PositionEncoding,
+    /// The last configuration file error that was shown, so it is not shown again for every
+    /// directory.
+    config_error: Option<String>,
+    /// The configuration for the scripts in each directory, so it is not read from disk on every
+    /// edit. Cleared when a document is saved or a watched file changes.
+    configs: HashMap<PathBuf, Config>,
 }
 
 impl<Emit> Inner<Emit>
 where
     Emit: Fn(serde_json::Value) + Send + 'static,
 {
+    /// Show a message in the editor.
+    fn show_message(&self, typ: MessageType, message: String) {
+        (self.emit)(json!({
+            "jsonrpc": "2.0",
+            "method": "window/showMessage",
+            "params": ShowMessageParams { typ, message },
+        }));
+    }
+
     /// Convert an rms-check warning to an LSP diagnostic.
     fn to_lsp_diagnostic(
         &self,
@@ -151,11 +380,25 @@ where
     }
 
     /// Initialize the language server.
-    fn initialize(&mut self, _params: InitializeParams) -> RpcResult {
+    fn initialize(&mut self, params: serde_json::Value) -> RpcResult {
+        // `lsp-types` does not know about position encodings or inlay hints yet, so those are read
+        // from and written to the raw JSON.
+        self.position_encoding = negotiate_position_encoding(&params);
+        let params: InitializeParams = serde_json::from_value(params)
+            .map_err(|err| jsonrpc_core::Error::invalid_params(err.to_string()))?;
+        if let Some(options) = &params.initialization_options {
+            match compatibility_setting(options) {
+                Ok(Some(compatibility)) => self.default_compatibility = compatibility,
+                Ok(None) => (),
+                Err(message) => self.show_message(MessageType::WARNING, message),
+            }
+        }
+
         let capabilities = ServerCapabilities {
             code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
             document_formatting_provider: Some(OneOf::Left(true)),
             folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+            document_symbol_provider: Some(OneOf::Left(true)),
             signature_help_provider: Some(SignatureHelpOptions {
                 trigger_characters: Some(vec![" ".to_string(), "\t".to_string()]),
                 retrigger_characters: None,
@@ -164,8 +407,35 @@ where
                 },
             }),
             definition_provider: Some(OneOf::Left(true)),
-            text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                TextDocumentSyncKind::INCREMENTAL,
+            references_provider: Some(OneOf::Left(true)),
+            rename_provider: Some(OneOf::Right(RenameOptions {
+                prepare_provider: Some(true),
+                work_done_progress_options: WorkDoneProgressOptions {
+                    work_done_progress: None,
+                },
+            })),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            completion_provider: Some(CompletionOptions {
+                trigger_characters: Some(vec!["<".to_string(), "#".to_string()]),
+                ..CompletionOptions::default()
+            }),
+            semantic_tokens_provider: Some(
+                SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                    legend: semantic_tokens::legend(),
+                    range: Some(true),
+                    full: Some(SemanticTokensFullOptions::Bool(true)),
+                    work_done_progress_options: WorkDoneProgressOptions {
+                        work_done_progress: None,
+                    },
+                }),
+            ),
+            text_document_sync: Some(TextDocumentSyncCapability::Options(
+                TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(TextDocumentSyncKind::INCREMENTAL),
+                    save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                    ..TextDocumentSyncOptions::default()
+                },
             )),
             ..ServerCapabilities::default()
         };
@@ -176,7 +446,11 @@ where
                 version: None,
             }),
         };
-        serde_json::to_value(result).map_err(internal_error)
+        let mut result = serde_json::to_value(result).map_err(internal_error)?;
+        result["capabilities"]["positionEncoding"] =
+            json!(position_encoding_name(self.position_encoding));
+        result["capabilities"]["inlayHintProvider"] = json!(true);
+        Ok(result)
     }
 
     /// A document was opened, lint.
@@ -186,7 +460,11 @@ where
         } = params.text_document;
         self.documents.insert(
             uri.clone(),
-            Document::new(RMSFile::from_string(uri.clone(), text), version),
+            Document::new(
+                RMSFile::from_string(uri.clone(), text),
+                version,
+                self.position_encoding,
+            ),
         );
 
         self.run_checks_and_publish(uri)
@@ -203,30 +481,93 @@ where
                 )));
             }
 
-            let mut splicer = Multisplice::new(doc.file.main_source());
+            // Changes must be applied in order: the range of each change refers to the text after
+            // the previous changes. The document is only updated once every change applied, so a
+            // bad change does not leave it out of sync with the editor.
+            let mut file = None;
+            let mut starts = vec![];
             for change in params.content_changes {
-                if let Some(range) = change.range {
-                    if let Some(location) = doc.to_source_location(doc.file.file_id(), range) {
-                        let start = usize::from(location.range().start);
-                        let end = usize::from(location.range().end);
-                        splicer.splice(start, end, change.text);
-                    } else {
-                        return Err(jsonrpc_core::Error::invalid_params(
-                            "Error applying incremental change: range out of bounds",
-                        ));
+                let current = file.as_ref().unwrap_or(&doc.file);
+                let (start, text) = match change.range {
+                    Some(range) => {
+                        let location =
+                            to_source_location(current, current.file_id(), range, doc.encoding)
+                                .ok_or_else(|| {
+                                    jsonrpc_core::Error::invalid_params(
+                                        "Error applying incremental change: range out of bounds",
+                                    )
+                                })?;
+                        let range = location.range();
+                        let mut text = current.main_source().to_string();
+                        text.replace_range(
+                            usize::from(range.start)..usize::from(range.end),
+                            &change.text,
+                        );
+                        (range.start, text)
                     }
-                } else {
-                    splicer.splice_range(.., change.text);
+                    None => (ByteIndex::from(0), change.text),
+                };
+                starts.push(start);
+                file = Some(RMSFile::from_string(uri.as_str(), text));
+            }
+            if let Some(file) = file {
+                for start in starts {
+                    doc.cache.invalidate(start);
                 }
+                doc.file = file;
             }
-            doc.version += 1;
-            doc.file = RMSFile::from_string(uri.as_str(), splicer.to_string());
+            doc.version = params.text_document.version;
             self.run_checks_and_publish(uri)?;
         }
 
         Ok(())
     }
 
+    /// The editor settings changed. Re-lint all documents if the default compatibility changed.
+    fn changed_configuration(
+        &mut self,
+        params: DidChangeConfigurationParams,
+    ) -> Result<(), jsonrpc_core::Error> {
+        let compatibility = match compatibility_setting(&params.settings) {
+            Ok(Some(compatibility)) => compatibility,
+            Ok(None) => return Ok(()),
+            Err(message) => {
+                self.show_message(MessageType::WARNING, message);
+                return Ok(());
+            }
+        };
+        if compatibility == self.default_compatibility {
+            return Ok(());
+        }
+        self.default_compatibility = compatibility;
+
+        let uris: Vec<Url> = self.documents.keys().cloned().collect();
+        for uri in uris {
+            self.run_checks_and_publish(uri)?;
+        }
+        Ok(())
+    }
+
+    /// A document was saved. Configuration files may have been saved with it, so load them again.
+    fn saved(&mut self, params: DidSaveTextDocumentParams) -> Result<(), jsonrpc_core::Error> {
+        self.configs.clear();
+        self.run_checks_and_publish(params.text_document.uri)
+    }
+
+    /// A watched file, like a configuration file, changed. Load the configuration again and
+    /// re-lint all documents.
+    fn changed_watched_files(
+        &mut self,
+        _params: DidChangeWatchedFilesParams,
+    ) -> Result<(), jsonrpc_core::Error> {
+        self.configs.clear();
+        let uris: Vec<Url> = self.documents.keys().cloned().collect();
+        for uri in uris {
+            self.run_checks_and_publish(uri)?;
+        }
+        Ok(())
+    }
+
     /// A document was closed, clean up.
     #[allow(clippy::unnecessary_wraps)]
     fn closed(&mut self, params: DidCloseTextDocumentParams) -> Result<(), jsonrpc_core::Error> {
@@ -252,32 +593,43 @@ where
         let mut code_actions = vec![];
         for diagnostic in matching_diagnostics {
             for fix in diagnostic.fixes().chain(diagnostic.suggestions()) {
-                if let Some(replacement) = fix.replacement() {
-                    code_actions.push(CodeAction {
-                        title: fix.message().to_string(),
-                        kind: Some(CodeActionKind::QUICKFIX),
-                        diagnostics: Some(vec![self.to_lsp_diagnostic(doc, diagnostic)?]),
-                        edit: Some(WorkspaceEdit {
-                            change_annotations: None,
-                            changes: Some({
-                                let mut map = HashMap::new();
-                                let edit = TextEdit {
-                                    range: doc
-                                        .to_lsp_range(fix.location())
-                                        .ok_or_else(out_of_range)?,
-                                    new_text: replacement.to_string(),
-                                };
-                                map.insert(params.text_document.uri.clone(), vec![edit]);
-                                map
-                            }),
-                            document_changes: None,
-                        }),
-                        command: None,
-                        data: None,
-                        disabled: None,
-                        is_preferred: None,
-                    });
+                // Only fixes that edit the open document can be applied.
+                if fix.edits().is_empty()
+                    || fix
+                        .edits()
+                        .iter()
+                        .any(|edit| edit.location().file() != doc.file.file_id())
+                {
+                    continue;
                 }
+                let edits = fix
+                    .edits()
+                    .iter()
+                    .map(|edit| {
+                        Ok(TextEdit {
+                            range: doc.to_lsp_range(edit.location()).ok_or_else(out_of_range)?,
+                            new_text: edit.replacement().to_string(),
+                        })
+                    })
+                    .collect::<jsonrpc_core::Result<Vec<_>>>()?;
+                code_actions.push(CodeAction {
+                    title: fix.message().to_string(),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![self.to_lsp_diagnostic(doc, diagnostic)?]),
+                    edit: Some(WorkspaceEdit {
+                        change_annotations: None,
+                        changes: Some({
+                            let mut map = HashMap::new();
+                            map.insert(params.text_document.uri.clone(), edits);
+                            map
+                        }),
+                        document_changes: None,
+                    }),
+                    command: None,
+                    data: None,
+                    disabled: None,
+                    is_preferred: None,
+                });
             }
         }
 
@@ -290,30 +642,143 @@ where
             .documents
             .get(&params.text_document.uri)
             .ok_or_else(unknown_file)?;
-        let folder = folds::FoldingRanges::new(&doc.file);
+        let folder = folds::FoldingRanges::new(&doc.file, doc.encoding);
 
         let folds: Vec<FoldingRange> = folder.collect();
 
         serde_json::to_value(folds).map_err(internal_error)
     }
 
+    /// Get the outline of the document.
+    fn document_symbol(&self, params: DocumentSymbolParams) -> RpcResult {
+        let doc = self
+            .documents
+            .get(&params.text_document.uri)
+            .ok_or_else(unknown_file)?;
+        let symbols = symbols::document_symbols(&doc.file, |location| doc.to_lsp_range(location));
+
+        serde_json::to_value(DocumentSymbolResponse::Nested(symbols)).map_err(internal_error)
+    }
+
+    /// Get semantic tokens for the whole document.
+    fn semantic_tokens_full(&self, params: SemanticTokensParams) -> RpcResult {
+        let doc = self
+            .documents
+            .get(&params.text_document.uri)
+            .ok_or_else(unknown_file)?;
+        let tokens =
+            semantic_tokens::semantic_tokens(&doc.file, doc.compatibility, doc.encoding, None);
+
+        serde_json::to_value(SemanticTokensResult::Tokens(tokens)).map_err(internal_error)
+    }
+
+    /// Get semantic tokens for part of the document.
+    fn semantic_tokens_range(&self, params: SemanticTokensRangeParams) -> RpcResult {
+        let doc = self
+            .documents
+            .get(&params.text_document.uri)
+            .ok_or_else(unknown_file)?;
+        let location = doc
+            .to_source_location(doc.file.file_id(), params.range)
+            .ok_or_else(out_of_range)?;
+        let tokens = semantic_tokens::semantic_tokens(
+            &doc.file,
+            doc.compatibility,
+            doc.encoding,
+            Some(location.range()),
+        );
+
+        serde_json::to_value(SemanticTokensRangeResult::Tokens(tokens)).map_err(internal_error)
+    }
+
+    /// Get inlay hints for the visible part of the document.
+    fn inlay_hint(&self, params: inlay_hints::InlayHintParams) -> RpcResult {
+        let doc = self
+            .documents
+            .get(&params.text_document.uri)
+            .ok_or_else(unknown_file)?;
+        let location = doc
+            .to_source_location(doc.file.file_id(), params.range)
+            .ok_or_else(out_of_range)?;
+        let hints =
+            inlay_hints::inlay_hints(&doc.file, doc.compatibility, location.range(), |location| {
+                doc.to_lsp_range(location)
+            });
+
+        serde_json::to_value(hints).map_err(internal_error)
+    }
+
     /// Get signature help.
     fn signature_help(&self, params: TextDocumentPositionParams) -> RpcResult {
         let doc = self
             .documents
             .get(&params.text_document.uri)
             .ok_or_else(unknown_file)?;
-        let Position { line, character } = params.position;
         let help = help::find_signature_help(
             &doc.file,
-            doc.file
-                .get_byte_index(doc.file.file_id(), line, character)
+            doc.to_byte_index(doc.file.file_id(), params.position)
                 .ok_or_else(out_of_range)?,
         );
 
         serde_json::to_value(help).map_err(internal_error)
     }
 
+    /// Get hover documentation for commands, sections, and #const or #define names.
+    fn hover(&self, params: HoverParams) -> RpcResult {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+        let doc = self
+            .documents
+            .get(&text_document.uri)
+            .ok_or_else(unknown_file)?;
+        let index = doc
+            .to_byte_index(doc.file.file_id(), position)
+            .ok_or_else(out_of_range)?;
+
+        let hover =
+            hover::find_hover(&doc.file, doc.compatibility, index).map(|(location, markdown)| {
+                Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: markdown,
+                    }),
+                    range: doc.to_lsp_range(location),
+                }
+            });
+
+        serde_json::to_value(hover).map_err(internal_error)
+    }
+
+    /// Get completions for the word at the cursor.
+    fn completion(&self, params: CompletionParams) -> RpcResult {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position;
+        let doc = self
+            .documents
+            .get(&text_document.uri)
+            .ok_or_else(unknown_file)?;
+        let index = doc
+            .to_byte_index(doc.file.file_id(), position)
+            .ok_or_else(out_of_range)?;
+
+        let (word, mut items) = completion::find_completions(&doc.file, doc.compatibility, index);
+        // Replace the whole partially typed word, as editors do not consider `#` and `<` to be part
+        // of words.
+        let range = doc.to_lsp_range(word).ok_or_else(out_of_range)?;
+        for item in &mut items {
+            item.text_edit = Some(CompletionTextEdit::Edit(TextEdit {
+                range,
+                new_text: item.label.clone(),
+            }));
+        }
+
+        serde_json::to_value(CompletionResponse::Array(items)).map_err(internal_error)
+    }
+
     /// Jump to the definition of a #const or #define.
     fn goto_definition(&self, params: GotoDefinitionParams) -> RpcResult {
         let GotoDefinitionParams {
@@ -329,15 +794,13 @@ where
             .documents
             .get(&text_document.uri)
             .ok_or_else(unknown_file)?;
-        let Position { line, character } = position;
         let index = doc
-            .file
-            .get_byte_index(doc.file.file_id(), line, character)
+            .to_byte_index(doc.file.file_id(), position)
             .ok_or_else(out_of_range)?;
 
         let (state, atom) = doc
             .file
-            .parse_to(doc.file.file_id(), index, Compatibility::Conquerors);
+            .parse_to(doc.file.file_id(), index, doc.compatibility);
         let atom = match atom {
             Some(atom) => atom,
             None => return Ok(serde_json::to_value(()).unwrap()),
@@ -390,6 +853,153 @@ where
         .map_err(internal_error)
     }
 
+    /// Find the `#const` or `#define` name at a position, and all its uses.
+    fn find_references(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<FoundReferences>, jsonrpc_core::Error> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params;
+        let scripts =
+            Scripts::for_document(&text_document.uri, &self.documents).ok_or_else(unknown_file)?;
+        let index = scripts
+            .file
+            .get_byte_index(
+                scripts.document,
+                position.line,
+                position.character,
+                scripts.encoding,
+            )
+            .ok_or_else(out_of_range)?;
+
+        let (kind, location, references) =
+            match references::find_name_at(&scripts.file, scripts.document, index) {
+                Some((kind, word)) => (
+                    kind,
+                    word.location,
+                    references::find_references(&scripts.file, kind, word.value),
+                ),
+                None => return Ok(None),
+            };
+        Ok(Some(FoundReferences {
+            scripts,
+            location,
+            kind,
+            references,
+        }))
+    }
+
+    /// Find all uses of a `#const` or `#define` name.
+    fn references(&self, params: ReferenceParams) -> RpcResult {
+        let include_declaration = params.context.include_declaration;
+        let found = match self.find_references(params.text_document_position)? {
+            Some(found) => found,
+            None => {
+                return serde_json::to_value(Option::<Vec<Location>>::None).map_err(internal_error)
+            }
+        };
+
+        let locations = found
+            .references
+            .iter()
+            .filter(|reference| include_declaration || !reference.is_declaration)
+            .map(|reference| found.scripts.to_lsp_location(reference.location))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        serde_json::to_value(locations).map_err(internal_error)
+    }
+
+    /// Check that the name at a position can be renamed. Builtin names can not be renamed.
+    fn prepare_rename(&self, params: TextDocumentPositionParams) -> RpcResult {
+        let found = match self.find_references(params)? {
+            Some(found) => found,
+            None => {
+                return serde_json::to_value(Option::<PrepareRenameResponse>::None)
+                    .map_err(internal_error)
+            }
+        };
+        if !found
+            .references
+            .iter()
+            .any(|reference| reference.is_declaration)
+        {
+            return Err(jsonrpc_core::Error::invalid_params(
+                "Only names declared in this map can be renamed",
+            ));
+        }
+
+        let range = found
+            .scripts
+            .to_lsp_range(found.location)
+            .ok_or_else(out_of_range)?;
+        serde_json::to_value(PrepareRenameResponse::Range(range)).map_err(internal_error)
+    }
+
+    /// Rename a `#const` or `#define` name everywhere it is used.
+    fn rename(&self, params: RenameParams) -> RpcResult {
+        let RenameParams {
+            text_document_position,
+            new_name,
+            ..
+        } = params;
+        if !references::is_valid_name(&new_name) {
+            return Err(jsonrpc_core::Error::invalid_params(format!(
+                "`{}` is not a valid name",
+                new_name
+            )));
+        }
+        let found = match self.find_references(text_document_position)? {
+            Some(found) => found,
+            None => {
+                return serde_json::to_value(Option::<WorkspaceEdit>::None).map_err(internal_error)
+            }
+        };
+        if !found
+            .references
+            .iter()
+            .any(|reference| reference.is_declaration)
+        {
+            return Err(jsonrpc_core::Error::invalid_params(
+                "Only names declared in this map can be renamed",
+            ));
+        }
+
+        // Renaming to an existing name would merge the two names.
+        let scripts = &found.scripts;
+        let range = found.location.range();
+        let old_name = &scripts.file.source(found.location.file())
+            [usize::from(range.start)..usize::from(range.end)];
+        if new_name != old_name
+            && references::is_declared(&scripts.file, scripts.compatibility, found.kind, &new_name)
+        {
+            let statement = match found.kind {
+                references::NameKind::Const => "#const",
+                references::NameKind::Define => "#define",
+            };
+            return Err(jsonrpc_core::Error::invalid_params(format!(
+                "A `{}` named `{}` already exists",
+                statement, new_name
+            )));
+        }
+
+        let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+        for reference in &found.references {
+            let Location { uri, range } = scripts.to_lsp_location(reference.location)?;
+            changes.entry(uri).or_default().push(TextEdit {
+                range,
+                new_text: new_name.clone(),
+            });
+        }
+
+        serde_json::to_value(WorkspaceEdit {
+            changes: Some(changes),
+            ..WorkspaceEdit::default()
+        })
+        .map_err(internal_error)
+    }
+
     /// Format a document.
     fn format(&self, params: DocumentFormattingParams) -> RpcResult {
         let doc = self
@@ -398,7 +1008,7 @@ where
             .ok_or_else(unknown_file)?;
 
         let options = FormatOptions::default()
-            .tab_size(params.options.tab_size as u32)
+            .tab_size(params.options.tab_size)
             .use_spaces(params.options.insert_spaces);
         let result = options.format(doc.file.main_source());
 
@@ -414,17 +1024,69 @@ where
         .map_err(internal_error)
     }
 
+    /// Get the configuration for a script on disk. Scripts in a project without a configuration
+    /// file, or with a broken one, use the default configuration.
+    fn config_for(&mut self, script: &Path) -> Config {
+        let dir = script.parent().unwrap_or(script).to_path_buf();
+        if let Some(config) = self.configs.get(&dir) {
+            return config.clone();
+        }
+
+        let config = match Config::for_script(script) {
+            Ok(config) => {
+                self.config_error = None;
+                let unknown = config.unknown_lints(RMSCheck::default().lint_names());
+                if !unknown.is_empty() {
+                    self.show_message(
+                        MessageType::WARNING,
+                        format!(
+                            "Unknown lints in the rms-check configuration: `{}`",
+                            unknown.join("`, `")
+                        ),
+                    );
+                }
+                config
+            }
+            Err(err) => {
+                let message = format!(
+                    "Could not load the rms-check configuration, using the defaults instead: {}",
+                    err
+                );
+                if self.config_error.as_ref() != Some(&message) {
+                    self.show_message(MessageType::ERROR, message.clone());
+                    self.config_error = Some(message);
+                }
+                Config::default()
+            }
+        };
+        self.configs.insert(dir, config.clone());
+        config
+    }
+
     /// Run rms-check.
     fn run_checks(&mut self, uri: Url) {
+        if !self.documents.contains_key(&uri) {
+            return;
+        }
+
+        // Documents that are not on disk use the default configuration.
+        let config = match uri.to_file_path() {
+            Ok(path) => self.config_for(&path),
+            Err(()) => Config::default(),
+        };
         let doc = match self.documents.get_mut(&uri) {
             Some(doc) => doc,
             _ => return,
         };
-
+        let compatibility = config
+            .get_compatibility()
+            .unwrap_or(self.default_compatibility);
         let result = RMSCheck::default()
-            .compatibility(Compatibility::Conquerors)
-            .check(&doc.file);
+            .compatibility(compatibility)
+            .with_config(config)
+            .check_with_cache(&doc.file, &mut doc.cache);
 
+        doc.compatibility = result.compatibility();
         doc.diagnostics = result.into_iter().collect();
     }
 
@@ -442,6 +1104,15 @@ where
             .map(|diagnostic| self.to_lsp_diagnostic(doc, diagnostic))
             .collect::<Result<Vec<_>, _>>()?;
 
+        (self.emit)(json!({
+            "jsonrpc": "2.0",
+            "method": "rms-check/compatibility",
+            "params": {
+                "uri": uri,
+                "compatibility": doc.compatibility.to_string(),
+            },
+        }));
+
         let params = PublishDiagnosticsParams::new(uri, diagnostics, Some(doc.version));
         (self.emit)(json!({
             "jsonrpc": "2.0",
@@ -470,6 +1141,10 @@ impl RMSCheckLSP {
             inner: Arc::new(Mutex::new(Inner {
                 emit: Box::new(emit),
                 documents: Default::default(),
+                default_compatibility: Compatibility::default(),
+                position_encoding: PositionEncoding::Utf16,
+                config_error: None,
+                configs: HashMap::new(),
             })),
             handler: IoHandler::new(),
         };
@@ -479,7 +1154,7 @@ impl RMSCheckLSP {
 
     /// Install JSON-RPC methods and notification handlers.
     fn install_handlers(&mut self) {
-        self.add_method("initialize", |inner, params: InitializeParams| {
+        self.add_method("initialize", |inner, params: serde_json::Value| {
             inner.initialize(params)
         });
 
@@ -495,11 +1170,26 @@ impl RMSCheckLSP {
             |inner, params: DidChangeTextDocumentParams| inner.changed(params),
         );
 
+        self.add_notification(
+            "textDocument/didSave",
+            |inner, params: DidSaveTextDocumentParams| inner.saved(params),
+        );
+
+        self.add_notification(
+            "workspace/didChangeWatchedFiles",
+            |inner, params: DidChangeWatchedFilesParams| inner.changed_watched_files(params),
+        );
+
         self.add_notification(
             "textDocument/didClose",
             |inner, params: DidCloseTextDocumentParams| inner.closed(params),
         );
 
+        self.add_notification(
+            "workspace/didChangeConfiguration",
+            |inner, params: DidChangeConfigurationParams| inner.changed_configuration(params),
+        );
+
         self.add_method(
             "textDocument/codeAction",
             |inner, params: CodeActionParams| inner.code_action(params),
@@ -510,11 +1200,54 @@ impl RMSCheckLSP {
             |inner, params: FoldingRangeParams| inner.folding_ranges(params),
         );
 
+        self.add_method(
+            "textDocument/documentSymbol",
+            |inner, params: DocumentSymbolParams| inner.document_symbol(params),
+        );
+
+        self.add_method(
+            "textDocument/semanticTokens/full",
+            |inner, params: SemanticTokensParams| inner.semantic_tokens_full(params),
+        );
+
+        self.add_method(
+            "textDocument/semanticTokens/range",
+            |inner, params: SemanticTokensRangeParams| inner.semantic_tokens_range(params),
+        );
+
+        self.add_method(
+            "textDocument/inlayHint",
+            |inner, params: inlay_hints::InlayHintParams| inner.inlay_hint(params),
+        );
+
         self.add_method(
             "textDocument/definition",
             |inner, params: GotoDefinitionParams| inner.goto_definition(params),
         );
 
+        self.add_method(
+            "textDocument/references",
+            |inner, params: ReferenceParams| inner.references(params),
+        );
+
+        self.add_method(
+            "textDocument/prepareRename",
+            |inner, params: TextDocumentPositionParams| inner.prepare_rename(params),
+        );
+
+        self.add_method("textDocument/rename", |inner, params: RenameParams| {
+            inner.rename(params)
+        });
+
+        self.add_method(
+            "textDocument/completion",
+            |inner, params: CompletionParams| inner.completion(params),
+        );
+
+        self.add_method("textDocument/hover", |inner, params: HoverParams| {
+            inner.hover(params)
+        });
+
         self.add_method(
             "textDocument/signatureHelp",
             |inner, params: TextDocumentPositionParams| inner.signature_help(params),
@@ -564,9 +1297,11 @@ impl RMSCheckLSP {
                 Err(err) => return handle_error(internal_error(err)),
             };
 
-            match callback(&mut guard, params) {
-                Ok(()) => (),
-                Err(err) => handle_error(err),
+            let result = callback(&mut guard, params);
+            // `handle_error` needs the lock too.
+            drop(guard);
+            if let Err(err) = result {
+                handle_error(err);
             }
         })
     }
@@ -592,3 +1327,938 @@ impl RMSCheckLSP {
             .map(|string| string.parse().unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn request(
+        lsp: &mut RMSCheckLSP,
+        method: &str,
+        params: serde_json::Value,
+    ) -> serde_json::Value {
+        lsp.handle_sync(json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+            .unwrap()
+    }
+
+    /// Open a document in a language server.
+    fn did_open(lsp: &mut RMSCheckLSP, uri: &Url, text: &str) {
+        lsp.handle_sync(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": "aoe2-rms",
+                    "version": 1,
+                    "text": text,
+                },
+            },
+        }));
+    }
+
+    /// Start a language server and open a document that is not saved to disk.
+    fn open_document(text: &str) -> (RMSCheckLSP, Url) {
+        let uri: Url = "untitled:test.rms".parse().unwrap();
+        let mut lsp = RMSCheckLSP::new(|_| {});
+        request(&mut lsp, "initialize", json!({ "capabilities": {} }));
+        did_open(&mut lsp, &uri, text);
+        (lsp, uri)
+    }
+
+    fn hover(lsp: &mut RMSCheckLSP, uri: &Url, line: u32, character: u32) -> Option<Hover> {
+        let response = request(
+            lsp,
+            "textDocument/hover",
+            json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+            }),
+        );
+        serde_json::from_value(response["result"].clone()).unwrap()
+    }
+
+    fn hover_markdown(hover: &Hover) -> &str {
+        match &hover.contents {
+            HoverContents::Markup(markup) => &markup.value,
+            contents => panic!("expected markdown, got {:?}", contents),
+        }
+    }
+
+    #[test]
+    fn hover_command() {
+        let (mut lsp, uri) =
+            open_document("<OBJECTS_GENERATION>\ncreate_object WOLF {\n  number_of_objects 3\n}\n");
+        let hover = hover(&mut lsp, &uri, 1, 4).unwrap();
+        assert_eq!(
+            hover.range,
+            Some(lsp_types::Range::new(
+                Position::new(1, 0),
+                Position::new(1, 13)
+            ))
+        );
+        assert_eq!(
+            hover_markdown(&hover),
+            "```rms\ncreate_object UnitType:Token\n```\n\n- `UnitType:Token`"
+        );
+    }
+
+    #[test]
+    fn hover_const() {
+        let (mut lsp, uri) = open_document(
+            "#const WOLF_COUNT 3
+<OBJECTS_GENERATION>
+create_object WOLF {
+  number_of_objects WOLF_COUNT
+}
+#const WOLF_COUNT 5
+create_object WOLF {
+  number_of_objects WOLF_COUNT
+}
+create_object GOLD {
+  number_of_objects 12
+}
+",
+        );
+        // Each use shows the value the name has at that point in the script.
+        let first = hover(&mut lsp, &uri, 3, 22).unwrap();
+        let second = hover(&mut lsp, &uri, 7, 22).unwrap();
+        let builtin = hover(&mut lsp, &uri, 9, 16).unwrap();
+        let number = hover(&mut lsp, &uri, 10, 21);
+
+        assert_eq!(
+            first.range,
+            Some(lsp_types::Range::new(
+                Position::new(3, 20),
+                Position::new(3, 30)
+            ))
+        );
+        assert_eq!(
+            hover_markdown(&first),
+            "```rms\n#const WOLF_COUNT 3\n```\n\nDefined on line 1."
+        );
+        assert_eq!(
+            hover_markdown(&second),
+            "```rms\n#const WOLF_COUNT 5\n```\n\nDefined on line 6."
+        );
+        assert_eq!(
+            hover_markdown(&builtin),
+            "```rms\n#const GOLD 66\n```\n\nBuiltin constant."
+        );
+        // Numbers have no documentation.
+        assert_eq!(number, None);
+    }
+
+    /// Get the labels of the completions at the end of a script.
+    fn complete_at_end(text: &str) -> Vec<String> {
+        let (mut lsp, uri) = open_document(text);
+        let line = text.matches('\n').count();
+        let character = text.len() - text.rfind('\n').map_or(0, |index| index + 1);
+        let response = request(
+            &mut lsp,
+            "textDocument/completion",
+            json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+            }),
+        );
+        let items: Vec<lsp_types::CompletionItem> =
+            serde_json::from_value(response["result"].clone()).unwrap();
+        items.into_iter().map(|item| item.label).collect()
+    }
+
+    #[test]
+    fn complete_sections() {
+        let labels = complete_at_end("");
+        assert!(labels.iter().any(|label| label == "<PLAYER_SETUP>"));
+        assert!(labels.iter().any(|label| label == "<OBJECTS_GENERATION>"));
+        assert!(!labels.iter().any(|label| label == "create_object"));
+        assert!(!labels.iter().any(|label| label == "number_of_objects"));
+
+        // The partially typed `<` is replaced.
+        let labels = complete_at_end("<PLAYER_SETUP>\n<LAND");
+        assert!(labels.iter().any(|label| label == "<LAND_GENERATION>"));
+    }
+
+    #[test]
+    fn complete_section_commands() {
+        let sections = [
+            ("<PLAYER_SETUP>", "random_placement", "create_land"),
+            ("<LAND_GENERATION>", "create_land", "create_object"),
+            ("<ELEVATION_GENERATION>", "create_elevation", "create_land"),
+            (
+                "<CLIFF_GENERATION>",
+                "min_number_of_cliffs",
+                "create_terrain",
+            ),
+            ("<TERRAIN_GENERATION>", "create_terrain", "create_elevation"),
+            (
+                "<CONNECTION_GENERATION>",
+                "create_connect_all_players_land",
+                "create_object",
+            ),
+            ("<OBJECTS_GENERATION>", "create_object", "create_land"),
+        ];
+        for (section, expected, unexpected) in &sections {
+            let labels = complete_at_end(&format!("{}\n", section));
+            assert!(
+                labels.iter().any(|label| label == expected),
+                "{} should be offered in {}",
+                expected,
+                section
+            );
+            assert!(
+                !labels.iter().any(|label| label == unexpected),
+                "{} should not be offered in {}",
+                unexpected,
+                section
+            );
+        }
+    }
+
+    #[test]
+    fn complete_attributes() {
+        let labels = complete_at_end("<OBJECTS_GENERATION>\ncreate_object GOLD {\n  number_of_obj");
+        assert!(labels.iter().any(|label| label == "number_of_objects"));
+        assert!(labels.iter().any(|label| label == "group_placement_radius"));
+        assert!(!labels.iter().any(|label| label == "base_size"));
+        assert!(!labels.iter().any(|label| label == "create_object"));
+
+        let labels = complete_at_end("<LAND_GENERATION>\ncreate_land {\n  ");
+        assert!(labels.iter().any(|label| label == "base_size"));
+        assert!(!labels.iter().any(|label| label == "number_of_objects"));
+    }
+
+    #[test]
+    fn complete_const_arguments() {
+        let labels = complete_at_end(
+            "#const MY_GOLD 66\n#define MY_DEFINE\n<OBJECTS_GENERATION>\ncreate_object MY_",
+        );
+        assert!(labels.iter().any(|label| label == "MY_GOLD"));
+        assert!(labels.iter().any(|label| label == "GOLD"));
+        assert!(!labels.iter().any(|label| label == "MY_DEFINE"));
+        assert!(!labels.iter().any(|label| label == "create_object"));
+
+        let labels = complete_at_end(
+            "#const MY_COUNT 6\n<OBJECTS_GENERATION>\ncreate_object GOLD {\n  number_of_objects ",
+        );
+        assert!(labels.iter().any(|label| label == "MY_COUNT"));
+        assert!(!labels.iter().any(|label| label == "number_of_groups"));
+
+        // Only `#define` names are offered in conditions.
+        let labels = complete_at_end("#const MY_GOLD 66\n#define MY_DEFINE\nif ");
+        assert!(labels.iter().any(|label| label == "MY_DEFINE"));
+        assert!(!labels.iter().any(|label| label == "MY_GOLD"));
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> lsp_types::Range {
+        lsp_types::Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    #[test]
+    fn document_symbols() {
+        let (mut lsp, uri) = open_document(
+            "#const WOLF_COUNT 3
+<OBJECTS_GENERATION>
+#define HAS_WOLVES
+create_object WOLF {
+  number_of_objects WOLF_COUNT
+}
+start_random
+  percent_chance 50
+    #const SHEEP_COUNT 2
+end_random
+",
+        );
+        let response = request(
+            &mut lsp,
+            "textDocument/documentSymbol",
+            json!({ "textDocument": { "uri": uri } }),
+        );
+        let symbols: Vec<lsp_types::DocumentSymbol> =
+            serde_json::from_value(response["result"].clone()).unwrap();
+
+        assert_eq!(symbols.len(), 2);
+        let wolf_count = &symbols[0];
+        assert_eq!(wolf_count.name, "WOLF_COUNT");
+        assert_eq!(wolf_count.detail.as_deref(), Some("3"));
+        assert_eq!(wolf_count.kind, lsp_types::SymbolKind::CONSTANT);
+        assert_eq!(wolf_count.range, range((0, 0), (0, 19)));
+        assert_eq!(wolf_count.selection_range, range((0, 7), (0, 17)));
+        assert_eq!(wolf_count.children, None);
+
+        let section = &symbols[1];
+        assert_eq!(section.name, "<OBJECTS_GENERATION>");
+        assert_eq!(section.kind, lsp_types::SymbolKind::NAMESPACE);
+        assert_eq!(section.range, range((1, 0), (9, 10)));
+        assert_eq!(section.selection_range, range((1, 0), (1, 20)));
+        let children = section.children.as_ref().unwrap();
+        assert_eq!(children.len(), 3);
+
+        let has_wolves = &children[0];
+        assert_eq!(has_wolves.name, "HAS_WOLVES");
+        assert_eq!(has_wolves.kind, lsp_types::SymbolKind::BOOLEAN);
+        assert_eq!(has_wolves.range, range((2, 0), (2, 18)));
+        assert_eq!(has_wolves.selection_range, range((2, 8), (2, 18)));
+
+        let wolf = &children[1];
+        assert_eq!(wolf.name, "WOLF");
+        assert_eq!(wolf.detail.as_deref(), Some("create_object"));
+        assert_eq!(wolf.kind, lsp_types::SymbolKind::OBJECT);
+        assert_eq!(wolf.range, range((3, 0), (5, 1)));
+        assert_eq!(wolf.selection_range, range((3, 14), (3, 18)));
+        assert_eq!(wolf.children, Some(vec![]));
+
+        let random = &children[2];
+        assert_eq!(random.name, "start_random");
+        assert_eq!(random.kind, lsp_types::SymbolKind::ENUM);
+        assert_eq!(random.range, range((6, 0), (9, 10)));
+        let branches = random.children.as_ref().unwrap();
+        assert_eq!(branches.len(), 1);
+        assert_eq!(branches[0].name, "percent_chance 50");
+        assert_eq!(branches[0].range, range((7, 2), (8, 24)));
+        let sheep_count = &branches[0].children.as_ref().unwrap()[0];
+        assert_eq!(sheep_count.name, "SHEEP_COUNT");
+        assert_eq!(sheep_count.selection_range, range((8, 11), (8, 22)));
+    }
+
+    const HIGHLIGHTED: &str = "/* Crème 🐺 */ #const WOLF_COUNT 3
+<OBJECTS_GENERATION>
+create_object WOLF {
+  number_of_objects WOLF_COUNT
+}
+";
+
+    fn semantic_token(
+        delta_line: u32,
+        delta_start: u32,
+        length: u32,
+        token_type: u32,
+        token_modifiers_bitset: u32,
+    ) -> lsp_types::SemanticToken {
+        lsp_types::SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type,
+            token_modifiers_bitset,
+        }
+    }
+
+    #[test]
+    fn semantic_tokens_full() {
+        let (mut lsp, uri) = open_document(HIGHLIGHTED);
+        let response = request(
+            &mut lsp,
+            "textDocument/semanticTokens/full",
+            json!({ "textDocument": { "uri": uri } }),
+        );
+        let tokens: lsp_types::SemanticTokens =
+            serde_json::from_value(response["result"].clone()).unwrap();
+
+        // Columns are counted in UTF-16 code units: the wolf takes two.
+        assert_eq!(
+            tokens.data,
+            vec![
+                // comment
+                semantic_token(0, 0, 14, 7, 0),
+                // `#const` keyword
+                semantic_token(0, 15, 6, 3, 0),
+                // declaration, readonly
+                semantic_token(0, 7, 10, 4, 0b011),
+                // number
+                semantic_token(0, 11, 1, 6, 0),
+                // section
+                semantic_token(1, 0, 20, 0, 0),
+                // command
+                semantic_token(1, 0, 13, 1, 0),
+                // builtin, readonly
+                semantic_token(0, 14, 4, 4, 0b110),
+                // attribute
+                semantic_token(1, 2, 17, 2, 0),
+                // readonly
+                semantic_token(0, 18, 10, 4, 0b010),
+            ]
+        );
+    }
+
+    #[test]
+    fn semantic_tokens_range() {
+        let (mut lsp, uri) = open_document(HIGHLIGHTED);
+        let response = request(
+            &mut lsp,
+            "textDocument/semanticTokens/range",
+            json!({
+                "textDocument": { "uri": uri },
+                "range": range((0, 15), (2, 13)),
+            }),
+        );
+        let tokens: lsp_types::SemanticTokens =
+            serde_json::from_value(response["result"].clone()).unwrap();
+
+        // Only tokens that start inside the range are included, and the first one is relative to
+        // the start of the document.
+        assert_eq!(
+            tokens.data,
+            vec![
+                semantic_token(0, 15, 6, 3, 0),
+                semantic_token(0, 7, 10, 4, 0b011),
+                semantic_token(0, 11, 1, 6, 0),
+                semantic_token(1, 0, 20, 0, 0),
+                semantic_token(1, 0, 13, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn semantic_tokens_inside_rnd() {
+        let (mut lsp, uri) = open_document(
+            "#const MAX_WOLVES 5
+<OBJECTS_GENERATION>
+create_object WOLF {
+  number_of_objects rnd(-1,MAX_WOLVES)
+}
+",
+        );
+        let response = request(
+            &mut lsp,
+            "textDocument/semanticTokens/range",
+            json!({
+                "textDocument": { "uri": uri },
+                "range": range((3, 20), (3, 38)),
+            }),
+        );
+        let tokens: lsp_types::SemanticTokens =
+            serde_json::from_value(response["result"].clone()).unwrap();
+
+        assert_eq!(
+            tokens.data,
+            vec![
+                // `rnd`
+                semantic_token(3, 20, 3, 1, 0b100),
+                // `(`
+                semantic_token(0, 3, 1, 8, 0),
+                // number
+                semantic_token(0, 1, 2, 6, 0),
+                // `,`
+                semantic_token(0, 2, 1, 8, 0),
+                // readonly
+                semantic_token(0, 1, 10, 4, 0b010),
+                // `)`
+                semantic_token(0, 10, 1, 8, 0),
+            ]
+        );
+    }
+
+    fn inlay_hints(lsp: &mut RMSCheckLSP, uri: &Url, range: lsp_types::Range) -> Vec<String> {
+        let response = request(
+            lsp,
+            "textDocument/inlayHint",
+            json!({ "textDocument": { "uri": uri }, "range": range }),
+        );
+        response["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|hint| {
+                format!(
+                    "{}:{} {}",
+                    hint["position"]["line"], hint["position"]["character"], hint["label"]
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inlay_hints_for_consts_and_chances() {
+        let (mut lsp, uri) = open_document(
+            "#const HALF 50
+#const WOLF_COUNT 3
+<OBJECTS_GENERATION>
+create_object WOLF {
+  number_of_objects WOLF_COUNT
+}
+start_random
+  percent_chance HALF
+    start_random
+      percent_chance 30
+      percent_chance 20
+    end_random
+  percent_chance 25
+end_random
+",
+        );
+        assert_eq!(
+            inlay_hints(&mut lsp, &uri, range((0, 0), (14, 0))),
+            vec![
+                "3:18 \"⟵ 126\"",
+                "4:30 \"⟵ 3\"",
+                "7:21 \"⟵ 50\"",
+                "7:21 \"50% overall\"",
+                "9:23 \"15% overall\"",
+                "10:23 \"10% overall\"",
+                "12:19 \"25% overall\"",
+            ]
+        );
+        // Only hints inside the visible range are returned.
+        assert_eq!(
+            inlay_hints(&mut lsp, &uri, range((9, 0), (11, 0))),
+            vec!["9:23 \"15% overall\"", "10:23 \"10% overall\""]
+        );
+    }
+
+    #[test]
+    fn compatibility_settings() {
+        assert_eq!(
+            compatibility_setting(&json!({ "defaultCompatibility": "up15" })),
+            Ok(Some(Compatibility::UserPatch15))
+        );
+        assert_eq!(
+            compatibility_setting(&json!({ "rmsCheck": { "defaultCompatibility": "HD Edition" } })),
+            Ok(Some(Compatibility::HDEdition))
+        );
+        assert_eq!(
+            compatibility_setting(&json!({ "defaultCompatibility": "up16" })),
+            Err("Unknown compatibility setting `up16`".to_string())
+        );
+        assert_eq!(compatibility_setting(&json!({})), Ok(None));
+        assert_eq!(
+            compatibility_setting(&json!({ "rmsCheck": { "otherSetting": true } })),
+            Ok(None)
+        );
+    }
+
+    /// Start a language server that records the messages it sends.
+    fn recording_server() -> (RMSCheckLSP, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let sent = Arc::clone(&messages);
+        let lsp = RMSCheckLSP::new(move |message| sent.lock().unwrap().push(message));
+        (lsp, messages)
+    }
+
+    /// Take the parameters of the recorded notifications with the given method.
+    fn take_notifications(
+        messages: &Mutex<Vec<serde_json::Value>>,
+        method: &str,
+    ) -> Vec<serde_json::Value> {
+        let mut messages = messages.lock().unwrap();
+        let (matching, rest) = messages
+            .drain(..)
+            .partition::<Vec<_>, _>(|message| message["method"] == method);
+        *messages = rest;
+        matching
+            .into_iter()
+            .map(|message| message["params"].clone())
+            .collect()
+    }
+
+    fn diagnostic_codes(params: &serde_json::Value) -> Vec<String> {
+        params["diagnostics"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|diagnostic| diagnostic["code"].as_str().map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn header_compatibility_overrides_configured_default() {
+        let (mut lsp, messages) = recording_server();
+        request(
+            &mut lsp,
+            "initialize",
+            json!({
+                "capabilities": {},
+                "initializationOptions": { "rmsCheck": { "defaultCompatibility": "up14" } },
+            }),
+        );
+        did_open(
+            &mut lsp,
+            &"untitled:default.rms".parse().unwrap(),
+            "<PLAYER_SETUP>\nnomad_resources\n",
+        );
+        did_open(
+            &mut lsp,
+            &"untitled:header.rms".parse().unwrap(),
+            "/* Compatibility: Conquerors */\n<PLAYER_SETUP>\nnomad_resources\n",
+        );
+
+        let compatibility = take_notifications(&messages, "rms-check/compatibility");
+        assert_eq!(
+            compatibility,
+            vec![
+                json!({ "uri": "untitled:default.rms", "compatibility": "UserPatch 1.4" }),
+                json!({ "uri": "untitled:header.rms", "compatibility": "Conquerors" }),
+            ]
+        );
+        let diagnostics = take_notifications(&messages, "textDocument/publishDiagnostics");
+        assert!(!diagnostic_codes(&diagnostics[0]).contains(&"compatibility".to_string()));
+        assert!(diagnostic_codes(&diagnostics[1]).contains(&"compatibility".to_string()));
+
+        // Changing the default re-checks the documents, but the header still wins.
+        lsp.handle_sync(json!({
+            "jsonrpc": "2.0",
+            "method": "workspace/didChangeConfiguration",
+            "params": { "settings": { "rmsCheck": { "defaultCompatibility": "Definitive Edition" } } },
+        }));
+        let mut compatibility = take_notifications(&messages, "rms-check/compatibility");
+        compatibility.sort_by_key(|params| params["uri"].as_str().unwrap().to_string());
+        assert_eq!(
+            compatibility,
+            vec![
+                json!({ "uri": "untitled:default.rms", "compatibility": "Definitive Edition" }),
+                json!({ "uri": "untitled:header.rms", "compatibility": "Conquerors" }),
+            ]
+        );
+    }
+
+    #[test]
+    fn configuration_is_cached_until_a_watched_file_changes() {
+        let dir = std::env::temp_dir().join(format!("rms-check-lsp-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("rms-check.toml");
+        std::fs::write(&config, "[lints]\nunused-declaration = \"off\"\n").unwrap();
+        let uri = Url::from_file_path(dir.join("config.rms")).unwrap();
+
+        let (mut lsp, messages) = recording_server();
+        request(&mut lsp, "initialize", json!({ "capabilities": {} }));
+        did_open(&mut lsp, &uri, "#const UNUSED 1\n");
+        let unused = |messages: &Mutex<Vec<serde_json::Value>>| {
+            let diagnostics = take_notifications(messages, "textDocument/publishDiagnostics");
+            diagnostic_codes(diagnostics.last().unwrap())
+                .contains(&"unused-declaration".to_string())
+        };
+        assert!(!unused(&messages));
+
+        // The configuration is not read again on every edit.
+        std::fs::write(&config, "[lints]\nunused-declarationn = \"off\"\n").unwrap();
+        lsp.handle_sync(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{ "text": "#const UNUSED 2\n" }],
+            },
+        }));
+        assert!(!unused(&messages));
+
+        lsp.handle_sync(json!({
+            "jsonrpc": "2.0",
+            "method": "workspace/didChangeWatchedFiles",
+            "params": {
+                "changes": [{ "uri": Url::from_file_path(&config).unwrap(), "type": 2 }],
+            },
+        }));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(unused(&messages));
+        let shown = take_notifications(&messages, "window/showMessage");
+        assert_eq!(
+            shown[0]["message"],
+            "Unknown lints in the rms-check configuration: `unused-declarationn`"
+        );
+    }
+
+    #[test]
+    fn negotiate_position_encodings() {
+        let negotiate = |capabilities: serde_json::Value| {
+            let mut lsp = RMSCheckLSP::new(|_| {});
+            let response = request(
+                &mut lsp,
+                "initialize",
+                json!({ "capabilities": capabilities }),
+            );
+            response["result"]["capabilities"]["positionEncoding"].clone()
+        };
+
+        assert_eq!(negotiate(json!({})), "utf-16");
+        assert_eq!(
+            negotiate(json!({ "general": { "positionEncodings": ["utf-32", "utf-8"] } })),
+            "utf-32"
+        );
+        assert_eq!(
+            negotiate(json!({ "general": { "positionEncodings": ["utf-7", "utf-8"] } })),
+            "utf-8"
+        );
+        assert_eq!(
+            negotiate(json!({ "general": { "positionEncodings": ["utf-7"] } })),
+            "utf-16"
+        );
+    }
+
+    #[test]
+    fn incremental_change_after_non_ascii() {
+        let (mut lsp, uri) = open_document("/* 🐺 */ #const A 1\n");
+        // The wolf is two UTF-16 code units, so `A` starts at character 16.
+        lsp.handle_sync(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{ "range": range((0, 16), (0, 16)), "text": "WOLF_" }],
+            },
+        }));
+        let response = request(
+            &mut lsp,
+            "textDocument/documentSymbol",
+            json!({ "textDocument": { "uri": uri } }),
+        );
+        let symbols: Vec<lsp_types::DocumentSymbol> =
+            serde_json::from_value(response["result"].clone()).unwrap();
+        assert_eq!(symbols[0].name, "WOLF_A");
+        assert_eq!(symbols[0].selection_range, range((0, 16), (0, 22)));
+    }
+
+    #[test]
+    fn change_past_the_end_of_a_line() {
+        let (mut lsp, uri) = open_document("#const A 1\r\n");
+        // Columns past the end of a line refer to the end of the line.
+        lsp.handle_sync(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [{ "range": range((0, 40), (0, 40)), "text": "0" }],
+            },
+        }));
+        let response = request(
+            &mut lsp,
+            "textDocument/hover",
+            json!({ "textDocument": { "uri": uri }, "position": { "line": 0, "character": 100 } }),
+        );
+        assert!(response.get("error").is_none());
+        let response = request(
+            &mut lsp,
+            "textDocument/documentSymbol",
+            json!({ "textDocument": { "uri": uri } }),
+        );
+        let symbols: Vec<lsp_types::DocumentSymbol> =
+            serde_json::from_value(response["result"].clone()).unwrap();
+        assert_eq!(symbols[0].detail.as_deref(), Some("10"));
+    }
+
+    #[test]
+    fn failed_change_batch_is_not_applied() {
+        let (mut lsp, uri) = open_document("#const A 1\n");
+        let symbol_names = |lsp: &mut RMSCheckLSP| {
+            let response = request(
+                lsp,
+                "textDocument/documentSymbol",
+                json!({ "textDocument": { "uri": uri } }),
+            );
+            let symbols: Vec<lsp_types::DocumentSymbol> =
+                serde_json::from_value(response["result"].clone()).unwrap();
+            symbols
+                .into_iter()
+                .map(|symbol| symbol.name)
+                .collect::<Vec<_>>()
+        };
+
+        // The second change is out of range, so the first one must not be applied either.
+        lsp.handle_sync(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [
+                    { "range": range((0, 7), (0, 8)), "text": "B" },
+                    { "range": range((5, 0), (5, 0)), "text": "#const C 3\n" },
+                ],
+            },
+        }));
+        assert_eq!(symbol_names(&mut lsp), vec!["A"]);
+
+        lsp.handle_sync(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": { "uri": uri, "version": 2 },
+                "contentChanges": [
+                    { "range": range((0, 7), (0, 8)), "text": "B" },
+                    { "range": range((1, 0), (1, 0)), "text": "#const C 3\n" },
+                ],
+            },
+        }));
+        assert_eq!(symbol_names(&mut lsp), vec!["B", "C"]);
+    }
+
+    #[test]
+    fn incremental_diagnostics_match_full_check() {
+        let uri: Url = "untitled:incremental.rms".parse().unwrap();
+        let open = |lsp: &mut RMSCheckLSP, text: &str| {
+            request(lsp, "initialize", json!({ "capabilities": {} }));
+            did_open(lsp, &uri, text);
+        };
+        let full_check = |text: &str| {
+            let (mut lsp, messages) = recording_server();
+            open(&mut lsp, text);
+            take_notifications(&messages, "textDocument/publishDiagnostics")
+                .pop()
+                .unwrap()["diagnostics"]
+                .clone()
+        };
+
+        let mut text = "<PLAYER_SETUP>\nrandom_placement\n#const MY_GOLD 66\n".to_string();
+        text.push_str("<LAND_GENERATION>\nbase_terrain GRASS\n");
+        text.push_str("<OBJECTS_GENERATION>\n");
+        for _ in 0..100 {
+            text.push_str("create_object MY_GOLD {\n  number_of_objects 4\n}\n");
+        }
+        let (mut lsp, messages) = recording_server();
+        open(&mut lsp, &text);
+
+        let edits = [
+            (
+                "  number_of_objects 4\n}\n",
+                "  number_of_objects UNKNOWN_COUNT\n}\n",
+            ),
+            (
+                "<OBJECTS_GENERATION>",
+                "#const MY_GOLD 67\n<OBJECTS_GENERATION>",
+            ),
+            ("create_object MY_GOLD {\n", "create_object MY_GOLD\n"),
+            ("#const MY_GOLD 66", "#const MY_GOLDEN 66"),
+        ];
+        for (version, (find, replace)) in edits.iter().enumerate() {
+            // Edit the last occurrence, so the checks can resume from a saved state.
+            let start = text.rfind(find).unwrap();
+            let end = start + find.len();
+            let position = |index: usize| {
+                let line = text[..index].matches('\n').count();
+                let character = index - text[..index].rfind('\n').map_or(0, |n| n + 1);
+                (line as u32, character as u32)
+            };
+            let edit_range = range(position(start), position(end));
+            text.replace_range(start..end, replace);
+            lsp.handle_sync(json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": uri, "version": version + 2 },
+                    "contentChanges": [{ "range": edit_range, "text": replace }],
+                },
+            }));
+            let incremental = take_notifications(&messages, "textDocument/publishDiagnostics")
+                .pop()
+                .unwrap()["diagnostics"]
+                .clone();
+            assert!(!incremental.as_array().unwrap().is_empty());
+            assert_eq!(incremental, full_check(&text), "after edit {}", version + 1);
+        }
+    }
+
+    /// Create an unpacked ZR@ map with two scripts, and open only the main script.
+    fn open_map(name: &str, shared: &[u8]) -> (RMSCheckLSP, PathBuf, Url, Url) {
+        let dir = std::env::temp_dir().join(format!("ZR@{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shared.inc"), shared).unwrap();
+        let main = "<OBJECTS_GENERATION>
+create_object WOLF {
+  number_of_objects WOLF_COUNT
+}
+#const SHEEP_COUNT 4
+";
+        // The open document has changes that are not saved yet.
+        std::fs::write(dir.join("main.rms"), "").unwrap();
+        let main_uri = Url::from_file_path(dir.join("main.rms")).unwrap();
+        let shared_uri = Url::from_file_path(dir.join("shared.inc")).unwrap();
+
+        let mut lsp = RMSCheckLSP::new(|_| {});
+        request(&mut lsp, "initialize", json!({ "capabilities": {} }));
+        did_open(&mut lsp, &main_uri, main);
+        (lsp, dir, main_uri, shared_uri)
+    }
+
+    fn rename(lsp: &mut RMSCheckLSP, uri: &Url, new_name: &str) -> serde_json::Value {
+        request(
+            lsp,
+            "textDocument/rename",
+            json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": 2, "character": 22 },
+                "newName": new_name,
+            }),
+        )
+    }
+
+    const SHARED: &[u8] = b"#const WOLF_COUNT 3\n#define HAS_WOLVES\n";
+
+    fn find_references(lsp: &mut RMSCheckLSP, uri: &Url) -> Vec<Location> {
+        let response = request(
+            lsp,
+            "textDocument/references",
+            json!({
+                "textDocument": { "uri": uri },
+                "position": { "line": 2, "character": 22 },
+                "context": { "includeDeclaration": true },
+            }),
+        );
+        let mut locations: Vec<Location> =
+            serde_json::from_value(response["result"].clone()).unwrap();
+        locations.sort_by_key(|location| location.uri.to_string());
+        locations
+    }
+
+    #[test]
+    fn references_across_map_files() {
+        let (mut lsp, dir, main_uri, shared_uri) = open_map("references", SHARED);
+        let locations = find_references(&mut lsp, &main_uri);
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            locations,
+            vec![
+                Location {
+                    uri: main_uri,
+                    range: lsp_types::Range::new(Position::new(2, 20), Position::new(2, 30)),
+                },
+                Location {
+                    uri: shared_uri,
+                    range: lsp_types::Range::new(Position::new(0, 7), Position::new(0, 17)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn references_in_windows_1252_map_files() {
+        // "Crème brûlée â€" in Windows-1252, where the last two bytes are not valid UTF-8.
+        let shared = b"/* Cr\xe8me br\xfbl\xe9e \xe2\x80 */ #const WOLF_COUNT 3\n";
+        let (mut lsp, dir, main_uri, shared_uri) = open_map("encoding", shared);
+        let locations = find_references(&mut lsp, &main_uri);
+        std::fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            locations[1],
+            Location {
+                uri: shared_uri,
+                range: lsp_types::Range::new(Position::new(0, 29), Position::new(0, 39)),
+            }
+        );
+    }
+
+    #[test]
+    fn rename_across_map_files() {
+        let (mut lsp, dir, main_uri, shared_uri) = open_map("rename", SHARED);
+        // `#define` names are separate from `#const` names.
+        let renamed = rename(&mut lsp, &main_uri, "HAS_WOLVES");
+        let existing = rename(&mut lsp, &main_uri, "SHEEP_COUNT");
+        let builtin = rename(&mut lsp, &main_uri, "GRASS");
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let edit: WorkspaceEdit = serde_json::from_value(renamed["result"].clone()).unwrap();
+        let changes = edit.changes.unwrap();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[&main_uri].len(), 1);
+        assert_eq!(changes[&shared_uri][0].new_text, "HAS_WOLVES");
+
+        assert_eq!(
+            existing["error"]["message"],
+            "A `#const` named `SHEEP_COUNT` already exists"
+        );
+        assert_eq!(
+            builtin["error"]["message"],
+            "A `#const` named `GRASS` already exists"
+        );
+    }
+}
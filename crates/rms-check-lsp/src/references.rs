@@ -0,0 +1,121 @@
+use rms_check::{
+    ArgType, AtomKind, ByteIndex, Compatibility, FileId, Parser, RMSFile, SourceLocation, Word,
+    TOKENS,
+};
+
+/// The namespaces that names live in. A `#const` and a `#define` can have the same name without
+/// referring to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameKind {
+    /// A `#const` name, used in command arguments.
+    Const,
+    /// A `#define` name, used in `if` and `elseif` conditions.
+    Define,
+}
+
+/// A use or declaration of a `#const` or `#define` name.
+#[derive(Debug, Clone, Copy)]
+pub struct Reference {
+    /// Where the name is.
+    pub location: SourceLocation,
+    /// Whether this is the `#const` or `#define` statement itself.
+    pub is_declaration: bool,
+}
+
+/// Can this argument of `command` refer to a `#const`?
+pub fn is_token_argument(command: &str, index: usize) -> bool {
+    match TOKENS
+        .get(command)
+        .and_then(|token| *token.arg_type(index as u8))
+    {
+        Some(ArgType::Word) | Some(ArgType::Filename) => false,
+        // Unknown commands and argument types may still use #consts.
+        _ => true,
+    }
+}
+
+/// Call `callback` with every `#const` or `#define` name in a file.
+fn visit_names<'a>(
+    file: FileId,
+    source: &'a str,
+    mut callback: impl FnMut(NameKind, Word<'a>, bool),
+) {
+    for (atom, _) in Parser::new(file, source) {
+        match atom.kind {
+            AtomKind::Const { name, .. } => callback(NameKind::Const, name, true),
+            AtomKind::Define { name, .. } => callback(NameKind::Define, name, true),
+            AtomKind::Undefine { name, .. } => callback(NameKind::Define, name, false),
+            AtomKind::If { condition, .. } | AtomKind::ElseIf { condition, .. } => {
+                callback(NameKind::Define, condition, false)
+            }
+            AtomKind::PercentChance { chance, .. } => callback(NameKind::Const, chance, false),
+            AtomKind::Command { name, arguments } => {
+                for (index, arg) in arguments.into_iter().enumerate() {
+                    if is_token_argument(name.value, index) {
+                        callback(NameKind::Const, arg, false);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Find the `#const` or `#define` name at the given position in a script.
+pub fn find_name_at<'a>(
+    file: &'a RMSFile<'_>,
+    id: FileId,
+    position: ByteIndex,
+) -> Option<(NameKind, Word<'a>)> {
+    let mut found = None;
+    visit_names(id, file.source(id), |kind, word, _| {
+        // Also match when the cursor is right after the name.
+        let range = word.location.range();
+        if found.is_none() && range.start <= position && position <= range.end {
+            found = Some((kind, word));
+        }
+    });
+    // Skip numbers and `rnd()` calls.
+    found.filter(|(_, word)| is_valid_name(word.value))
+}
+
+/// Find all uses and declarations of a `#const` or `#define` name across all scripts in the map.
+pub fn find_references(file: &RMSFile<'_>, kind: NameKind, name: &str) -> Vec<Reference> {
+    let mut references = vec![];
+    for id in file.script_files() {
+        visit_names(id, file.source(id), |found_kind, word, is_declaration| {
+            if found_kind == kind && word.value == name {
+                references.push(Reference {
+                    location: word.location,
+                    is_declaration,
+                });
+            }
+        });
+    }
+    references
+}
+
+/// Check if a `#const` or `#define` name is already declared in the map or builtin.
+pub fn is_declared(
+    file: &RMSFile<'_>,
+    compatibility: Compatibility,
+    kind: NameKind,
+    name: &str,
+) -> bool {
+    let (state, _) = file.parse_to(file.file_id(), ByteIndex::from(0), compatibility);
+    let is_builtin = match kind {
+        NameKind::Const => state.has_const(name),
+        NameKind::Define => state.may_have_define(name),
+    };
+    is_builtin
+        || find_references(file, kind, name)
+            .iter()
+            .any(|reference| reference.is_declaration)
+}
+
+/// Check if a string can be used as a `#const` or `#define` name.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.parse::<i32>().is_err()
+        && !name.contains(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == ',')
+}
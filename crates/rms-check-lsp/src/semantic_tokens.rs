@@ -0,0 +1,271 @@
+use lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensLegend,
+};
+use rms_check::{
+    AtomKind, ByteIndex, Compatibility, ParseState, Parser, PositionEncoding, RMSFile,
+    SourceLocation, TokenContext, Word, TOKENS,
+};
+use std::ops::Range;
+
+/// The token types used by the server. The index of a type in this list is its ID.
+const TOKEN_TYPES: [SemanticTokenType; 9] = [
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::MACRO,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::OPERATOR,
+];
+
+const SECTION: u32 = 0;
+const COMMAND: u32 = 1;
+const ATTRIBUTE: u32 = 2;
+const KEYWORD: u32 = 3;
+const CONST: u32 = 4;
+const DEFINE: u32 = 5;
+const NUMBER: u32 = 6;
+const COMMENT: u32 = 7;
+const OPERATOR: u32 = 8;
+
+/// The token modifiers used by the server. The index of a modifier in this list is its bit.
+const TOKEN_MODIFIERS: [SemanticTokenModifier; 3] = [
+    SemanticTokenModifier::DECLARATION,
+    SemanticTokenModifier::READONLY,
+    SemanticTokenModifier::DEFAULT_LIBRARY,
+];
+
+const NO_MODIFIERS: u32 = 0;
+const DECLARATION: u32 = 1 << 0;
+const READONLY: u32 = 1 << 1;
+const BUILTIN: u32 = 1 << 2;
+
+/// Get the legend describing the token types and modifiers the server uses.
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: TOKEN_MODIFIERS.to_vec(),
+    }
+}
+
+/// A token with an absolute position, before it is delta-encoded.
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    start: ByteIndex,
+    end: ByteIndex,
+    token_type: u32,
+    modifiers: u32,
+}
+
+/// Classifies the words of a script.
+struct Highlighter<'a> {
+    state: ParseState<'a>,
+    tokens: Vec<Token>,
+}
+
+impl Highlighter<'_> {
+    fn push(&mut self, location: SourceLocation, token_type: u32, modifiers: u32) {
+        self.tokens.push(Token {
+            start: location.start(),
+            end: location.end(),
+            token_type,
+            modifiers,
+        });
+    }
+
+    fn push_command(&mut self, name: &Word<'_>) {
+        fn classify(context: &TokenContext) -> u32 {
+            match context {
+                TokenContext::Flow => KEYWORD,
+                TokenContext::Section | TokenContext::Command(_) => COMMAND,
+                TokenContext::TopLevelAttribute(_) | TokenContext::Attribute(_) => ATTRIBUTE,
+                TokenContext::AnyOf(contexts) => contexts.first().map_or(ATTRIBUTE, classify),
+            }
+        }
+        // Unknown commands are not highlighted, so typos stand out.
+        if let Some(token) = TOKENS.get(name.value) {
+            self.push(name.location, classify(token.context()), NO_MODIFIERS);
+        }
+    }
+
+    /// Highlight a `rnd(min,max)` expression. The bounds can be numbers or `#const` names.
+    fn push_rnd(&mut self, word: &Word<'_>) {
+        let start = word.location.start();
+        let file = word.location.file();
+        let at = |offset: usize| start + offset as isize;
+        self.push(
+            SourceLocation::new(file, start..at("rnd".len())),
+            COMMAND,
+            BUILTIN,
+        );
+        let mut value_start = "rnd".len();
+        let push_bound = |highlighter: &mut Self, value_start: usize, end: usize| {
+            if value_start < end {
+                highlighter.push_value(&Word {
+                    value: &word.value[value_start..end],
+                    location: SourceLocation::new(file, at(value_start)..at(end)),
+                });
+            }
+        };
+        for (offset, c) in word.value.char_indices().skip("rnd".len()) {
+            if matches!(c, '(' | ',' | ')') {
+                push_bound(self, value_start, offset);
+                self.push(
+                    SourceLocation::new(file, at(offset)..at(offset + 1)),
+                    OPERATOR,
+                    NO_MODIFIERS,
+                );
+                value_start = offset + 1;
+            }
+        }
+        push_bound(self, value_start, word.value.len());
+    }
+
+    /// Highlight a word that may be a number, a `rnd()` expression or a `#const` name.
+    fn push_value(&mut self, word: &Word<'_>) {
+        if word.value.parse::<i32>().is_ok() {
+            self.push(word.location, NUMBER, NO_MODIFIERS);
+        } else if word.value.starts_with("rnd(") {
+            self.push_rnd(word);
+        } else if self.state.get_const(word.value).is_some() {
+            self.push(word.location, CONST, READONLY);
+        } else if self.state.is_builtin_const(word.value) {
+            self.push(word.location, CONST, READONLY | BUILTIN);
+        }
+    }
+
+    /// Highlight a word that may be a `#define` name.
+    fn push_define(&mut self, word: &Word<'_>) {
+        if self.state.get_define(word.value).is_some() {
+            self.push(word.location, DEFINE, NO_MODIFIERS);
+        } else if self.state.may_have_define(word.value) {
+            self.push(word.location, DEFINE, BUILTIN);
+        }
+    }
+
+    /// Highlight a comment. Tokens can not span multiple lines, so this adds one token per line.
+    fn push_comment(&mut self, source: &str, location: SourceLocation) {
+        let text = &source[usize::from(location.start())..usize::from(location.end())];
+        let mut offset = 0;
+        for line in text.split('\n') {
+            let trimmed = line.trim_end_matches('\r');
+            if !trimmed.is_empty() {
+                let start = location.start() + offset as isize;
+                self.push(
+                    SourceLocation::new(location.file(), start..start + trimmed.len() as isize),
+                    COMMENT,
+                    NO_MODIFIERS,
+                );
+            }
+            offset += line.len() + 1;
+        }
+    }
+}
+
+/// Delta-encode tokens for the language server protocol.
+fn encode(file: &RMSFile<'_>, encoding: PositionEncoding, tokens: &[Token]) -> Vec<SemanticToken> {
+    let mut data = vec![];
+    let (mut prev_line, mut prev_start) = (0, 0);
+    for token in tokens {
+        let (line, start) = match file.get_location(file.file_id(), token.start, encoding) {
+            Some(position) => position,
+            None => continue,
+        };
+        // Tokens never span lines, so the length is the difference between the columns.
+        let length = match file.get_location(file.file_id(), token.end, encoding) {
+            Some((_, end)) => end - start,
+            None => continue,
+        };
+        let delta_start = if line == prev_line {
+            start - prev_start
+        } else {
+            start
+        };
+        data.push(SemanticToken {
+            delta_line: line - prev_line,
+            delta_start,
+            length,
+            token_type: token.token_type,
+            token_modifiers_bitset: token.modifiers,
+        });
+        prev_line = line;
+        prev_start = start;
+    }
+    data
+}
+
+/// Classify the words in a script, for syntax highlighting. If `range` is given, only tokens
+/// that start inside it are returned.
+pub fn semantic_tokens(
+    file: &RMSFile<'_>,
+    compatibility: Compatibility,
+    encoding: PositionEncoding,
+    range: Option<Range<ByteIndex>>,
+) -> SemanticTokens {
+    let source = file.main_source();
+    // Use the definitions from the whole file, so uses before a `#const` are highlighted too.
+    let (state, _) = file.parse_to(file.file_id(), ByteIndex::from(source.len()), compatibility);
+    let mut highlighter = Highlighter {
+        state,
+        tokens: vec![],
+    };
+
+    for (atom, _) in Parser::new(file.file_id(), source) {
+        if let Some(range) = &range {
+            if atom.location.end() < range.start || atom.location.start() >= range.end {
+                continue;
+            }
+        }
+        match &atom.kind {
+            AtomKind::Section { name } => highlighter.push(name.location, SECTION, NO_MODIFIERS),
+            AtomKind::Command { name, arguments } => {
+                highlighter.push_command(name);
+                for arg in arguments {
+                    highlighter.push_value(arg);
+                }
+            }
+            AtomKind::Const { head, name, value } => {
+                highlighter.push(head.location, KEYWORD, NO_MODIFIERS);
+                highlighter.push(name.location, CONST, DECLARATION | READONLY);
+                if let Some(value) = value {
+                    highlighter.push_value(value);
+                }
+            }
+            AtomKind::Define { head, name } => {
+                highlighter.push(head.location, KEYWORD, NO_MODIFIERS);
+                highlighter.push(name.location, DEFINE, DECLARATION);
+            }
+            AtomKind::Undefine { head, name } => {
+                highlighter.push(head.location, KEYWORD, NO_MODIFIERS);
+                highlighter.push_define(name);
+            }
+            AtomKind::If { head, condition } | AtomKind::ElseIf { head, condition } => {
+                highlighter.push(head.location, KEYWORD, NO_MODIFIERS);
+                highlighter.push_define(condition);
+            }
+            AtomKind::PercentChance { head, chance } => {
+                highlighter.push(head.location, KEYWORD, NO_MODIFIERS);
+                highlighter.push_value(chance);
+            }
+            AtomKind::Else { head }
+            | AtomKind::EndIf { head }
+            | AtomKind::StartRandom { head }
+            | AtomKind::EndRandom { head } => {
+                highlighter.push(head.location, KEYWORD, NO_MODIFIERS)
+            }
+            AtomKind::Comment { .. } => highlighter.push_comment(source, atom.location),
+            AtomKind::OpenBlock { .. } | AtomKind::CloseBlock { .. } | AtomKind::Other { .. } => (),
+        }
+    }
+
+    let mut tokens = highlighter.tokens;
+    if let Some(range) = &range {
+        tokens.retain(|token| token.start >= range.start && token.start < range.end);
+    }
+    SemanticTokens {
+        result_id: None,
+        data: encode(file, encoding, &tokens),
+    }
+}
@@ -0,0 +1,174 @@
+use lsp_types::{DocumentSymbol, Range, SymbolKind};
+use rms_check::{AtomKind, Node, RMSFile, SourceLocation, SyntaxTree, Token};
+
+/// Collects a hierarchy of document symbols from the syntax tree.
+struct SymbolCollector<F>
+where
+    F: Fn(SourceLocation) -> Option<Range>,
+{
+    to_range: F,
+}
+
+impl<F> SymbolCollector<F>
+where
+    F: Fn(SourceLocation) -> Option<Range>,
+{
+    #[allow(deprecated)] // for the `deprecated` field, which must still be initialised.
+    fn symbol(
+        &self,
+        name: impl ToString,
+        detail: Option<String>,
+        kind: SymbolKind,
+        location: SourceLocation,
+        selection: SourceLocation,
+        children: Option<Vec<DocumentSymbol>>,
+    ) -> Option<DocumentSymbol> {
+        Some(DocumentSymbol {
+            name: name.to_string(),
+            detail,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: (self.to_range)(location)?,
+            selection_range: (self.to_range)(selection)?,
+            children,
+        })
+    }
+
+    fn visit_nodes(&self, nodes: &[Node<'_>], symbols: &mut Vec<DocumentSymbol>) {
+        for node in nodes {
+            self.visit_node(node, symbols);
+        }
+    }
+
+    /// Create a symbol for a `#const` or `#define`.
+    fn visit_token(&self, token: &Token<'_>, symbols: &mut Vec<DocumentSymbol>) {
+        let symbol = match &token.atom.kind {
+            AtomKind::Const { name, value, .. } => self.symbol(
+                name.value,
+                value.map(|value| value.value.to_string()),
+                SymbolKind::CONSTANT,
+                token.location(),
+                name.location,
+                None,
+            ),
+            AtomKind::Define { name, .. } => self.symbol(
+                name.value,
+                None,
+                SymbolKind::BOOLEAN,
+                token.location(),
+                name.location,
+                None,
+            ),
+            _ => None,
+        };
+        symbols.extend(symbol);
+    }
+
+    fn visit_node(&self, node: &Node<'_>, symbols: &mut Vec<DocumentSymbol>) {
+        match node {
+            Node::Section(section) => {
+                let mut children = vec![];
+                self.visit_nodes(&section.children, &mut children);
+                symbols.extend(self.symbol(
+                    section.header.text,
+                    None,
+                    SymbolKind::NAMESPACE,
+                    node.location(),
+                    section.header.location(),
+                    Some(children),
+                ));
+            }
+            Node::Command(command) => {
+                let (name, arguments) = match &command.command.atom.kind {
+                    AtomKind::Command { name, arguments } => (name, arguments),
+                    _ => return,
+                };
+                // Only commands with blocks, like `create_land` and `create_connect_all_lands`,
+                // are interesting enough to show.
+                let block = match &command.block {
+                    Some(block) => block,
+                    None => return,
+                };
+                let mut children = vec![];
+                self.visit_nodes(&block.children, &mut children);
+                let kind = match name.value {
+                    "create_object" => SymbolKind::OBJECT,
+                    "create_land" | "create_player_lands" => SymbolKind::STRUCT,
+                    _ if name.value.starts_with("create_connect_") => SymbolKind::INTERFACE,
+                    _ => SymbolKind::FIELD,
+                };
+                let symbol = match arguments.first() {
+                    Some(first) => self.symbol(
+                        first.value,
+                        Some(name.value.to_string()),
+                        kind,
+                        node.location(),
+                        first.location,
+                        Some(children),
+                    ),
+                    None => self.symbol(
+                        name.value,
+                        None,
+                        kind,
+                        node.location(),
+                        name.location,
+                        Some(children),
+                    ),
+                };
+                symbols.extend(symbol);
+            }
+            Node::Block(block) => self.visit_nodes(&block.children, symbols),
+            Node::Condition(condition) => {
+                for branch in &condition.branches {
+                    self.visit_nodes(&branch.children, symbols);
+                }
+            }
+            Node::Random(random) => {
+                let mut children = vec![];
+                self.visit_nodes(&random.children, &mut children);
+                for (index, branch) in random.branches.iter().enumerate() {
+                    let mut branch_children = vec![];
+                    self.visit_nodes(&branch.children, &mut branch_children);
+                    let end = match random.branches.get(index + 1) {
+                        Some(next) => next.head.location().start(),
+                        None => branch
+                            .children
+                            .last()
+                            .map_or(branch.head.location().end(), |child| child.location().end()),
+                    };
+                    let location = branch.head.location();
+                    children.extend(self.symbol(
+                        branch.head.text,
+                        None,
+                        SymbolKind::ENUM_MEMBER,
+                        SourceLocation::new(location.file(), location.start()..end),
+                        location,
+                        Some(branch_children),
+                    ));
+                }
+                symbols.extend(self.symbol(
+                    "start_random",
+                    None,
+                    SymbolKind::ENUM,
+                    node.location(),
+                    random.start.location(),
+                    Some(children),
+                ));
+            }
+            Node::Atom(token) => self.visit_token(token, symbols),
+        }
+    }
+}
+
+/// Get the outline of a script: sections, blocks, `start_random` groups, and declarations.
+pub fn document_symbols(
+    file: &RMSFile<'_>,
+    to_range: impl Fn(SourceLocation) -> Option<Range>,
+) -> Vec<DocumentSymbol> {
+    let tree = SyntaxTree::parse(file.file_id(), file.main_source());
+    let collector = SymbolCollector { to_range };
+    let mut symbols = vec![];
+    collector.visit_nodes(&tree.nodes, &mut symbols);
+    symbols
+}
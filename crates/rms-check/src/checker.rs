@@ -1,23 +1,34 @@
 //! The checker that runs lints and keeps track of warnings.
 
-use crate::diagnostic::Diagnostic;
-use crate::parser::Atom;
-use crate::state::{Compatibility, ParseState};
-use crate::RMSFile;
+use crate::diagnostic::{ByteIndex, Diagnostic, FileId, SourceLocation};
+use crate::parser::{Atom, AtomKind};
+use crate::state::{Compatibility, Nesting, ParseState, ParseStateSnapshot};
+use crate::{PositionEncoding, RMSFile};
 use lazy_static::lazy_static;
+use std::collections::HashSet;
 
 pub trait Lint {
     fn name(&self) -> &'static str;
     fn run_inside_comments(&self) -> bool {
         false
     }
+    /// Copy the lint, including everything it has collected so far.
+    ///
+    /// This lets a [`CheckCache`] resume checking in the middle of a script. Lints that return
+    /// `None` make every check start from the beginning of the script.
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        None
+    }
     fn lint_atom(&mut self, _state: &mut ParseState<'_>, _atom: &Atom<'_>) -> Vec<Diagnostic> {
         Default::default()
     }
+    /// Called once after all atoms in the map were linted, with the final parse state.
+    fn lint_end(&mut self, _state: &mut ParseState<'_>) -> Vec<Diagnostic> {
+        Default::default()
+    }
 }
 
 /// Builtin #define or #const names for AoE2: The Age of Conquerors.
-#[allow(dead_code)] // need to use this at some point?
 const AOC_OPTION_DEFINES: [&str; 8] = [
     "TINY_MAP",
     "SMALL_MAP",
@@ -31,7 +42,6 @@ const AOC_OPTION_DEFINES: [&str; 8] = [
 
 lazy_static! {
     /// Builtin #define or #const names for UserPatch.
-    #[allow(dead_code)] // need to use this at some point?
     static ref UP_OPTION_DEFINES: Vec<String> = {
         let mut list = vec![
             "FIXED_POSITIONS".to_string(),
@@ -67,13 +77,35 @@ lazy_static! {
     };
 }
 
+/// Is `name` a `#define` that the game sets for a lobby, like `LARGE_MAP` or `TEAM1_SIZE2`?
+pub(crate) fn is_lobby_define(name: &str) -> bool {
+    AOC_OPTION_DEFINES.contains(&name) || UP_OPTION_DEFINES.iter().any(|define| define == name)
+}
+
 #[derive(Default)]
 pub struct CheckerBuilder {
     lints: Vec<Box<dyn Lint>>,
     compatibility: Compatibility,
+    is_builtin_map: bool,
 }
 
 impl CheckerBuilder {
+    /// The settings and files that a [`CheckCache`] is only valid for.
+    fn cache_key(&self, rms: &RMSFile<'_>) -> CacheKey {
+        CacheKey {
+            compatibility: self.compatibility,
+            is_builtin_map: self.is_builtin_map,
+            lints: self.lints.iter().map(|lint| lint.name()).collect(),
+            main_file: rms.name(rms.file_id()).to_string(),
+            other_files: rms
+                .script_files()
+                .skip(1)
+                .chain(rms.included_files())
+                .map(|file| (rms.name(file).to_string(), rms.source(file).to_string()))
+                .collect(),
+        }
+    }
+
     pub fn build<'source>(self, rms: &'source RMSFile<'source>) -> Checker<'source> {
         // Default to UP 1.5 if it's a ZR@ map
         let compatibility = if rms.is_zip_rms() && self.compatibility < Compatibility::UserPatch15 {
@@ -82,10 +114,14 @@ impl CheckerBuilder {
             self.compatibility
         };
 
-        let state = ParseState::new(rms, compatibility);
+        let mut state = ParseState::new(rms, compatibility);
+        if self.is_builtin_map {
+            state.set_builtin_map(true);
+        }
         Checker {
             lints: self.lints,
             state,
+            suppressions: vec![],
         }
     }
 
@@ -94,15 +130,242 @@ impl CheckerBuilder {
         self
     }
 
+    /// Get the names of the lints that will run.
+    pub fn lint_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.lints.iter().map(|lint| lint.name())
+    }
+
+    /// Only keep the lints for which `keep` returns true, given their name.
+    pub fn retain_lints(mut self, keep: impl Fn(&str) -> bool) -> Self {
+        self.lints.retain(|lint| keep(lint.name()));
+        self
+    }
+
     pub const fn compatibility(mut self, compatibility: Compatibility) -> Self {
         self.compatibility = compatibility;
         self
     }
+
+    pub const fn builtin_map(mut self, is_builtin_map: bool) -> Self {
+        self.is_builtin_map = is_builtin_map;
+        self
+    }
+}
+
+/// What a suppression comment applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SuppressionScope {
+    /// `rms-check-disable-next-line`: applies to diagnostics starting on the given line.
+    Line(FileId, u32),
+    /// `rms-check-disable`: applies to diagnostics starting after the given offset, up to the end
+    /// of the file.
+    File(FileId, ByteIndex),
+}
+
+/// An inline comment that suppresses diagnostics.
+#[derive(Debug, Clone)]
+struct Suppression {
+    location: SourceLocation,
+    scope: SuppressionScope,
+    /// The lint names to suppress. If empty, all diagnostics are suppressed.
+    codes: Vec<String>,
+    used: bool,
+}
+
+impl Suppression {
+    /// Parse a suppression comment, like `/* rms-check-disable-next-line arg-types */`.
+    fn parse(rms: &RMSFile<'_>, atom: &Atom<'_>) -> Option<Self> {
+        let content = match &atom.kind {
+            AtomKind::Comment { content, .. } => content.trim(),
+            _ => return None,
+        };
+        let file = atom.location.file();
+        let (scope, codes) = if let Some(codes) =
+            content.strip_prefix("rms-check-disable-next-line")
+        {
+            let (line, _) = rms.get_location(file, atom.location.end(), PositionEncoding::Utf8)?;
+            (SuppressionScope::Line(file, line + 1), codes)
+        } else if let Some(codes) = content.strip_prefix("rms-check-disable") {
+            (SuppressionScope::File(file, atom.location.end()), codes)
+        } else {
+            return None;
+        };
+        // Require whitespace between the directive and the lint names.
+        if !codes.is_empty() && !codes.starts_with(char::is_whitespace) {
+            return None;
+        }
+
+        Some(Self {
+            location: atom.location,
+            scope,
+            codes: codes
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|code| !code.is_empty())
+                .map(ToString::to_string)
+                .collect(),
+            used: false,
+        })
+    }
+
+    fn matches(&self, rms: &RMSFile<'_>, diagnostic: &Diagnostic) -> bool {
+        let location = diagnostic.location();
+        let in_scope = match self.scope {
+            SuppressionScope::Line(file, line) => {
+                file == location.file()
+                    && rms
+                        .get_location(file, location.start(), PositionEncoding::Utf8)
+                        .is_some_and(|(start_line, _)| start_line == line)
+            }
+            SuppressionScope::File(file, start) => {
+                file == location.file() && location.start() >= start
+            }
+        };
+        in_scope
+            && (self.codes.is_empty()
+                || diagnostic
+                    .code()
+                    .is_some_and(|code| self.codes.iter().any(|name| name == code)))
+    }
+}
+
+/// The settings and files a [`CheckCache`] was filled with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CacheKey {
+    compatibility: Compatibility,
+    is_builtin_map: bool,
+    lints: Vec<&'static str>,
+    main_file: String,
+    /// The names and sources of the files other than the main script. Edits are only tracked in
+    /// the main script, so any change to these files clears the cache.
+    other_files: Vec<(String, String)>,
+}
+
+/// The checker state right before a top-level atom in the main script.
+struct Checkpoint {
+    /// The start of the atom.
+    offset: ByteIndex,
+    /// The end of the first word of the atom. The parser looks ahead one word to find the end of
+    /// the previous atom, so the checkpoint is only valid while the source up to here is unchanged.
+    guard: ByteIndex,
+    lints: Vec<Box<dyn Lint + Send>>,
+    state: ParseStateSnapshot,
+    suppressions: Vec<Suppression>,
+    /// The files that were already checked, because they were included.
+    checked: HashSet<FileId>,
+    /// The number of diagnostics for everything before `offset`.
+    diagnostics: usize,
+}
+
+/// Checker states saved during a check, so the script can be checked again after an edit without
+/// linting the unchanged part before it.
+///
+/// Use with [`RMSCheck::check_with_cache`](crate::RMSCheck::check_with_cache).
+#[derive(Default)]
+pub struct CheckCache {
+    key: Option<CacheKey>,
+    checkpoints: Vec<Checkpoint>,
+    /// The diagnostics from the last check, before the end-of-file lints ran. Checkpoints refer
+    /// to a prefix of this list.
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl CheckCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget the saved states that are affected by an edit to the main script starting at
+    /// `index`.
+    pub fn invalidate(&mut self, index: ByteIndex) {
+        self.checkpoints
+            .retain(|checkpoint| checkpoint.guard < index);
+    }
+
+    /// Forget all saved states.
+    pub fn clear(&mut self) {
+        self.checkpoints.clear();
+        self.diagnostics.clear();
+    }
+
+    /// Prepare for a check with the given settings, clearing the cache if they or the files other
+    /// than the main script changed.
+    pub(crate) fn prepare(&mut self, builder: &CheckerBuilder, rms: &RMSFile<'_>) {
+        let key = builder.cache_key(rms);
+        if self.key.as_ref() != Some(&key) {
+            self.key = Some(key);
+            self.clear();
+        }
+    }
+
+    /// Restore the last saved state into `checker`. Returns where parsing should resume, the
+    /// files that were already checked, and the diagnostics for everything before that point.
+    pub(crate) fn restore(
+        &mut self,
+        checker: &mut Checker<'_>,
+    ) -> Option<(ByteIndex, HashSet<FileId>, Vec<Diagnostic>)> {
+        let checkpoint = self.checkpoints.last()?;
+        let lints = checkpoint
+            .lints
+            .iter()
+            .map(|lint| lint.clone_lint().map(|lint| lint as Box<dyn Lint>))
+            .collect::<Option<Vec<_>>>()?;
+        checker.state = ParseState::restore(checker.state.rms, &checkpoint.state)?;
+        checker.lints = lints;
+        checker.suppressions = checkpoint.suppressions.clone();
+        let mut diagnostics = std::mem::take(&mut self.diagnostics);
+        diagnostics.truncate(checkpoint.diagnostics);
+        Some((checkpoint.offset, checkpoint.checked.clone(), diagnostics))
+    }
+
+    /// Save the state of `checker` right before `atom`. Returns false if the lints can not be
+    /// copied.
+    pub(crate) fn save(
+        &mut self,
+        checker: &Checker<'_>,
+        atom: &Atom<'_>,
+        checked: &HashSet<FileId>,
+        diagnostics: &[Diagnostic],
+    ) -> bool {
+        let lints = match checker
+            .lints
+            .iter()
+            .map(|lint| lint.clone_lint())
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(lints) => lints,
+            None => return false,
+        };
+        let guard = match &atom.kind {
+            AtomKind::Section { name } | AtomKind::Command { name, .. } => name.location.end(),
+            AtomKind::If { head, .. }
+            | AtomKind::StartRandom { head }
+            | AtomKind::Const { head, .. }
+            | AtomKind::Define { head, .. } => head.location.end(),
+            _ => atom.location.end(),
+        };
+        self.checkpoints.push(Checkpoint {
+            offset: atom.location.start(),
+            guard,
+            lints,
+            state: checker.state.snapshot(),
+            suppressions: checker.suppressions.clone(),
+            checked: checked.clone(),
+            diagnostics: diagnostics.len(),
+        });
+        true
+    }
+
+    /// Keep the diagnostics of a check, for the next check to resume from.
+    pub(crate) fn set_diagnostics(&mut self, diagnostics: &[Diagnostic]) {
+        self.diagnostics = diagnostics.to_vec();
+    }
 }
 
 pub struct Checker<'a> {
     lints: Vec<Box<dyn Lint>>,
     state: ParseState<'a>,
+    suppressions: Vec<Suppression>,
 }
 
 impl<'a> Checker<'a> {
@@ -110,8 +373,30 @@ impl<'a> Checker<'a> {
         CheckerBuilder::default()
     }
 
+    /// Remove diagnostics that are disabled by suppression comments.
+    pub fn filter_suppressed(&mut self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        let rms = self.state.rms;
+        diagnostics
+            .into_iter()
+            .filter(|diagnostic| {
+                let mut suppressed = false;
+                for suppression in self.suppressions.iter_mut() {
+                    if suppression.matches(rms, diagnostic) {
+                        suppression.used = true;
+                        suppressed = true;
+                    }
+                }
+                !suppressed
+            })
+            .collect()
+    }
+
     pub fn write_atom(&mut self, atom: &Atom<'a>) -> Vec<Diagnostic> {
-        let mut state = &mut self.state;
+        if let Some(suppression) = Suppression::parse(self.state.rms, atom) {
+            self.suppressions.push(suppression);
+        }
+
+        let state = &mut self.state;
         let mut warnings = vec![];
         for lint in self.lints.iter_mut() {
             let new_warnings = lint
@@ -126,6 +411,306 @@ impl<'a> Checker<'a> {
             warnings.push(nest_warning);
         }
 
+        self.filter_suppressed(warnings)
+    }
+
+    /// Is the checker outside of any `{ }` block? `if` and `start_random` branches do not count.
+    pub(crate) fn is_at_top_level(&self) -> bool {
+        !self
+            .state
+            .nesting
+            .iter()
+            .any(|nesting| matches!(nesting, Nesting::Brace(_)))
+    }
+
+    /// Get the compatibility in effect at the current position.
+    pub(crate) const fn compatibility(&self) -> Compatibility {
+        self.state.compatibility()
+    }
+
+    /// Run the end-of-file lints.
+    pub fn finish(&mut self) -> Vec<Diagnostic> {
+        let state = &mut self.state;
+        let mut warnings = vec![];
+        for lint in self.lints.iter_mut() {
+            let new_warnings = lint
+                .lint_end(state)
+                .into_iter()
+                .map(move |warning| warning.with_code(lint.name()));
+            warnings.extend(new_warnings);
+        }
+        let mut warnings = self.filter_suppressed(warnings);
+
+        // Codes of lints that are not running can not be checked, so they are not reported.
+        let lints = &self.lints;
+        let is_checked =
+            |code: &&str| *code == "parse" || lints.iter().any(|lint| lint.name() == *code);
+        for suppression in self.suppressions.iter().filter(|s| !s.used) {
+            let codes: Vec<&str> = suppression
+                .codes
+                .iter()
+                .map(String::as_str)
+                .filter(is_checked)
+                .collect();
+            let message = if suppression.codes.is_empty() {
+                "This suppression comment does not suppress anything".to_string()
+            } else if codes.is_empty() {
+                continue;
+            } else {
+                format!(
+                    "This suppression comment does not suppress anything: no `{}` diagnostics here",
+                    codes.join("`, `")
+                )
+            };
+            warnings.push(
+                Diagnostic::warning(suppression.location, message).with_code("unused-suppression"),
+            );
+        }
+
         warnings
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RMSCheck, Severity};
+
+    /// Reports the last definition of `A` at the end of the file.
+    struct LastDefinitionLint;
+
+    impl Lint for LastDefinitionLint {
+        fn name(&self) -> &'static str {
+            "last-definition"
+        }
+        fn lint_end(&mut self, state: &mut ParseState<'_>) -> Vec<Diagnostic> {
+            state
+                .get_const("A")
+                .map(|definition| Diagnostic::hint(definition.location(), "Last definition of A"))
+                .into_iter()
+                .collect()
+        }
+    }
+
+    #[test]
+    fn lint_end() {
+        let file = RMSFile::from_string("lint_end.rms", "#const A 1\n#const A 2\n");
+        let result = RMSCheck::new()
+            .with_lint(Box::new(LastDefinitionLint))
+            .check(&file);
+        let mut warnings = result.iter();
+        let first = warnings.next().unwrap();
+        assert_eq!(first.severity(), Severity::Hint);
+        assert_eq!(first.code(), Some("last-definition"));
+        assert_eq!(usize::from(first.location().start()), 11);
+        assert!(warnings.next().is_none());
+    }
+
+    #[test]
+    fn suppressions() {
+        use crate::lints::ArgTypesLint;
+
+        let file = RMSFile::from_string(
+            "suppressions.rms",
+            "<OBJECTS_GENERATION>
+/* rms-check-disable-next-line arg-types */
+create_object NOT_AN_OBJECT { }
+create_object ALSO_NOT_AN_OBJECT { }
+/* rms-check-disable-next-line compatibility */
+create_object GOLD { }
+/* rms-check-disable-next-line arg-types, compatibility, parse */
+create_object GOLD { }
+",
+        );
+        let result = RMSCheck::new()
+            .with_lint(Box::new(ArgTypesLint::new()))
+            .check(&file);
+        let mut warnings = result.iter();
+        let first = warnings.next().unwrap();
+        assert_eq!(first.code(), Some("arg-types"));
+        assert_eq!(
+            file.get_location(
+                file.file_id(),
+                first.location().start(),
+                PositionEncoding::Utf8
+            ),
+            Some((3, 14))
+        );
+        let second = warnings.next().unwrap();
+        assert_eq!(second.severity(), Severity::Warning);
+        assert_eq!(second.code(), Some("unused-suppression"));
+        assert_eq!(
+            second.message(),
+            "This suppression comment does not suppress anything: no `arg-types`, `parse` diagnostics here"
+        );
+        assert_eq!(
+            file.get_location(
+                file.file_id(),
+                second.location().start(),
+                PositionEncoding::Utf8
+            ),
+            Some((6, 0))
+        );
+        // The `compatibility` lint is not running, so its suppression is not reported.
+        assert!(warnings.next().is_none());
+    }
+
+    #[test]
+    fn file_suppressions() {
+        use crate::lints::ArgTypesLint;
+
+        let file = RMSFile::from_string(
+            "file_suppressions.rms",
+            "/* rms-check-disable arg-types, compatibility */
+<OBJECTS_GENERATION>
+create_object NOT_AN_OBJECT { }
+create_object ALSO_NOT_AN_OBJECT { }
+",
+        );
+        let result = RMSCheck::new()
+            .with_lint(Box::new(ArgTypesLint::new()))
+            .check(&file);
+        assert!(result.iter().next().is_none());
+    }
+
+    #[test]
+    fn file_suppressions_from_here_on() {
+        use crate::lints::UnusedDeclarationLint;
+
+        let file = RMSFile::from_string(
+            "file_suppressions.rms",
+            "#const UNUSED_ABOVE 1
+/* rms-check-disable unused-declaration */
+#const UNUSED_BELOW 2
+",
+        );
+        let result = RMSCheck::new()
+            .with_lint(Box::new(UnusedDeclarationLint::new()))
+            .check(&file);
+        let mut warnings = result.iter();
+        let first = warnings.next().unwrap();
+        assert_eq!(first.code(), Some("unused-declaration"));
+        assert_eq!(first.message(), "`#const UNUSED_ABOVE` is never used");
+        assert!(warnings.next().is_none());
+    }
+
+    #[test]
+    fn check_with_cache() {
+        use crate::CheckCache;
+
+        let mut source = "#const UNUSED 1\n#const MY_GOLD 66\n<OBJECTS_GENERATION>\n".to_string();
+        for index in 0..200 {
+            source.push_str(&format!(
+                "create_object MY_GOLD {{ number_of_objects {} }}\n",
+                index
+            ));
+        }
+        source.push_str("create_object NOT_AN_OBJECT { }\n");
+        let edit_at = source.len() / 2;
+        let edit_at = edit_at + source[edit_at..].find("create_object").unwrap();
+
+        let mut cache = CheckCache::new();
+        let file = RMSFile::from_string("cache.rms", source.clone());
+        RMSCheck::default().check_with_cache(&file, &mut cache);
+        assert!(cache.checkpoints.len() > 1);
+
+        source.insert_str(
+            edit_at,
+            "create_object MISSPELLED_GOLD { }\nif UNKNOWN endif\n",
+        );
+        cache.invalidate(ByteIndex::from(edit_at));
+        assert!(!cache.checkpoints.is_empty());
+        assert!(cache
+            .checkpoints
+            .iter()
+            .all(|checkpoint| usize::from(checkpoint.offset) < edit_at));
+
+        let file = RMSFile::from_string("cache.rms", source);
+        let cached = RMSCheck::default().check_with_cache(&file, &mut cache);
+        let full = RMSCheck::default().check(&file);
+        let cached: Vec<_> = cached.iter().map(|d| format!("{:?}", d)).collect();
+        let full: Vec<_> = full.iter().map(|d| format!("{:?}", d)).collect();
+        assert!(full.len() > 1);
+        assert_eq!(cached, full);
+    }
+
+    #[test]
+    fn check_with_cache_resumes_at_sections() {
+        use crate::CheckCache;
+        use std::borrow::Cow;
+
+        let shared = "#const SHARED_GOLD 66\n";
+        let main = "\
+<PLAYER_SETUP>
+random_placement
+#include shared.inc
+if TINY_MAP
+  #const MY_GOLD 66
+else
+  #const MY_GOLD 67
+endif
+#define UNUSED_DEFINE
+<LAND_GENERATION>
+base_terrain GRASS
+start_random
+percent_chance 50 #define MAYBE
+end_random
+<OBJECTS_GENERATION>
+create_object SHARED_GOLD { }
+create_object MY_GOLD { }
+"
+        .to_string();
+        let zip = |main: &str, shared: &str| {
+            RMSFile::from_zip_rms_files(vec![
+                ("main.rms".to_string(), Cow::Owned(main.to_string())),
+                ("shared.inc".to_string(), Cow::Owned(shared.to_string())),
+            ])
+        };
+        let debug = |result: crate::RMSCheckResult| {
+            result
+                .iter()
+                .map(|d| format!("{:?}", d))
+                .collect::<Vec<_>>()
+        };
+
+        let mut cache = CheckCache::new();
+        RMSCheck::default().check_with_cache(&zip(&main, shared), &mut cache);
+        let sections: Vec<_> = main.match_indices('<').map(|(index, _)| index).collect();
+        let offsets: Vec<_> = cache
+            .checkpoints
+            .iter()
+            .map(|checkpoint| usize::from(checkpoint.offset))
+            .collect();
+        assert_eq!(offsets, sections);
+
+        let mut main = main;
+        let edit_at = main.find("create_object MY_GOLD").unwrap();
+        main.insert_str(edit_at, "if MAYBE create_object UNKNOWN { } endif\n");
+        cache.invalidate(ByteIndex::from(edit_at));
+        assert_eq!(cache.checkpoints.len(), 3);
+
+        let file = zip(&main, shared);
+        let mut checker = Checker::builder().build(&file);
+        let (resume, checked, diagnostics) = cache.restore(&mut checker).unwrap();
+        assert_eq!(resume, ByteIndex::from(sections[2]));
+        assert_eq!(checked.len(), 2);
+        assert!(checker.state.get_const("MY_GOLD").is_some());
+        assert!(checker.state.may_have_define("MAYBE"));
+        cache.set_diagnostics(&diagnostics);
+
+        let cached = RMSCheck::default().check_with_cache(&file, &mut cache);
+        let full = RMSCheck::default().check(&file);
+        let cached = debug(cached);
+        assert!(cached.iter().any(|d| d.contains("UNKNOWN")));
+        assert!(cached.iter().any(|d| d.contains("UNUSED_DEFINE")));
+        assert_eq!(cached, debug(full));
+
+        // Changing another file in the map clears the cache.
+        let file = zip(&main, "#const OTHER_GOLD 66\n");
+        let cached = RMSCheck::default().check_with_cache(&file, &mut cache);
+        let full = RMSCheck::default().check(&file);
+        let cached = debug(cached);
+        assert!(cached.iter().any(|d| d.contains("SHARED_GOLD")));
+        assert_eq!(cached, debug(full));
+    }
+}
@@ -0,0 +1,273 @@
+//! Project configuration from `rms-check.toml` files.
+//!
+//! ```toml
+//! # The default compatibility for scripts that do not have a `Compatibility:` header.
+//! compatibility = "UserPatch 1.5"
+//!
+//! [lints]
+//! # Disable a lint.
+//! unused-declaration = "off"
+//! # Change the severity of a lint: "error", "warning" or "hint".
+//! percent-chance = "error"
+//! ```
+
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::state::Compatibility;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The name of the configuration file.
+pub const CONFIG_FILE_NAME: &str = "rms-check.toml";
+
+/// How a lint should be run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    /// Do not run the lint.
+    Off,
+    /// Run the lint, with the severities it chooses itself.
+    On,
+    /// Run the lint, and report all its diagnostics as errors.
+    Error,
+    /// Run the lint, and report all its diagnostics as warnings.
+    Warning,
+    /// Run the lint, and report all its diagnostics as hints.
+    Hint,
+}
+
+impl std::str::FromStr for LintLevel {
+    type Err = ();
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "off" => Ok(LintLevel::Off),
+            "on" => Ok(LintLevel::On),
+            "error" => Ok(LintLevel::Error),
+            "warning" => Ok(LintLevel::Warning),
+            "hint" => Ok(LintLevel::Hint),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    compatibility: Option<String>,
+    lints: HashMap<String, String>,
+}
+
+/// Configuration for a map project.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    compatibility: Option<Compatibility>,
+    lints: HashMap<String, LintLevel>,
+}
+
+fn invalid_data(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// The diagnostic codes that can be configured in `[lints]` besides the names of the lints: parse
+/// errors and the warning for unused suppression comments.
+const BUILTIN_CODES: [&str; 2] = ["parse", "unused-suppression"];
+
+impl Config {
+    /// Create an empty configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a configuration from TOML source code.
+    pub fn from_toml(source: &str) -> io::Result<Self> {
+        let raw: RawConfig = toml::from_str(source).map_err(invalid_data)?;
+        let compatibility = match raw.compatibility {
+            Some(name) => Some(
+                name.parse()
+                    .map_err(|_| invalid_data(format!("unknown compatibility `{}`", name)))?,
+            ),
+            None => None,
+        };
+        let lints = raw
+            .lints
+            .into_iter()
+            .map(|(name, level)| {
+                match level.parse() {
+                    Ok(level) => Ok((name, level)),
+                    Err(()) => Err(invalid_data(format!(
+                        "unknown level `{}` for lint `{}`, expected one of \"off\", \"on\", \"error\", \"warning\", \"hint\"",
+                        level, name
+                    ))),
+                }
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            compatibility,
+            lints,
+        })
+    }
+
+    /// Load a configuration file.
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let source = std::fs::read_to_string(path.as_ref())?;
+        Self::from_toml(&source)
+            .map_err(|err| invalid_data(format!("{}: {}", path.as_ref().display(), err)))
+    }
+
+    /// Find the configuration file that applies to a script, by looking for an `rms-check.toml`
+    /// file in the directory containing the script and then in its parent directories.
+    pub fn find_path(script: impl AsRef<Path>) -> Option<PathBuf> {
+        let script = script.as_ref();
+        let script = script
+            .canonicalize()
+            .unwrap_or_else(|_| script.to_path_buf());
+        script
+            .ancestors()
+            .skip(1)
+            .map(|dir| dir.join(CONFIG_FILE_NAME))
+            .find(|path| path.is_file())
+    }
+
+    /// Load the configuration file that applies to a script, or an empty configuration if there
+    /// is none. See [`find_path`](Self::find_path).
+    pub fn for_script(script: impl AsRef<Path>) -> io::Result<Self> {
+        match Self::find_path(script) {
+            Some(path) => Self::from_path(path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Set the default compatibility.
+    pub const fn compatibility(mut self, compatibility: Compatibility) -> Self {
+        self.compatibility = Some(compatibility);
+        self
+    }
+
+    /// Set how a lint should be run.
+    pub fn lint(mut self, name: impl ToString, level: LintLevel) -> Self {
+        self.lints.insert(name.to_string(), level);
+        self
+    }
+
+    /// Get the default compatibility, if the configuration sets one.
+    pub const fn get_compatibility(&self) -> Option<Compatibility> {
+        self.compatibility
+    }
+
+    /// Get how a lint should be run.
+    pub fn get_lint(&self, name: &str) -> LintLevel {
+        self.lints.get(name).copied().unwrap_or(LintLevel::On)
+    }
+
+    /// Get the configured lint names that are not in `lint_names`, the names of the lints that a
+    /// checker runs. Parse errors and unused suppression comments can always be configured.
+    pub fn unknown_lints<'a>(&self, lint_names: impl IntoIterator<Item = &'a str>) -> Vec<&str> {
+        let known = lint_names
+            .into_iter()
+            .chain(BUILTIN_CODES)
+            .collect::<Vec<_>>();
+        let mut unknown = self
+            .lints
+            .keys()
+            .map(String::as_str)
+            .filter(|name| !known.contains(name))
+            .collect::<Vec<_>>();
+        unknown.sort_unstable();
+        unknown
+    }
+
+    /// Should a lint run?
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.get_lint(name) != LintLevel::Off
+    }
+
+    /// Apply the configured lint levels to a diagnostic. Returns `None` if the diagnostic comes
+    /// from a disabled lint.
+    pub(crate) fn apply(&self, diagnostic: Diagnostic) -> Option<Diagnostic> {
+        let level = match diagnostic.code() {
+            Some(code) => self.get_lint(code),
+            None => LintLevel::On,
+        };
+        match level {
+            LintLevel::Off => None,
+            LintLevel::On => Some(diagnostic),
+            LintLevel::Error => Some(diagnostic.with_severity(Severity::Error)),
+            LintLevel::Warning => Some(diagnostic.with_severity(Severity::Warning)),
+            LintLevel::Hint => Some(diagnostic.with_severity(Severity::Hint)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Lint, RMSCheck, RMSFile};
+
+    #[test]
+    fn parse() {
+        let config = Config::from_toml(
+            r#"
+            compatibility = "UserPatch 1.5"
+            [lints]
+            arg-types = "off"
+            percent-chance = "error"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.get_compatibility(), Some(Compatibility::UserPatch15));
+        assert_eq!(config.get_lint("arg-types"), LintLevel::Off);
+        assert_eq!(config.get_lint("percent-chance"), LintLevel::Error);
+        assert_eq!(config.get_lint("compatibility"), LintLevel::On);
+
+        assert!(Config::from_toml("compatibility = \"AoE3\"").is_err());
+        assert!(Config::from_toml("[lints]\narg-types = \"loud\"").is_err());
+        assert!(Config::from_toml("unknown = 1").is_err());
+    }
+
+    #[test]
+    fn unknown_lints() {
+        let config = Config::from_toml(
+            r#"
+            [lints]
+            arg-type = "off"
+            my-lint = "error"
+            parse = "warning"
+            unused-suppression = "off"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.unknown_lints(RMSCheck::default().lint_names()),
+            vec!["arg-type", "my-lint"]
+        );
+
+        // Lints that a library user adds are known too.
+        struct MyLint;
+        impl Lint for MyLint {
+            fn name(&self) -> &'static str {
+                "my-lint"
+            }
+        }
+        let checker = RMSCheck::default().with_lint(Box::new(MyLint));
+        assert_eq!(config.unknown_lints(checker.lint_names()), vec!["arg-type"]);
+    }
+
+    #[test]
+    fn apply() {
+        let file = RMSFile::from_string(
+            "config.rms",
+            "#const UNUSED 1\nstart_random percent_chance 50 percent_chance 60 end_random\n",
+        );
+        let config = Config::new()
+            .lint("unused-declaration", LintLevel::Off)
+            .lint("percent-chance", LintLevel::Error);
+        let result = RMSCheck::default().with_config(config).check(&file);
+        let mut warnings = result.iter();
+        let first = warnings.next().unwrap();
+        assert_eq!(first.code(), Some("percent-chance"));
+        assert_eq!(first.severity(), Severity::Error);
+        assert!(warnings.next().is_none());
+    }
+}
@@ -2,7 +2,7 @@
 use std::fmt::Display;
 use std::ops::Range;
 
-///
+/// Identifies a file inside an [`RMSFile`](crate::RMSFile).
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FileId(u32);
 
@@ -11,7 +11,6 @@ impl FileId {
         Self(id)
     }
 
-    #[allow(unused)]
     pub(crate) const fn to_u32(self) -> u32 {
         self.0
     }
@@ -103,12 +102,36 @@ pub enum Severity {
     Hint,
 }
 
+/// A single text replacement that is part of a [`Fix`].
+#[derive(Debug, Clone, Hash)]
+pub struct Edit {
+    location: SourceLocation,
+    replacement: String,
+}
+
+impl Edit {
+    pub fn new(location: SourceLocation, replacement: impl Display) -> Self {
+        Self {
+            location,
+            replacement: replacement.to_string(),
+        }
+    }
+
+    pub fn location(&self) -> SourceLocation {
+        self.location
+    }
+
+    pub fn replacement(&self) -> &str {
+        &self.replacement
+    }
+}
+
 /// A source code replacement that may fix a problem.
 #[derive(Debug, Clone, Hash)]
 pub struct Fix {
     message: String,
     location: SourceLocation,
-    replacement: Option<String>,
+    edits: Vec<Edit>,
 }
 
 impl Fix {
@@ -116,15 +139,20 @@ impl Fix {
         Self {
             location,
             message: message.to_string(),
-            replacement: None,
+            edits: vec![],
         }
     }
 
+    /// Replace the code at the location of the fix.
     pub fn replace(self, replacement: impl Display) -> Self {
-        Self {
-            replacement: Some(replacement.to_string()),
-            ..self
-        }
+        let location = self.location;
+        self.edit(location, replacement)
+    }
+
+    /// Add an edit to the fix. A fix can make several edits, which must not overlap.
+    pub fn edit(mut self, location: SourceLocation, replacement: impl Display) -> Self {
+        self.edits.push(Edit::new(location, replacement));
+        self
     }
 
     pub fn message(&self) -> &str {
@@ -135,8 +163,17 @@ impl Fix {
         self.location
     }
 
+    /// Get the replacement text, if the fix only replaces the code at its own location.
     pub fn replacement(&self) -> Option<&str> {
-        self.replacement.as_deref()
+        match self.edits.as_slice() {
+            [edit] if edit.location == self.location => Some(&edit.replacement),
+            _ => None,
+        }
+    }
+
+    /// Get the edits that this fix makes. This is empty if the fix must be done by hand.
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
     }
 }
 
@@ -199,6 +236,10 @@ impl Diagnostic {
         Self::new(Severity::Warning, location, message)
     }
 
+    pub fn hint(location: SourceLocation, message: impl Display) -> Self {
+        Self::new(Severity::Hint, location, message)
+    }
+
     pub fn with_code(self, code: impl ToString) -> Self {
         Self {
             code: Some(code.to_string()),
@@ -206,6 +247,10 @@ impl Diagnostic {
         }
     }
 
+    pub fn with_severity(self, severity: Severity) -> Self {
+        Self { severity, ..self }
+    }
+
     pub fn add_labels(mut self, labels: impl IntoIterator<Item = Label>) -> Self {
         self.labels.extend(labels);
         self
@@ -1,10 +1,11 @@
 //! A code formatter for AoE2 random map scripts.
 
 use crate::diagnostic::FileId;
-use crate::parser::{Atom, AtomKind, Parser};
+use crate::parser::{Atom, AtomKind};
+use crate::syntax_tree::{
+    BlockNode, CommandNode, ConditionNode, Node, RandomNode, SyntaxTree, Token, Trivia,
+};
 use crate::tokenizer::Word;
-use itertools::Itertools;
-use std::iter::Peekable;
 
 /// Keeps track of alignment widths for commands/attributes.
 #[derive(Debug, Default, Clone, Copy)]
@@ -87,9 +88,8 @@ impl FormatOptions {
     }
 
     pub fn format(self, code: &str) -> String {
-        let file_id = FileId::new(0);
-        let script = Parser::new(file_id, code).map(|(atom, _errors)| atom);
-        Formatter::new(self, code).format(script)
+        let tree = SyntaxTree::parse(FileId::new(0), code);
+        Formatter::new(self, code).format(&tree)
     }
 }
 
@@ -97,6 +97,8 @@ impl FormatOptions {
 pub struct Formatter<'file> {
     options: FormatOptions,
     source: &'file str,
+    /// All atoms in the script, including comments, in source order.
+    atoms: Vec<Atom<'file>>,
     /// The current indentation level.
     indent: u32,
     /// Whether this line still needs indentation. A line needs indentation if no text has been
@@ -126,6 +128,14 @@ impl<'file> Formatter<'file> {
         self.prev.as_ref().map(|atom| &atom.kind)
     }
 
+    /// Get the Kind of the atom that follows `atom` in the source, if any exist.
+    fn next_kind(&self, atom: &Atom<'_>) -> Option<&AtomKind<'file>> {
+        let index = self
+            .atoms
+            .partition_point(|other| other.location.start() <= atom.location.start());
+        self.atoms.get(index).map(|atom| &atom.kind)
+    }
+
     /// Write a newline (Windows-style).
     fn newline(&mut self) {
         self.result.push_str("\r\n");
@@ -206,49 +216,54 @@ impl<'file> Formatter<'file> {
         self.newline();
     }
 
-    /// Write a command block. This reads atoms from the iterator until the end of the block, and
-    /// writes both the command and any attributes it may contain.
-    fn block<I>(&mut self, mut input: Peekable<I>) -> Peekable<I>
-    where
-        I: Iterator<Item = Atom<'file>>,
-    {
-        let is_end = |atom: &Atom<'_>| matches!(atom.kind, AtomKind::CloseBlock { .. });
+    /// Measure the commands in a block, so their arguments can be aligned.
+    fn measure(&self, nodes: &[Node<'_>], indent: usize, width: &mut Width) {
+        for node in nodes {
+            match node {
+                Node::Command(CommandNode { command, .. }) => {
+                    if let AtomKind::Command { name, arguments } = command.kind() {
+                        width.command_width = width
+                            .command_width
+                            .max(name.value.len() + indent * self.options.tab_size as usize);
+                        width.arg_width = width
+                            .arg_width
+                            .max(arguments.first().map(|word| word.value.len()).unwrap_or(0));
+                    }
+                }
+                Node::Condition(condition) => {
+                    for branch in &condition.branches {
+                        self.measure(&branch.children, indent + 1, width);
+                    }
+                }
+                Node::Random(random) => {
+                    self.measure(&random.children, indent, width);
+                    for branch in &random.branches {
+                        self.measure(&branch.children, indent, width);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Write a command block, including any attributes it may contain.
+    fn block(&mut self, block: &BlockNode<'file>) {
+        self.comments(&block.open);
+        self.before_atom(&block.open.atom);
+        self.prev = Some(block.open.atom.clone());
 
         self.inside_block += 1;
 
-        let mut commands = vec![];
         let mut width = Width::default();
-        let mut indent = 0;
-        for atom in input.by_ref().take_while(|atom| !is_end(atom)) {
-            width = match &atom.kind {
-                AtomKind::Command { name, arguments } => Width {
-                    command_width: width
-                        .command_width
-                        .max(name.value.len() + indent * self.options.tab_size as usize),
-                    arg_width: width
-                        .arg_width
-                        .max(arguments.get(0).map(|word| word.value.len()).unwrap_or(0)),
-                },
-                AtomKind::If { .. } => {
-                    indent += 1;
-                    width
-                }
-                AtomKind::EndIf { .. } => {
-                    indent -= 1;
-                    width
-                }
-                _ => width,
-            };
-            commands.push(atom);
-        }
+        self.measure(&block.children, 0, &mut width);
         self.text("{");
         self.newline();
         self.indent += 1;
 
         self.widths.push(width);
-        let mut sub_input = commands.into_iter().peekable();
-        while let Some(atom) = sub_input.next() {
-            sub_input = self.write_atom(atom, sub_input);
+        self.nodes(&block.children);
+        if let Some(close) = &block.close {
+            self.comments(close);
         }
         self.widths.pop();
 
@@ -261,16 +276,20 @@ impl<'file> Formatter<'file> {
         self.indent -= 1;
         self.text("}");
         self.newline();
-
-        input
     }
 
-    fn condition<I>(&mut self, cond: &Word<'_>, mut input: Peekable<I>) -> Peekable<I>
-    where
-        I: Iterator<Item = Atom<'file>>,
-    {
-        self.text("if ");
-        self.text(cond.value);
+    fn condition(&mut self, condition: &ConditionNode<'file>) {
+        let (first, others) = condition
+            .branches
+            .split_first()
+            .expect("an if statement always has a first branch");
+        self.comments(&first.head);
+        self.before_atom(&first.head.atom);
+        self.prev = Some(first.head.atom.clone());
+        if let AtomKind::If { condition, .. } = first.head.kind() {
+            self.text("if ");
+            self.text(condition.value);
+        }
         self.newline();
         self.indent += 1;
 
@@ -285,42 +304,32 @@ impl<'file> Formatter<'file> {
             arg_width,
         });
 
-        let mut depth = 1;
-        let mut sub_input = input
-            .by_ref()
-            .peeking_take_while(|atom| {
-                match atom.kind {
-                    AtomKind::If { .. } => depth += 1,
-                    AtomKind::EndIf { .. } => depth -= 1,
-                    _ => (),
-                }
+        self.nodes(&first.children);
+        for branch in others {
+            self.comments(&branch.head);
+            self.indent -= 1;
+            self.atom(&branch.head.atom);
+            self.indent += 1;
+            self.nodes(&branch.children);
+        }
 
-                // end on an endif atom at depth 0, else continue
-                depth > 0 || !matches!(atom.kind, AtomKind::EndIf { .. })
-            })
-            .peekable();
-
-        while let Some(atom) = sub_input.next() {
-            match &atom.kind {
-                AtomKind::ElseIf { .. } | AtomKind::Else { .. } => {
-                    self.indent -= 1;
-                    sub_input = self.write_atom(atom, sub_input);
-                    self.indent += 1;
-                }
-                _ => {
-                    sub_input = self.write_atom(atom, sub_input);
-                }
+        let end = match &condition.end {
+            Some(end) => end,
+            None => {
+                self.widths.pop();
+                self.indent -= 1;
+                return;
             }
-        }
+        };
+        self.comments(end);
 
         self.widths.pop();
 
         self.indent -= 1;
-        let endif = input.next().unwrap();
-        let mut input = self.write_atom(endif, input);
+        self.atom(&end.atom);
 
         if self.inside_block == 0 {
-            let next_kind = input.peek().map(|atom| &atom.kind);
+            let next_kind = self.next_kind(&end.atom);
             if let Some(AtomKind::OpenBlock { .. }) = next_kind {
                 // No newline before an open brace:
                 // ```
@@ -334,14 +343,13 @@ impl<'file> Formatter<'file> {
                 self.newline();
             }
         }
-
-        input
     }
 
-    fn random<I>(&mut self, mut input: Peekable<I>) -> Peekable<I>
-    where
-        I: Iterator<Item = Atom<'file>>,
-    {
+    fn random(&mut self, random: &RandomNode<'file>) {
+        self.comments(&random.start);
+        self.before_atom(&random.start.atom);
+        self.prev = Some(random.start.atom.clone());
+
         self.text("start_random");
         self.newline();
         self.indent += 1;
@@ -350,76 +358,71 @@ impl<'file> Formatter<'file> {
         // does not over-indent.
         self.widths.push(Width::default());
 
-        let mut null_branch = vec![];
-        let mut branches = vec![];
-        let mut depth = 1;
-        for atom in input.by_ref() {
-            match atom.kind {
-                AtomKind::PercentChance { chance, .. } if depth == 1 => {
-                    branches.push((chance, vec![]));
-                    continue;
-                }
-                AtomKind::StartRandom { .. } => {
-                    depth += 1;
-                }
-                AtomKind::EndRandom { .. } => {
-                    depth -= 1;
-                    if depth == 0 {
-                        break;
-                    }
-                }
-                _ => (),
-            };
-
-            if branches.is_empty() {
-                null_branch.push(atom);
-            } else {
-                branches.last_mut().unwrap().1.push(atom);
+        // Comments before a `percent_chance` or the `end_random` belong to the preceding branch.
+        let trailing_comments = |index: usize| -> Vec<&Atom<'file>> {
+            match random.branches.get(index) {
+                Some(branch) => branch.head.comments().collect(),
+                None => random.end.iter().flat_map(|end| end.comments()).collect(),
             }
+        };
+
+        self.nodes(&random.children);
+        for comment in trailing_comments(0) {
+            self.atom(comment);
         }
 
-        let has_simple_branches = branches.iter().all(|(_, stmts)| {
-            if stmts.len() > 1 {
-                return false;
-            }
-            if stmts.is_empty() {
-                return true;
-            }
-            matches!(
-                stmts[0].kind,
-                AtomKind::Define { .. }
-                    | AtomKind::Const { .. }
-                    | AtomKind::Undefine { .. }
-                    | AtomKind::Command { .. }
-            )
-        });
+        let has_simple_branches =
+            random.branches.iter().enumerate().all(|(index, branch)| {
+                match (&branch.children[..], trailing_comments(index + 1).len()) {
+                    ([], 0) => true,
+                    ([Node::Atom(token)], 0) if token.comments().next().is_none() => matches!(
+                        token.kind(),
+                        AtomKind::Define { .. }
+                            | AtomKind::Const { .. }
+                            | AtomKind::Undefine { .. }
+                    ),
+                    ([Node::Command(command)], 0) => {
+                        command.block.is_none() && command.command.comments().next().is_none()
+                    }
+                    _ => false,
+                }
+            });
 
         if has_simple_branches {
-            let longest = branches.iter().fold(0, |acc, (chance, _)| {
-                acc.max(format!("percent_chance {}", chance.value).len())
-            });
-            for (chance, mut branch) in branches {
-                let mut chance = format!("percent_chance {}", chance.value);
+            let chances = random
+                .branches
+                .iter()
+                .map(|branch| match branch.head.kind() {
+                    AtomKind::PercentChance { chance, .. } => {
+                        format!("percent_chance {}", chance.value)
+                    }
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>();
+            let longest = chances.iter().map(String::len).max().unwrap_or(0);
+            for (mut chance, branch) in chances.into_iter().zip(&random.branches) {
                 while chance.len() < longest {
                     chance.push(' ');
                 }
                 self.text(&chance);
-                if !branch.is_empty() {
+                if !branch.children.is_empty() {
                     self.text(" ");
-                    input = self.write_atom(branch.remove(0), input);
+                    self.nodes(&branch.children);
                 } else {
                     self.newline();
                 }
             }
         } else {
-            for (chance, branch) in branches {
-                self.text(&format!("percent_chance {}", chance.value));
+            for (index, branch) in random.branches.iter().enumerate() {
+                if let AtomKind::PercentChance { chance, .. } = branch.head.kind() {
+                    self.text(&format!("percent_chance {}", chance.value));
+                }
                 self.newline();
                 self.indent += 1;
 
-                let mut sub_input = branch.into_iter().peekable();
-                while let Some(atom) = sub_input.next() {
-                    sub_input = self.write_atom(atom, sub_input);
+                self.nodes(&branch.children);
+                for comment in trailing_comments(index + 1) {
+                    self.atom(comment);
                 }
 
                 self.indent -= 1;
@@ -431,8 +434,6 @@ impl<'file> Formatter<'file> {
         self.indent -= 1;
         self.text("end_random");
         self.newline();
-
-        input
     }
 
     /// Write a comment. Multiline comments are formatted Java-style, with a * at the start of each
@@ -520,10 +521,8 @@ impl<'file> Formatter<'file> {
         }
     }
 
-    fn write_atom<I>(&mut self, atom: Atom<'file>, mut input: Peekable<I>) -> Peekable<I>
-    where
-        I: Iterator<Item = Atom<'file>>,
-    {
+    /// Write the whitespace that goes between the previous atom and `atom`.
+    fn before_atom(&mut self, atom: &Atom<'_>) {
         match (self.prev_kind(), &atom.kind) {
             // Add an additional newline after each }
             (Some(AtomKind::CloseBlock { .. }), _) => self.newline(),
@@ -538,13 +537,13 @@ impl<'file> Formatter<'file> {
             // - Maintain padding lines.
             // - Do not add linebreak before comments at the end of a line
 
-            if self.has_padding_line(prev, &atom) {
+            if self.has_padding_line(prev, atom) {
                 // A padding line may already have been added by the formatter for another reason,
                 // like after top-level `endif`s. Don't add another in that case.
                 if !self.result.ends_with("\r\n\r\n") {
                     self.newline();
                 }
-            } else if self.should_comment_be_on_same_line(prev, &atom) {
+            } else if self.should_comment_be_on_same_line(prev, atom) {
                 if self.result.ends_with("\r\n") {
                     self.result.pop();
                     self.result.pop();
@@ -553,6 +552,11 @@ impl<'file> Formatter<'file> {
                 self.text(" ");
             }
         }
+    }
+
+    /// Write a single atom that does not start a nested construct.
+    fn atom(&mut self, atom: &Atom<'file>) {
+        self.before_atom(atom);
 
         match &atom.kind {
             AtomKind::Section { name, .. } => self.section(name),
@@ -560,10 +564,7 @@ impl<'file> Formatter<'file> {
             AtomKind::Const { name, value, .. } => self.const_(name, value),
             AtomKind::Undefine { name, .. } => self.undefine(name),
             AtomKind::Command { name, arguments } => {
-                let is_block = matches!(
-                    input.peek().map(|atom| &atom.kind),
-                    Some(AtomKind::OpenBlock { .. })
-                );
+                let is_block = matches!(self.next_kind(atom), Some(AtomKind::OpenBlock { .. }));
                 self.command(name, arguments, is_block);
             }
             AtomKind::Comment { content, .. } => self.comment(content),
@@ -583,11 +584,13 @@ impl<'file> Formatter<'file> {
                 }
             }
 
-            // Chunks of other control flow constructs. When encountering the start of one of these
-            // constructs, the formatter calls a separate method that will deal with the entire
-            // construct. They do not need special handling here. These atoms may also appear in
-            // incorrect positions, and then we don't want special handling either. So, for these
-            // cases we only print the command as is.
+            // Control flow tokens are normally written by the method for the node they belong to.
+            // They can also appear in incorrect positions, and then we only print them as is.
+            AtomKind::If { condition, .. } => {
+                self.text("if ");
+                self.text(condition.value);
+                self.newline();
+            }
             AtomKind::ElseIf { condition, .. } => {
                 self.text("elseif ");
                 self.text(condition.value);
@@ -601,10 +604,18 @@ impl<'file> Formatter<'file> {
                 self.text("endif");
                 self.newline();
             }
+            AtomKind::OpenBlock { .. } => {
+                self.text("{");
+                self.newline();
+            }
             AtomKind::CloseBlock { .. } => {
                 self.text("}");
                 self.newline();
             }
+            AtomKind::StartRandom { .. } => {
+                self.text("start_random");
+                self.newline();
+            }
             AtomKind::PercentChance { chance, .. } => {
                 self.text("percent_chance ");
                 self.text(chance.value);
@@ -614,35 +625,67 @@ impl<'file> Formatter<'file> {
                 self.text("end_random");
                 self.newline();
             }
+        }
+        self.prev = Some(atom.clone());
+    }
 
-            // These call into methods that do nested `write_atom` calls. They need to update
-            // `prev` first and not do anything _after_ calling the method to avoid getting in a
-            // bad state.
-            //
-            // FIXME It would be nice to approach this differently!
-            AtomKind::OpenBlock { .. } => {
-                self.prev = Some(atom);
-                return self.block(input);
+    /// Write the comments that precede a token.
+    fn comments(&mut self, token: &Token<'file>) {
+        for comment in token.comments() {
+            self.atom(comment);
+        }
+    }
+
+    fn node(&mut self, node: &Node<'file>) {
+        match node {
+            Node::Section(section) => {
+                self.comments(&section.header);
+                self.atom(&section.header.atom);
+                self.nodes(&section.children);
             }
-            AtomKind::If { condition, .. } => {
-                let condition = *condition;
-                self.prev = Some(atom);
-                return self.condition(&condition, input);
+            Node::Command(command) => {
+                self.comments(&command.command);
+                self.atom(&command.command.atom);
+                if let Some(block) = &command.block {
+                    self.block(block);
+                }
             }
-            AtomKind::StartRandom { .. } => {
-                self.prev = Some(atom);
-                return self.random(input);
+            Node::Block(block) => self.block(block),
+            Node::Condition(condition) => self.condition(condition),
+            Node::Random(random) => self.random(random),
+            Node::Atom(token) => {
+                self.comments(token);
+                self.atom(&token.atom);
             }
         }
-        self.prev = Some(atom);
-        input
     }
 
-    /// Format a script. Takes an iterator over atoms.
-    pub fn format(mut self, input: impl Iterator<Item = Atom<'file>>) -> String {
-        let mut input = input.peekable();
-        while let Some(atom) = input.next() {
-            input = self.write_atom(atom, input);
+    fn nodes(&mut self, nodes: &[Node<'file>]) {
+        for node in nodes {
+            self.node(node);
+        }
+    }
+
+    /// Format a script.
+    pub fn format(mut self, tree: &SyntaxTree<'file>) -> String {
+        tree.visit_tokens(|token| {
+            self.atoms.extend(token.comments().cloned());
+            self.atoms.push(token.atom.clone());
+        });
+        let trailing_comments = tree
+            .trailing_trivia
+            .iter()
+            .filter_map(|trivia| match trivia {
+                Trivia::Comment(_, atom) => Some(atom),
+                Trivia::Whitespace(_) => None,
+            })
+            .collect::<Vec<_>>();
+        self.atoms
+            .extend(trailing_comments.iter().map(|atom| (*atom).clone()));
+
+        self.nodes(&tree.nodes);
+        for comment in trailing_comments {
+            self.atom(comment);
         }
         self.result
     }
@@ -721,4 +764,23 @@ mod tests {
             "if A\r\n\r\n  #define X\r\n\r\nelse\r\n\r\nendif\r\n"
         );
     }
+
+    #[test]
+    fn nested_statements() {
+        assert_eq!(
+            format(
+                "create_object X { if A min_distance 5 else max_distance 10 endif number_of_objects 1 }",
+                FormatOptions::default()
+            ),
+            "create_object X {\r\n  if A\r\n    min_distance 5\r\n  else\r\n    max_distance 10\r\n  endif\r\n  number_of_objects 1\r\n}\r\n"
+        );
+        // Code before the first `percent_chance` is kept.
+        assert_eq!(
+            format(
+                "start_random #define X percent_chance 10 #define Y end_random",
+                FormatOptions::default()
+            ),
+            "start_random\r\n  #define X\r\n  percent_chance 10 #define Y\r\nend_random\r\n"
+        );
+    }
 }
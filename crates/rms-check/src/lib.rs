@@ -8,27 +8,41 @@
 #![warn(unused)]
 
 mod checker;
+mod config;
 mod diagnostic;
 mod formatter;
 mod lints;
 mod parser;
+mod preprocessor;
+mod random;
 mod state;
+mod syntax_tree;
 mod tokenizer;
 mod tokens;
 
 use crate::checker::Checker;
-pub use crate::checker::{CheckerBuilder, Lint};
-pub use crate::diagnostic::{ByteIndex, Diagnostic, FileId, Fix, Severity, SourceLocation};
+pub use crate::checker::{CheckCache, CheckerBuilder, Lint};
+pub use crate::config::{Config, LintLevel, CONFIG_FILE_NAME};
+pub use crate::diagnostic::{ByteIndex, Diagnostic, Edit, FileId, Fix, Severity, SourceLocation};
 pub use crate::formatter::{format, FormatOptions};
-pub use crate::parser::{Atom, AtomKind, ParseErrorKind, Parser};
+pub use crate::parser::{Atom, AtomKind, ParseError, ParseErrorKind, Parser};
+pub use crate::preprocessor::{PreprocessedScript, Preprocessor, SourceMapping};
+pub use crate::random::{RandomOutcome, RandomStatement, RandomTree};
 pub use crate::state::{Compatibility, Nesting, ParseState};
+pub use crate::syntax_tree::{
+    BlockNode, Branch, CommandNode, ConditionNode, Node, RandomNode, SectionNode, SyntaxTree,
+    Token, Trivia,
+};
 pub use crate::tokenizer::Word;
 pub use crate::tokens::{ArgType, TokenContext, TokenType, TOKENS};
 use encoding_rs::Encoding;
+use std::collections::HashSet;
 use std::{borrow::Cow, fs::File, io, path::Path};
 use zip::ZipArchive;
 
-fn to_chardet_string(bytes: Vec<u8>) -> String {
+/// Decode the contents of a script file. Scripts that are not valid UTF-8 are decoded using the
+/// encoding that chardet detects, which is usually Windows-1252.
+pub fn to_chardet_string(bytes: Vec<u8>) -> String {
     String::from_utf8(bytes).unwrap_or_else(|err| {
         let bytes = err.as_bytes();
         let (encoding_name, _, _) = chardet::detect(bytes);
@@ -40,6 +54,65 @@ fn to_chardet_string(bytes: Vec<u8>) -> String {
     })
 }
 
+/// How the columns in line/column positions are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PositionEncoding {
+    /// Columns count UTF-8 bytes.
+    #[default]
+    Utf8,
+    /// Columns count UTF-16 code units, like in JavaScript strings.
+    Utf16,
+    /// Columns count Unicode code points.
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Get the length of a character in this encoding.
+    const fn char_len(self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+
+    /// Get the byte offset of a column in a line, without its line break. A column past the end
+    /// of the line refers to the end of the line, like in the LSP. A column in the middle of a
+    /// character refers to the start of that character.
+    fn byte_offset(self, line: &str, column: u32) -> usize {
+        let mut column = column as usize;
+        if self == PositionEncoding::Utf8 {
+            if column >= line.len() {
+                return line.len();
+            }
+            while !line.is_char_boundary(column) {
+                column -= 1;
+            }
+            return column;
+        }
+        let mut units = 0;
+        for (offset, c) in line.char_indices() {
+            units += self.char_len(c);
+            if units > column {
+                return offset;
+            }
+        }
+        line.len()
+    }
+
+    /// Get the column of a byte offset in a line. An offset in the middle of a character refers
+    /// to the end of that character.
+    fn column(self, line: &str, offset: usize) -> u32 {
+        if self == PositionEncoding::Utf8 {
+            return offset as u32;
+        }
+        line.char_indices()
+            .take_while(|&(start, _)| start < offset)
+            .map(|(_, c)| self.char_len(c))
+            .sum::<usize>() as u32
+    }
+}
+
 #[derive(Debug, Clone)]
 struct FileData<'source> {
     name: String,
@@ -63,38 +136,45 @@ impl<'source> FileData<'source> {
         }
     }
 
-    /// Get the ByteIndex for a line/column pair. Returns None if the line or column is out of
-    /// range.
-    fn get_byte_index(&self, line: u32, column: u32) -> Option<ByteIndex> {
-        let &start = self.line_indices.get(line as usize)?;
-        if let Some(&end) = self.line_indices.get(line as usize + 1) {
-            let line_len = usize::from(end) - usize::from(start);
-            if column as usize > line_len {
-                return None;
-            }
-        }
+    /// Get the text of a line, including the line break.
+    fn line(&self, line: usize) -> Option<&str> {
+        let start = usize::from(*self.line_indices.get(line)?);
+        let end = self
+            .line_indices
+            .get(line + 1)
+            .map_or(self.source.len(), |&end| usize::from(end));
+        Some(&self.source[start..end])
+    }
 
-        let index = start + column as isize;
-        if usize::from(index) < self.source.len() {
-            Some(index)
-        } else {
-            None
-        }
+    /// Get the ByteIndex for a line/column pair. Returns None if the line is out of range. A
+    /// column past the end of the line refers to the end of the line, before the line break.
+    fn get_byte_index(
+        &self,
+        line: u32,
+        column: u32,
+        encoding: PositionEncoding,
+    ) -> Option<ByteIndex> {
+        let start = *self.line_indices.get(line as usize)?;
+        let text = self.line(line as usize)?;
+        let text = text
+            .strip_suffix('\n')
+            .map_or(text, |text| text.strip_suffix('\r').unwrap_or(text));
+        let offset = encoding.byte_offset(text, column);
+        Some(start + offset as isize)
     }
 
     /// Get the line/column location of a byte index.
-    fn get_location(&self, index: ByteIndex) -> Option<(u32, u32)> {
+    fn get_location(&self, index: ByteIndex, encoding: PositionEncoding) -> Option<(u32, u32)> {
+        if usize::from(index) > self.source.len() {
+            return None;
+        }
         let line = match self.line_indices.binary_search(&index) {
-            Ok(line) => Some(line),
-            Err(next_line) => Some(next_line - 1),
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
         };
-
-        line.and_then(|line| {
-            let start_index = self.line_indices[line];
-            usize::from(index)
-                .checked_sub(usize::from(start_index))
-                .map(|column| (line as u32, column as u32))
-        })
+        let offset = usize::from(index) - usize::from(self.line_indices[line]);
+        let column = encoding.column(self.line(line)?, offset);
+        Some((line as u32, column))
     }
 }
 
@@ -173,19 +253,39 @@ impl<'source> RMSFile<'source> {
         let mut files = vec![];
         for index in 0..zip.len() {
             let mut file = zip.by_index(index)?;
+            if !file.name().ends_with(".rms") && !file.name().ends_with(".inc") {
+                continue;
+            }
             let mut bytes = vec![];
             std::io::copy(&mut file, &mut bytes)?;
-            if file.name().ends_with(".rms") || file.name().ends_with(".inc") {
-                let source = to_chardet_string(bytes);
-                files.push(FileData::new(file.name().to_string(), Cow::Owned(source)));
-                // If this is an .rms file, move it to the front so main_file() does the right thing
-                if file.name().ends_with(".rms") {
-                    files.rotate_right(1);
-                }
+            files.push((
+                file.name().to_string(),
+                Cow::Owned(to_chardet_string(bytes)),
+            ));
+        }
+
+        Ok(Self::from_zip_rms_files(files))
+    }
+
+    /// Create an RMSFile from the files in a ZR@ map, given as pairs of file names and sources.
+    /// Only `.rms` and `.inc` files are used.
+    pub fn from_zip_rms_files(
+        files: impl IntoIterator<Item = (String, Cow<'source, str>)>,
+    ) -> Self {
+        let mut scripts = vec![];
+        for (name, source) in files {
+            if !name.ends_with(".rms") && !name.ends_with(".inc") {
+                continue;
+            }
+            let is_rms = name.ends_with(".rms");
+            scripts.push(FileData::new(name, source));
+            // If this is an .rms file, move it to the front so main_file() does the right thing
+            if is_rms {
+                scripts.rotate_right(1);
             }
         }
 
-        Ok(Self::new(files))
+        Self::new(scripts)
     }
 
     /// Create an RMSFile from a string of bytes containing a ZR@ map.
@@ -198,17 +298,19 @@ impl<'source> RMSFile<'source> {
         let mut files = vec![];
         for entry in std::fs::read_dir(path)? {
             let path = entry?.path();
-            let name = path.to_string_lossy();
-            let bytes = std::fs::read(&path)?;
-            let source = to_chardet_string(bytes);
-            files.push(FileData::new(name.to_string(), Cow::Owned(source)));
-            // If this is an .rms file, move it to the front so main_file() does the right thing
-            if name.ends_with(".rms") {
-                files.rotate_right(1);
+            // Name files like the ZR@ archive does, so `#include` can find them.
+            let name = match path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if !name.ends_with(".rms") && !name.ends_with(".inc") {
+                continue;
             }
+            let bytes = std::fs::read(&path)?;
+            files.push((name, Cow::Owned(to_chardet_string(bytes))));
         }
 
-        Ok(Self::new(files))
+        Ok(Self::from_zip_rms_files(files))
     }
 
     /// Create an RMSFile from a file path containing a ZR@ map.
@@ -235,6 +337,61 @@ impl<'source> RMSFile<'source> {
         FileId::new(0)
     }
 
+    /// Get the [`FileId`]s of all the scripts in this map, starting with the main script. For ZR@
+    /// maps, this includes `.inc` files.
+    pub fn script_files(&self) -> impl Iterator<Item = FileId> {
+        (0..self.def_aoc.to_u32()).map(FileId::new)
+    }
+
+    /// Get the [`FileId`]s of the files loaded by [`load_includes`](Self::load_includes).
+    pub fn included_files(&self) -> impl Iterator<Item = FileId> {
+        (self.def_wk.to_u32() + 1..self.files.len() as u32).map(FileId::new)
+    }
+
+    /// Load the files used by `#include` and `#include_drs` commands from a directory, for
+    /// builtin maps. Included files are searched recursively. Files that do not exist in the
+    /// directory are skipped.
+    pub fn load_includes(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        let mut queue: Vec<FileId> = self.script_files().collect();
+        while let Some(file) = queue.pop() {
+            let names: Vec<String> = Parser::new(file, self.source(file))
+                .filter_map(|(atom, _)| match atom.kind {
+                    AtomKind::Command { name, arguments }
+                        if (name.value == "#include" || name.value == "#include_drs")
+                            && !arguments.is_empty() =>
+                    {
+                        Some(arguments[0].value.to_string())
+                    }
+                    _ => None,
+                })
+                .collect();
+
+            for name in names {
+                if self.find_include(&name).is_some() {
+                    continue;
+                }
+                let path = dir.as_ref().join(&name);
+                if !path.is_file() {
+                    continue;
+                }
+                let source = to_chardet_string(std::fs::read(&path)?);
+                queue.push(FileId::new(self.files.len() as u32));
+                self.files.push(FileData::new(name, Cow::Owned(source)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the file that an `#include` or `#include_drs` command refers to: either a file
+    /// loaded by [`load_includes`](Self::load_includes), or a script inside a ZR@ map.
+    ///
+    /// This does not find the bundled definitions files.
+    pub fn find_include(&self, name: &str) -> Option<FileId> {
+        self.included_files()
+            .chain(self.script_files())
+            .find(|&file| self.name(file) == name)
+    }
+
     /// Get the source code of the main script in this map.
     pub fn main_source(&self) -> &str {
         self.source(self.file_id())
@@ -272,19 +429,31 @@ impl<'source> RMSFile<'source> {
         self.files[0].name.starts_with("ZR@")
     }
 
-    /// Get the ByteIndex for a line/column pair. Returns None if the line or column is out of
-    /// range.
-    pub fn get_byte_index(&self, file: FileId, line: u32, column: u32) -> Option<ByteIndex> {
+    /// Get the ByteIndex for a line/column pair, with columns counted in the given encoding.
+    /// Returns None if the line is out of range. A column past the end of the line refers to the
+    /// end of the line.
+    pub fn get_byte_index(
+        &self,
+        file: FileId,
+        line: u32,
+        column: u32,
+        encoding: PositionEncoding,
+    ) -> Option<ByteIndex> {
         self.files
             .get(file.to_usize())
-            .and_then(|file| file.get_byte_index(line, column))
+            .and_then(|file| file.get_byte_index(line, column, encoding))
     }
 
-    /// Get the line/column location of a byte index.
-    pub fn get_location(&self, file: FileId, index: ByteIndex) -> Option<(u32, u32)> {
+    /// Get the line/column location of a byte index, with columns counted in the given encoding.
+    pub fn get_location(
+        &self,
+        file: FileId,
+        index: ByteIndex,
+        encoding: PositionEncoding,
+    ) -> Option<(u32, u32)> {
         self.files
             .get(file.to_usize())
-            .and_then(|file| file.get_location(index))
+            .and_then(|file| file.get_location(index, encoding))
     }
 
     /// Get the parsing state at a particular offset.
@@ -304,21 +473,45 @@ impl<'source> RMSFile<'source> {
                 focus_atom = Some(atom);
             } else {
                 state.update(&atom);
+                state.update_nesting(&atom);
             }
         }
         (state, focus_atom)
     }
+
+    /// Parse a file, calling `visit` with each atom and the parsing state right before it.
+    pub fn visit_atoms<'a>(
+        &'a self,
+        file: FileId,
+        compatibility: Compatibility,
+        mut visit: impl FnMut(&ParseState<'a>, &Atom<'a>),
+    ) {
+        let mut state = ParseState::new(self, compatibility);
+        for (atom, _) in Parser::new(file, self.source(file)) {
+            visit(&state, &atom);
+            state.update(&atom);
+            state.update_nesting(&atom);
+        }
+    }
 }
 
 /// The result of a lint run.
 pub struct RMSCheckResult {
     diagnostics: Vec<Diagnostic>,
+    compatibility: Compatibility,
 }
 
 impl RMSCheckResult {
-    /// Were there any warnings?
+    /// Were there any warnings? Hints do not count.
     pub fn has_warnings(&self) -> bool {
-        !self.diagnostics.is_empty()
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity() != Severity::Hint)
+    }
+
+    /// The compatibility the script was checked with, after applying `Compatibility:` comments.
+    pub const fn compatibility(&self) -> Compatibility {
+        self.compatibility
     }
 
     /// Iterate over the diagnostics.
@@ -336,9 +529,10 @@ impl IntoIterator for RMSCheckResult {
     }
 }
 
-///
+/// The rms-check linter, with a configurable set of lints.
 pub struct RMSCheck {
     checker: CheckerBuilder,
+    config: Config,
 }
 
 impl Default for RMSCheck {
@@ -349,9 +543,12 @@ impl Default for RMSCheck {
             .with_lint(Box::new(lints::AttributeCaseLint {}))
             .with_lint(Box::new(lints::CommentContentsLint::new()))
             .with_lint(Box::new(lints::CompatibilityLint::new()))
+            .with_lint(Box::new(lints::ConstRedefinitionLint::new()))
             .with_lint(Box::new(lints::IncludeLint::new()))
             .with_lint(Box::new(lints::IncorrectSectionLint::new()))
+            .with_lint(Box::new(lints::PercentChanceLint::new()))
             .with_lint(Box::new(lints::UnknownAttributeLint {}))
+            .with_lint(Box::new(lints::UnusedDeclarationLint::new()))
     }
 }
 
@@ -360,6 +557,7 @@ impl RMSCheck {
     pub fn new() -> Self {
         RMSCheck {
             checker: Checker::builder(),
+            config: Config::default(),
         }
     }
 
@@ -370,6 +568,21 @@ impl RMSCheck {
     pub fn compatibility(self, compatibility: Compatibility) -> Self {
         Self {
             checker: self.checker.compatibility(compatibility),
+            ..self
+        }
+    }
+
+    /// Treat the script as a builtin map, which can use `#include_drs` and `#include`.
+    ///
+    /// Builtin maps have to `#include_drs random_map.def` to use the builtin `#const` names. Other
+    /// included files are checked if they are loaded using [`RMSFile::load_includes`].
+    ///
+    /// Scripts can also declare themselves as builtin maps using a `Builtin map: yes` comment.
+    #[allow(clippy::missing_const_for_fn)] // false positive
+    pub fn builtin_map(self, is_builtin_map: bool) -> Self {
+        Self {
+            checker: self.checker.builtin_map(is_builtin_map),
+            ..self
         }
     }
 
@@ -377,39 +590,285 @@ impl RMSCheck {
     pub fn with_lint(self, lint: Box<dyn Lint>) -> Self {
         Self {
             checker: self.checker.with_lint(lint),
+            ..self
         }
     }
 
+    /// Apply a project configuration: disable lints or change their severity, and set the
+    /// default compatibility if the configuration has one.
+    pub fn with_config(self, config: Config) -> Self {
+        let checker = match config.get_compatibility() {
+            Some(compatibility) => self.checker.compatibility(compatibility),
+            None => self.checker,
+        };
+        Self { checker, config }
+    }
+
+    /// Get the names of the lints that will run, before applying the configuration.
+    pub fn lint_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.checker.lint_names()
+    }
+
     /// Run the lints and get the result.
+    ///
+    /// Files that are included using `#include_drs` or `#include` are checked at the point where
+    /// they are included, sharing the state of the including script. For ZR@ maps, script files
+    /// that are never included are checked after the main script.
     pub fn check(self, rms: &RMSFile<'_>) -> RMSCheckResult {
-        let mut checker = self.checker.build(rms);
+        let config = self.config;
+        let mut checker = self
+            .checker
+            .retain_lints(|name| config.is_enabled(name))
+            .build(rms);
 
         let mut diagnostics = vec![];
+        let mut checked = HashSet::new();
+        for file in rms.script_files() {
+            if checked.insert(file) {
+                check_file(rms, file, &mut checker, &mut checked, &mut diagnostics);
+            }
+        }
+        diagnostics.extend(checker.finish());
+        let diagnostics = diagnostics
+            .into_iter()
+            .filter_map(|diagnostic| config.apply(diagnostic))
+            .collect();
 
-        let parser = Parser::new(rms.file_id(), rms.main_source());
-        for (atom, parse_warning) in parser {
-            let warnings = checker.write_atom(&atom);
-            for w in parse_warning {
-                if w.kind == ParseErrorKind::MissingCommandArgs {
-                    // Handled by arg-types lint
-                    continue;
+        RMSCheckResult {
+            diagnostics,
+            compatibility: checker.compatibility(),
+        }
+    }
+
+    /// Run the lints, reusing the work of a previous check of the same script where possible.
+    ///
+    /// After editing the main script, call [`CheckCache::invalidate`] with the start of the edit.
+    /// Checking then resumes at the last top-level `<SECTION>` or statement before the edit,
+    /// without parsing or linting the unchanged part before it again. The result is the same as
+    /// that of [`check`](Self::check).
+    ///
+    /// The cache is cleared if any file other than the main script changed.
+    pub fn check_with_cache(self, rms: &RMSFile<'_>, cache: &mut CheckCache) -> RMSCheckResult {
+        let config = self.config;
+        let builder = self.checker.retain_lints(|name| config.is_enabled(name));
+        cache.prepare(&builder, rms);
+        let mut checker = builder.build(rms);
+
+        let (resume, mut checked, mut diagnostics) = match cache.restore(&mut checker) {
+            Some((resume, checked, diagnostics)) => (Some(resume), checked, diagnostics),
+            None => {
+                cache.clear();
+                (None, HashSet::new(), vec![])
+            }
+        };
+        let file = rms.file_id();
+        checked.insert(file);
+        let mut last_checkpoint = resume;
+        let mut can_save = true;
+        let start = resume.unwrap_or_else(|| ByteIndex::from(0));
+        for (atom, parse_warnings) in Parser::starting_at(file, rms.source(file), start) {
+            let start = atom.location.start();
+            let is_section = matches!(atom.kind, AtomKind::Section { .. });
+            let is_construct_start = is_section
+                || matches!(
+                    atom.kind,
+                    AtomKind::Command { .. }
+                        | AtomKind::If { .. }
+                        | AtomKind::StartRandom { .. }
+                        | AtomKind::Const { .. }
+                        | AtomKind::Define { .. }
+                );
+            // Save a state at every top-level section, and in between if sections are long.
+            let is_due = match last_checkpoint {
+                Some(last) => {
+                    start > last
+                        && (is_section
+                            || usize::from(start) >= usize::from(last) + CHECKPOINT_INTERVAL)
                 }
+                None => is_section || usize::from(start) >= CHECKPOINT_INTERVAL,
+            };
+            if can_save && is_due && is_construct_start && checker.is_at_top_level() {
+                can_save = cache.save(&checker, &atom, &checked, &diagnostics);
+                last_checkpoint = Some(start);
+            }
+            check_atom(
+                rms,
+                &atom,
+                parse_warnings,
+                &mut checker,
+                &mut checked,
+                &mut diagnostics,
+            );
+        }
+        cache.set_diagnostics(&diagnostics);
+        for file in rms.script_files() {
+            if checked.insert(file) {
+                check_file(rms, file, &mut checker, &mut checked, &mut diagnostics);
+            }
+        }
+        diagnostics.extend(checker.finish());
+        let diagnostics = diagnostics
+            .into_iter()
+            .filter_map(|diagnostic| config.apply(diagnostic))
+            .collect();
 
-                let overlaps_parse_warning = |lint_warning: &Diagnostic| {
-                    let lint_range = lint_warning.location().range();
-                    let parse_range = w.location.range();
-                    lint_range.contains(&parse_range.start) && lint_range.contains(&parse_range.end)
-                };
-                if warnings.iter().all(overlaps_parse_warning) {
-                    diagnostics.push(
-                        Diagnostic::parse_error(w.location, format!("{:?}", w.kind))
-                            .with_code("parse"),
-                    );
+        RMSCheckResult {
+            diagnostics,
+            compatibility: checker.compatibility(),
+        }
+    }
+}
+
+/// The minimum distance in bytes between two [`CheckCache`] checkpoints inside one section.
+/// Saving a checkpoint copies the state of every lint, so doing it before every atom would be
+/// slower than linting.
+const CHECKPOINT_INTERVAL: usize = 4096;
+
+/// Check a single file in a map, and the files it includes.
+fn check_file<'source>(
+    rms: &'source RMSFile<'source>,
+    file: FileId,
+    checker: &mut Checker<'source>,
+    checked: &mut HashSet<FileId>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let parser = Parser::new(file, rms.source(file));
+    for (atom, parse_warnings) in parser {
+        check_atom(rms, &atom, parse_warnings, checker, checked, diagnostics);
+    }
+}
+
+/// Lint a single atom, and check the file it includes, if any.
+fn check_atom<'source>(
+    rms: &'source RMSFile<'source>,
+    atom: &Atom<'source>,
+    parse_warnings: Vec<ParseError>,
+    checker: &mut Checker<'source>,
+    checked: &mut HashSet<FileId>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let warnings = checker.write_atom(atom);
+    for w in parse_warnings {
+        if w.kind == ParseErrorKind::MissingCommandArgs {
+            // Handled by arg-types lint
+            continue;
+        }
+
+        let overlaps_parse_warning = |lint_warning: &Diagnostic| {
+            let lint_range = lint_warning.location().range();
+            let parse_range = w.location.range();
+            lint_range.contains(&parse_range.start) && lint_range.contains(&parse_range.end)
+        };
+        if warnings.iter().all(overlaps_parse_warning) {
+            let parse_error =
+                Diagnostic::parse_error(w.location, format!("{:?}", w.kind)).with_code("parse");
+            diagnostics.extend(checker.filter_suppressed(vec![parse_error]));
+        }
+    }
+    diagnostics.extend(warnings);
+
+    if let AtomKind::Command { name, arguments } = &atom.kind {
+        if (name.value == "#include_drs" || name.value == "#include") && !arguments.is_empty() {
+            if let Some(included) = rms.find_include(arguments[0].value) {
+                if checked.insert(included) {
+                    check_file(rms, included, checker, checked, diagnostics);
                 }
             }
-            diagnostics.extend(warnings);
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn position_encodings() {
+        // `é` is 2 bytes in UTF-8, `𐍈` is 4 bytes in UTF-8 and 2 code units in UTF-16.
+        let file = RMSFile::from_string("positions.rms", "/* é𐍈 */ x\ny");
+        let id = file.file_id();
+        let x = ByteIndex::from(13);
+        assert_eq!(&file.main_source()[13..14], "x");
+
+        assert_eq!(
+            file.get_location(id, x, PositionEncoding::Utf8),
+            Some((0, 13))
+        );
+        assert_eq!(
+            file.get_location(id, x, PositionEncoding::Utf16),
+            Some((0, 10))
+        );
+        assert_eq!(
+            file.get_location(id, x, PositionEncoding::Utf32),
+            Some((0, 9))
+        );
+        assert_eq!(
+            file.get_byte_index(id, 0, 13, PositionEncoding::Utf8),
+            Some(x)
+        );
+        assert_eq!(
+            file.get_byte_index(id, 0, 10, PositionEncoding::Utf16),
+            Some(x)
+        );
+        assert_eq!(
+            file.get_byte_index(id, 0, 9, PositionEncoding::Utf32),
+            Some(x)
+        );
+
+        // The end of the file is a valid position.
+        let end = ByteIndex::from(file.main_source().len());
+        assert_eq!(
+            file.get_location(id, end, PositionEncoding::Utf16),
+            Some((1, 1))
+        );
+        assert_eq!(
+            file.get_byte_index(id, 1, 1, PositionEncoding::Utf16),
+            Some(end)
+        );
+        assert_eq!(
+            file.get_byte_index(id, 1, 2, PositionEncoding::Utf16),
+            Some(end)
+        );
+        assert_eq!(file.get_byte_index(id, 2, 0, PositionEncoding::Utf16), None);
+    }
+
+    #[test]
+    fn columns_past_the_end_of_a_line() {
+        let file = RMSFile::from_string("lines.rms", "é\r\nab\ncd");
+        let id = file.file_id();
+        for encoding in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            // The end of the line comes before the line break.
+            assert_eq!(
+                file.get_byte_index(id, 0, 100, encoding),
+                Some(ByteIndex::from(2))
+            );
+            assert_eq!(
+                file.get_byte_index(id, 1, 3, encoding),
+                Some(ByteIndex::from(6))
+            );
+            assert_eq!(
+                file.get_byte_index(id, 2, 3, encoding),
+                Some(ByteIndex::from(9))
+            );
+        }
+    }
+
+    #[test]
+    fn unpacked_zip_rms() {
+        let dir = std::env::temp_dir().join(format!("rms-check-unpacked-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("ZR@map.rms"), "#include land.inc\n").unwrap();
+        std::fs::write(dir.join("land.inc"), "<LAND_GENERATION>\n").unwrap();
+
+        let file = RMSFile::from_zip_rms_path_unpacked(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
 
-        RMSCheckResult { diagnostics }
+        assert_eq!(file.name(file.file_id()), "ZR@map.rms");
+        let include = file.find_include("land.inc").unwrap();
+        assert_eq!(file.source(include), "<LAND_GENERATION>\n");
     }
 }
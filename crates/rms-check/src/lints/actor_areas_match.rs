@@ -1,7 +1,7 @@
 use crate::diagnostic::{Diagnostic, SourceLocation};
 use crate::{Atom, AtomKind, Lint, ParseState};
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ActorAreasMatchLint {
     actor_areas: Vec<(i32, SourceLocation)>,
 }
@@ -16,6 +16,10 @@ impl Lint for ActorAreasMatchLint {
     fn name(&self) -> &'static str {
         "actor-areas-match"
     }
+
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
     fn lint_atom(&mut self, _state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
         if let AtomKind::Command { name, arguments } = &atom.kind {
             let mut warnings = vec![];
@@ -3,7 +3,7 @@ use crate::{ArgType, Atom, AtomKind, Lint, ParseState, Word, TOKENS};
 use cow_utils::CowUtils;
 use strsim::jaro_winkler;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ArgTypesLint {}
 
 impl ArgTypesLint {
@@ -160,13 +160,14 @@ impl ArgTypesLint {
     }
 
     /// Check the arguments to an `assign_to` attribute.
+    #[allow(clippy::collapsible_match)] // the fallthrough arms would change behaviour
     fn check_assign_to(&self, args: &[Word<'_>], warnings: &mut Vec<Diagnostic>) {
         enum AssignTarget {
             Color,
             Player,
             Team,
         }
-        let target = if let Some(arg) = args.get(0) {
+        let target = if let Some(arg) = args.first() {
             match arg.value {
                 "AT_COLOR" => Some(AssignTarget::Color),
                 "AT_PLAYER" => Some(AssignTarget::Player),
@@ -194,7 +195,7 @@ impl ArgTypesLint {
                     }
                 }
                 Some(AssignTarget::Team) => {
-                    if (number < -4 || number > 4) && number != -10 {
+                    if !(-4..=4).contains(&number) && number != -10 {
                         warnings.push(Diagnostic::warning(
                             args[1].location,
                             "`assign_to` Number must be 1-4 when targeting AT_TEAM",
@@ -240,6 +241,11 @@ impl Lint for ArgTypesLint {
     fn name(&self) -> &'static str {
         "arg-types"
     }
+
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
+    #[allow(clippy::collapsible_match)]
     fn lint_atom(&mut self, state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
         if let AtomKind::Command { name, arguments } = &atom.kind {
             let token_type = &TOKENS[name.value.cow_to_ascii_lowercase().as_ref()];
@@ -268,7 +274,7 @@ impl Lint for ArgTypesLint {
                     }
                 }
                 "land_position" => {
-                    if let Some(Ok(first)) = arguments.get(0).map(|f| f.value.parse::<i32>()) {
+                    if let Some(Ok(first)) = arguments.first().map(|f| f.value.parse::<i32>()) {
                         if !(0..=100).contains(&first) {
                             warnings.push(Diagnostic::warning(
                                 arguments[0].location,
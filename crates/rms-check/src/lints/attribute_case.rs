@@ -3,6 +3,7 @@ use crate::{Atom, AtomKind, Lint, ParseState, TOKENS};
 use cow_utils::CowUtils;
 use std::borrow::Cow;
 
+#[derive(Clone)]
 pub struct AttributeCaseLint {}
 
 impl AttributeCaseLint {
@@ -26,6 +27,10 @@ impl Lint for AttributeCaseLint {
     fn name(&self) -> &'static str {
         "attribute-case"
     }
+
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
     fn lint_atom(&mut self, _state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
         match atom.kind {
             AtomKind::Command { name, .. } => {
@@ -9,7 +9,7 @@ fn offset_range(span: Range<ByteIndex>, offset: isize) -> Range<ByteIndex> {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct CommentContentsLint {}
 
 impl CommentContentsLint {
@@ -23,6 +23,10 @@ impl Lint for CommentContentsLint {
         "comment-contents"
     }
 
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
+
     fn run_inside_comments(&self) -> bool {
         true
     }
@@ -93,7 +97,7 @@ impl Lint for CommentContentsLint {
             return warnings;
         }
 
-        return vec![];
+        vec![]
     }
 }
 
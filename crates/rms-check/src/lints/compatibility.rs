@@ -1,9 +1,44 @@
-use crate::diagnostic::{Diagnostic, Fix};
+use crate::diagnostic::{ByteIndex, Diagnostic, Fix, SourceLocation};
 use crate::{Atom, AtomKind, Compatibility, Lint, ParseState};
+use std::ops::Range;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct CompatibilityLint {
     conditions: Vec<String>,
+    /// Are we past the header comments of the main script?
+    end_of_headers: bool,
+    /// The location of the value of the last `Compatibility:` header in the main script.
+    header: Option<SourceLocation>,
+}
+
+/// Find the value of the last `Compatibility:` header in the source text of a comment.
+fn find_compatibility_header(comment: &str) -> Option<Range<usize>> {
+    let mut found = None;
+    let mut line_start = 0;
+    for line in comment.split('\n') {
+        if let Some(separator) = line.find(": ") {
+            let name = line[..separator].trim_start();
+            let name = name.strip_prefix("/*").unwrap_or(name).trim_start();
+            let name = name.strip_prefix("* ").unwrap_or(name);
+            if name.trim().eq_ignore_ascii_case("compatibility") {
+                let value_start = separator + 2;
+                let value = &line[value_start..];
+                let value = &value[..value.find("*/").unwrap_or(value.len())];
+                let start = line_start + value_start + (value.len() - value.trim_start().len());
+                found = Some(start..line_start + value_start + value.trim_end().len());
+            }
+        }
+        line_start += line.len() + 1;
+    }
+    found
+}
+
+/// Find the line ending used in a source text, so fixes can insert lines that match it.
+fn line_ending(source: &str) -> &'static str {
+    match source.find('\n') {
+        Some(index) if source[..index].ends_with('\r') => "\r\n",
+        _ => "\n",
+    }
 }
 
 impl CompatibilityLint {
@@ -27,6 +62,80 @@ impl CompatibilityLint {
     fn add_define_check(&mut self, name: &str) {
         self.conditions.push(name.to_string());
     }
+
+    /// Remember where the `Compatibility:` header of the main script is, so fixes can change it.
+    fn update_headers(&mut self, state: &ParseState<'_>, atom: &Atom<'_>) {
+        if self.end_of_headers || atom.location.file() != state.rms.file_id() {
+            return;
+        }
+        if let AtomKind::Comment { .. } = atom.kind {
+            let start = usize::from(atom.location.start());
+            let source = &state.rms.main_source()[start..usize::from(atom.location.end())];
+            if let Some(range) = find_compatibility_header(source) {
+                self.header = Some(SourceLocation::new(
+                    atom.location.file(),
+                    ByteIndex::from(start + range.start)..ByteIndex::from(start + range.end),
+                ));
+            }
+        } else {
+            self.end_of_headers = true;
+        }
+    }
+
+    /// Suggest wrapping a command in an `if` statement.
+    fn wrap_fix(state: &ParseState<'_>, atom: &Atom<'_>, condition: &str) -> Fix {
+        let file = atom.location.file();
+        let source = state.rms.source(file);
+        let start = usize::from(atom.location.start());
+        let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line = &source[line_start..start];
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let newline = line_ending(source);
+
+        let at_start = SourceLocation::new(file, atom.location.start()..atom.location.start());
+        let at_end = SourceLocation::new(file, atom.location.end()..atom.location.end());
+        Fix::new(
+            atom.location,
+            format_args!("Wrap this command in an `if {}` statement", condition),
+        )
+        .edit(
+            at_start,
+            format_args!("if {}{}{}  ", condition, newline, indent),
+        )
+        .edit(at_end, format_args!("{}{}endif", newline, indent))
+    }
+
+    /// Suggest adding or changing the `Compatibility:` header comment.
+    fn header_fix(
+        &self,
+        state: &ParseState<'_>,
+        atom: &Atom<'_>,
+        compatibility: Compatibility,
+    ) -> Fix {
+        match self.header {
+            Some(header) => Fix::new(
+                atom.location,
+                format_args!("Change the `Compatibility:` header to {}", compatibility),
+            )
+            .edit(header, compatibility),
+            None => {
+                let file = state.rms.file_id();
+                let newline = line_ending(state.rms.source(file));
+                let top = SourceLocation::new(file, ByteIndex::from(0)..ByteIndex::from(0));
+                Fix::new(
+                    atom.location,
+                    format_args!(
+                        "Add a /* Compatibility: {} */ comment at the top of the file",
+                        compatibility
+                    ),
+                )
+                .edit(
+                    top,
+                    format_args!("/* Compatibility: {} */{}", compatibility, newline),
+                )
+            }
+        }
+    }
 }
 
 impl Lint for CompatibilityLint {
@@ -34,83 +143,81 @@ impl Lint for CompatibilityLint {
         "compatibility"
     }
 
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
+
     fn lint_atom(&mut self, state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
+        self.update_headers(state, atom);
+
         let mut warnings = vec![];
+        let is_de = state.compatibility() == Compatibility::DefinitiveEdition;
 
         if let AtomKind::Command { name, .. } = &atom.kind {
-            match name.value {
-                "effect_amount" | "effect_percent" => {
-                    if !self.has_up_extension(state) && state.compatibility() != Compatibility::DefinitiveEdition {
-                        warnings.push(Diagnostic::warning(atom.location, "RMS Effects require UserPatch 1.5 or Definitive Edition")
-                                      .suggest(Fix::new(atom.location, "Wrap this command in an `if UP_EXTENSION` statement or add a /* Compatibility: UserPatch 1.5 */ comment at the top of the file")));
-                    }
-                }
-                "direct_placement" => {
-                    if !self.has_up_extension(state) {
-                        warnings.push(Diagnostic::warning(atom.location, "Direct placement requires UserPatch 1.5 or Definitive Edition")
-                                .suggest(Fix::new(atom.location,
-                                    "Wrap this command in an `if UP_EXTENSION` statement or add a /* Compatibility: UserPatch 1.5 */ comment at the top of the file",
-                                ))
-                        )
-                    }
-                }
-                "nomad_resources" => {
-                    if !self.has_up_available(state) && state.compatibility() != Compatibility::HDEdition {
-                        warnings.push(
-                            Diagnostic::warning(atom.location, "Nomad resources requires UserPatch 1.4 or HD Edition")
-                                .suggest(Fix::new(atom.location,
-                                    "Wrap this command in an `if UP_AVAILABLE` statement or add a /* Compatibility: UserPatch 1.4 */ comment at the top of the file",
-                                ))
-                        )
-                    }
+            let message = match name.value {
+                "effect_amount" | "effect_percent" if !self.has_up_extension(state) && !is_de => {
+                    Some("RMS Effects require UserPatch 1.5 or Definitive Edition".to_string())
                 }
+                "direct_placement" if !self.has_up_extension(state) => Some(
+                    "Direct placement requires UserPatch 1.5 or Definitive Edition".to_string(),
+                ),
+                _ => None,
+            };
+            if let Some(message) = message {
+                warnings.push(
+                    Diagnostic::warning(atom.location, message)
+                        .suggest(Self::wrap_fix(state, atom, "UP_EXTENSION"))
+                        .suggest(self.header_fix(state, atom, Compatibility::UserPatch15)),
+                );
+            }
+
+            if name.value == "nomad_resources"
+                && !self.has_up_available(state)
+                && state.compatibility() != Compatibility::HDEdition
+            {
+                warnings.push(
+                    Diagnostic::warning(
+                        atom.location,
+                        "Nomad resources requires UserPatch 1.4 or HD Edition",
+                    )
+                    .suggest(Self::wrap_fix(state, atom, "UP_AVAILABLE"))
+                    .suggest(self.header_fix(
+                        state,
+                        atom,
+                        Compatibility::UserPatch14,
+                    )),
+                );
+            }
+
+            let message = match name.value {
                 "actor_area"
                 | "actor_area_to_place_in"
                 | "avoid_actor_area"
                 | "avoid_all_actor_areas"
-                | "actor_area_radius" if state.compatibility() != Compatibility::DefinitiveEdition => {
-                    warnings.push(
-                        Diagnostic::warning(atom.location, "Actor areas are only supported in the Definitive Edition")
-                        .suggest(Fix::new(atom.location,
-                                          "Add a /* Compatibility: Definitive Edition */ comment at the top of the file",)
-                                )
-                        )
+                | "actor_area_radius" => {
+                    Some("Actor areas are only supported in the Definitive Edition".to_string())
                 }
-                "avoid_forest_zone"
-                | "place_on_forest_zone"
-                | "avoid_cliff_zone" if state.compatibility() != Compatibility::DefinitiveEdition => {
-                    warnings.push(
-                        Diagnostic::warning(atom.location, "Forest and cliff zones are only supported in the Definitive Edition")
-                            .suggest(Fix::new(atom.location,
-                                "Add a /* Compatibility: Definitive Edition */ comment at the top of the file",)
-                            )
-                    )
-                }
-                "second_object" if state.compatibility() != Compatibility::DefinitiveEdition => {
-                    warnings.push(
-                        Diagnostic::warning(atom.location, "second_object is only supported in the Definitive Edition")
-                            .suggest(Fix::new(atom.location,
-                                "Add a /* Compatibility: Definitive Edition */ comment at the top of the file",)
-                            )
-                    )
-                }
-                "enable_balanced_elevation" if state.compatibility() != Compatibility::DefinitiveEdition => {
-                    warnings.push(
-                        Diagnostic::warning(atom.location, "enable_balanced_elevation is only supported in the Definitive Edition")
-                            .suggest(Fix::new(atom.location,
-                                "Add a /* Compatibility: Definitive Edition */ comment at the top of the file",)
-                            )
-                    )
-                }
-                "set_gaia_unconvertible" | "set_gaia_civilization" if state.compatibility() != Compatibility::DefinitiveEdition => {
-                    warnings.push(
-                        Diagnostic::warning(atom.location, format_args!("{} is only supported in the Definitive Edition", name.value))
-                            .suggest(Fix::new(atom.location,
-                                "Add a /* Compatibility: Definitive Edition */ comment at the top of the file",)
-                            )
-                    )
-                }
-                _ => (),
+                "avoid_forest_zone" | "place_on_forest_zone" | "avoid_cliff_zone" => Some(
+                    "Forest and cliff zones are only supported in the Definitive Edition"
+                        .to_string(),
+                ),
+                "second_object"
+                | "enable_balanced_elevation"
+                | "set_gaia_unconvertible"
+                | "set_gaia_civilization" => Some(format!(
+                    "{} is only supported in the Definitive Edition",
+                    name.value
+                )),
+                _ => None,
+            };
+            if let Some(message) = message.filter(|_| !is_de) {
+                warnings.push(
+                    Diagnostic::warning(atom.location, message).suggest(self.header_fix(
+                        state,
+                        atom,
+                        Compatibility::DefinitiveEdition,
+                    )),
+                );
             }
         };
 
@@ -138,7 +245,22 @@ impl Lint for CompatibilityLint {
 #[cfg(test)]
 mod tests {
     use super::CompatibilityLint;
-    use crate::{Compatibility, RMSCheck, RMSFile, Severity};
+    use crate::{Compatibility, Fix, RMSCheck, RMSFile, Severity};
+
+    /// Apply the edits of a fix to a source text.
+    fn apply(source: &str, fix: &Fix) -> String {
+        let mut edits = fix.edits().to_vec();
+        edits.sort_by_key(|edit| edit.location().start());
+        let mut result = source.to_string();
+        for edit in edits.iter().rev() {
+            let range = edit.location().range();
+            result.replace_range(
+                usize::from(range.start)..usize::from(range.end),
+                edit.replacement(),
+            );
+        }
+        result
+    }
 
     #[test]
     fn compatibility() -> anyhow::Result<()> {
@@ -190,4 +312,77 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn fixes() {
+        let source = "/* Generated map */\n<PLAYER_SETUP>\n  effect_amount ENABLE_TECH 1 1 1\n";
+        let file = RMSFile::from_string("fixes.rms", source);
+        let result = RMSCheck::new()
+            .with_lint(Box::new(CompatibilityLint::new()))
+            .check(&file);
+
+        let warning = result.iter().next().unwrap();
+        let mut fixes = warning.suggestions();
+        let wrap = fixes.next().unwrap();
+        assert_eq!(
+            wrap.message(),
+            "Wrap this command in an `if UP_EXTENSION` statement"
+        );
+        assert_eq!(
+            apply(source, wrap),
+            "/* Generated map */\n<PLAYER_SETUP>\n  if UP_EXTENSION\n    effect_amount ENABLE_TECH 1 1 1\n  endif\n"
+        );
+        let header = fixes.next().unwrap();
+        assert_eq!(
+            header.message(),
+            "Add a /* Compatibility: UserPatch 1.5 */ comment at the top of the file"
+        );
+        assert_eq!(
+            apply(source, header),
+            "/* Compatibility: UserPatch 1.5 */\n/* Generated map */\n<PLAYER_SETUP>\n  effect_amount ENABLE_TECH 1 1 1\n"
+        );
+        assert!(fixes.next().is_none());
+    }
+
+    #[test]
+    fn crlf_fixes() {
+        let source = "<PLAYER_SETUP>\r\n  effect_amount ENABLE_TECH 1 1 1\r\n";
+        let file = RMSFile::from_string("crlf_fixes.rms", source);
+        let result = RMSCheck::new()
+            .with_lint(Box::new(CompatibilityLint::new()))
+            .check(&file);
+
+        let warning = result.iter().next().unwrap();
+        let mut fixes = warning.suggestions();
+        assert_eq!(
+            apply(source, fixes.next().unwrap()),
+            "<PLAYER_SETUP>\r\n  if UP_EXTENSION\r\n    effect_amount ENABLE_TECH 1 1 1\r\n  endif\r\n"
+        );
+        assert_eq!(
+            apply(source, fixes.next().unwrap()),
+            "/* Compatibility: UserPatch 1.5 */\r\n<PLAYER_SETUP>\r\n  effect_amount ENABLE_TECH 1 1 1\r\n"
+        );
+    }
+
+    #[test]
+    fn change_header() {
+        let source = "/*\n * Compatibility: Conquerors\n */\n<OBJECTS_GENERATION>\ncreate_object VILLAGER { second_object HORSE }\n";
+        let file = RMSFile::from_string("change_header.rms", source);
+        let result = RMSCheck::new()
+            .with_lint(Box::new(CompatibilityLint::new()))
+            .check(&file);
+
+        let warning = result.iter().next().unwrap();
+        let mut fixes = warning.suggestions();
+        let header = fixes.next().unwrap();
+        assert_eq!(
+            header.message(),
+            "Change the `Compatibility:` header to Definitive Edition"
+        );
+        assert_eq!(
+            apply(source, header),
+            "/*\n * Compatibility: Definitive Edition\n */\n<OBJECTS_GENERATION>\ncreate_object VILLAGER { second_object HORSE }\n"
+        );
+        assert!(fixes.next().is_none());
+    }
 }
@@ -0,0 +1,130 @@
+use crate::diagnostic::{Diagnostic, Label};
+use crate::{Atom, AtomKind, Lint, ParseState};
+
+#[derive(Default, Clone)]
+pub struct ConstRedefinitionLint {}
+
+impl ConstRedefinitionLint {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl Lint for ConstRedefinitionLint {
+    fn name(&self) -> &'static str {
+        "const-redefinition"
+    }
+
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
+    fn lint_atom(&mut self, state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
+        let (name, value) = match &atom.kind {
+            AtomKind::Const {
+                name,
+                value: Some(value),
+                ..
+            } => (name, value),
+            _ => return vec![],
+        };
+        let new_value = value.value.parse::<i32>().ok();
+
+        if let Some(previous) = state.get_const(name.value) {
+            if !state.is_in_scope(previous) {
+                return vec![];
+            }
+            let previous_value = match previous.value() {
+                Some(previous_value) => previous_value,
+                None => return vec![],
+            };
+            let is_same = match (previous.resolved_value(), new_value) {
+                (Some(a), Some(b)) => a == b,
+                _ => previous_value.value == value.value,
+            };
+            if !is_same {
+                return vec![Diagnostic::warning(
+                    value.location,
+                    format_args!(
+                        "Redefining `{}` with a different value: it was {}, it is now {}",
+                        name.value, previous_value.value, value.value
+                    ),
+                )
+                .add_label(Label::new(previous.location(), "Previously defined here"))];
+            }
+        } else if let Some(builtin_value) = state.resolve_builtin_const(name.value) {
+            if new_value != Some(builtin_value) {
+                return vec![Diagnostic::warning(
+                    value.location,
+                    format_args!(
+                        "Redefining builtin `{}` with a different value: it was {}, it is now {}",
+                        name.value, builtin_value, value.value
+                    ),
+                )];
+            }
+        }
+
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RMSCheck, RMSFile, Severity};
+
+    #[test]
+    fn const_redefinition() {
+        let file = RMSFile::from_string(
+            "const_redefinition.rms",
+            "#const A 1
+            #const A 1
+            #const A 2
+            if TINY_MAP
+              #const B 1
+            else
+              #const B 2
+            endif
+            start_random
+              percent_chance 50 #const C 1
+              percent_chance 50 #const C 2
+            end_random
+            #const GOLD 66
+            #const GOLD 5",
+        );
+        let result = RMSCheck::new()
+            .with_lint(Box::new(ConstRedefinitionLint::new()))
+            .check(&file);
+        let mut warnings = result.iter();
+
+        let first = warnings.next().unwrap();
+        assert_eq!(first.severity(), Severity::Warning);
+        assert_eq!(first.code(), Some("const-redefinition"));
+        assert_eq!(
+            first.message(),
+            "Redefining `A` with a different value: it was 1, it is now 2"
+        );
+        assert_eq!(first.labels().count(), 1);
+        let second = warnings.next().unwrap();
+        assert_eq!(
+            second.message(),
+            "Redefining `GOLD` with a different value: it was 66, it is now 5"
+        );
+        assert!(warnings.next().is_none());
+    }
+
+    #[test]
+    fn builtin_redefinition() {
+        let file = RMSFile::from_string("builtin_redefinition.rms", "#const GOLD 5");
+        let result = RMSCheck::new()
+            .with_lint(Box::new(ConstRedefinitionLint::new()))
+            .check(&file);
+        let mut warnings = result.iter();
+
+        let first = warnings.next().unwrap();
+        assert_eq!(
+            first.message(),
+            "Redefining builtin `GOLD` with a different value: it was 66, it is now 5"
+        );
+        assert!(warnings.next().is_none());
+    }
+}
@@ -1,7 +1,7 @@
 use crate::diagnostic::{Diagnostic, Fix};
 use crate::{Atom, AtomKind, Lint, ParseState};
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct IncludeLint {}
 
 impl IncludeLint {
@@ -14,24 +14,55 @@ impl Lint for IncludeLint {
     fn name(&self) -> &'static str {
         "include"
     }
-    fn lint_atom(&mut self, _state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
-        match atom.kind {
-            AtomKind::Command { name, .. } if name.value == "#include_drs" => {
-                vec![Diagnostic::error(
-                    atom.location,
-                    "#include_drs can only be used by builtin maps",
-                )]
+
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
+    fn lint_atom(&mut self, state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
+        let (name, included) = match &atom.kind {
+            AtomKind::Command { name, arguments }
+                if name.value == "#include_drs" || name.value == "#include" =>
+            {
+                (name.value, arguments.first().map(|file| file.value))
+            }
+            _ => return Default::default(),
+        };
+
+        // ZR@ maps can include other files from the archive, and builtin maps can include files
+        // from the include directory.
+        if let Some(file) = included {
+            if state.rms.find_include(file).is_some() {
+                return Default::default();
             }
-            AtomKind::Command { name, .. } if name.value == "#include" => {
-                vec![
-                    Diagnostic::error(atom.location, "#include can only be used by builtin maps")
-                        .suggest(Fix::new(
+        }
+
+        if state.is_builtin_map {
+            return match included {
+                Some("random_map.def") if name == "#include_drs" => Default::default(),
+                Some(file) => vec![Diagnostic::warning(
+                    atom.location,
+                    format_args!(
+                        "Could not find included file `{}`, so its contents are not checked",
+                        file
+                    ),
+                )],
+                None => Default::default(),
+            };
+        }
+
+        if name == "#include_drs" {
+            vec![Diagnostic::error(
+                atom.location,
+                "#include_drs can only be used by builtin maps",
+            )]
+        } else {
+            vec![
+                Diagnostic::error(atom.location, "#include can only be used by builtin maps")
+                    .suggest(Fix::new(
                         atom.location,
                         "If you're trying to make a map pack, use a map pack generator instead.",
                     )),
-                ]
-            }
-            _ => Default::default(),
+            ]
         }
     }
 }
@@ -76,4 +107,124 @@ mod tests {
             SourceLocation::new(file, ByteIndex::from(37)..ByteIndex::from(53))
         );
     }
+
+    #[test]
+    fn zip_rms_include() {
+        use std::io::Write;
+        use zip::write::{FileOptions, ZipWriter};
+
+        let mut bytes = vec![];
+        {
+            let mut zip = ZipWriter::new(std::io::Cursor::new(&mut bytes));
+            let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+            zip.start_file("ZR@test.rms", options).unwrap();
+            zip.write_all(b"#include_drs land.inc\n#include_drs missing.inc\n")
+                .unwrap();
+            zip.start_file("land.inc", options).unwrap();
+            zip.write_all(b"#include other.inc\n").unwrap();
+            zip.finish().unwrap();
+        }
+
+        let file = RMSFile::from_zip_rms("ZR@test.rms", &bytes).unwrap();
+        let result = RMSCheck::new()
+            .with_lint(Box::new(IncludeLint::new()))
+            .check(&file);
+
+        // Included files are checked where they are included.
+        let mut warnings = result.iter();
+        let first = warnings.next().unwrap();
+        let second = warnings.next().unwrap();
+        assert!(warnings.next().is_none());
+        assert_eq!(first.message(), "#include can only be used by builtin maps");
+        assert_eq!(
+            first.location().file(),
+            file.find_file_id("land.inc").unwrap()
+        );
+        assert_eq!(
+            second.message(),
+            "#include_drs can only be used by builtin maps"
+        );
+        assert_eq!(second.location().file(), file.file_id());
+    }
+
+    #[test]
+    fn builtin_map() {
+        use crate::lints::ArgTypesLint;
+
+        let source = "#include_drs random_map.def 54000
+            #include land.inc
+            <OBJECTS_GENERATION>
+            create_object GOLD { }";
+        let file = RMSFile::from_string("builtin.rms", source);
+        let result = RMSCheck::new()
+            .builtin_map(true)
+            .with_lint(Box::new(ArgTypesLint::new()))
+            .with_lint(Box::new(IncludeLint::new()))
+            .check(&file);
+        let mut warnings = result.iter();
+        let first = warnings.next().unwrap();
+        assert_eq!(first.severity(), Severity::Warning);
+        assert_eq!(
+            first.message(),
+            "Could not find included file `land.inc`, so its contents are not checked"
+        );
+        assert!(warnings.next().is_none());
+
+        // Builtin consts are only available after including the definitions.
+        let file = RMSFile::from_string("builtin.rms", &source[34..]);
+        let result = RMSCheck::new()
+            .builtin_map(true)
+            .with_lint(Box::new(ArgTypesLint::new()))
+            .with_lint(Box::new(IncludeLint::new()))
+            .check(&file);
+        assert_eq!(
+            result
+                .iter()
+                .filter(|w| w.code() == Some("arg-types"))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn builtin_map_header() {
+        let file = RMSFile::from_string(
+            "builtin.rms",
+            "/* Builtin map: yes */\n#include_drs random_map.def\n",
+        );
+        let result = RMSCheck::new()
+            .with_lint(Box::new(IncludeLint::new()))
+            .check(&file);
+        assert!(result.iter().next().is_none());
+    }
+
+    #[test]
+    fn load_includes() {
+        let dir = std::env::temp_dir().join(format!("rms-check-includes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("land.inc"), "#include missing.inc\n").unwrap();
+
+        let mut file = RMSFile::from_string(
+            "builtin.rms",
+            "#include_drs random_map.def\n#include land.inc\n",
+        );
+        file.load_includes(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let result = RMSCheck::new()
+            .builtin_map(true)
+            .with_lint(Box::new(IncludeLint::new()))
+            .check(&file);
+        let mut warnings = result.iter();
+        let first = warnings.next().unwrap();
+        assert_eq!(
+            first.message(),
+            "Could not find included file `missing.inc`, so its contents are not checked"
+        );
+        assert_eq!(
+            first.location().file(),
+            file.find_include("land.inc").unwrap()
+        );
+        assert!(warnings.next().is_none());
+    }
 }
@@ -2,7 +2,7 @@ use crate::diagnostic::{Diagnostic, Label};
 use crate::{Atom, AtomKind, Lint, ParseState, TokenContext, TOKENS};
 use cow_utils::CowUtils;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct IncorrectSectionLint {}
 
 impl IncorrectSectionLint {
@@ -16,6 +16,10 @@ impl Lint for IncorrectSectionLint {
         "incorrect-section"
     }
 
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
+
     fn lint_atom(&mut self, state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
         if let AtomKind::Command { name, .. } = atom.kind {
             let token_type = &TOKENS[name.value.cow_to_ascii_lowercase().as_ref()];
@@ -3,15 +3,21 @@ mod arg_types;
 mod attribute_case;
 mod comment_contents;
 mod compatibility;
+mod const_redefinition;
 mod include;
 mod incorrect_section;
+mod percent_chance;
 mod unknown_attribute;
+mod unused_declaration;
 
 pub use self::actor_areas_match::ActorAreasMatchLint;
 pub use self::arg_types::ArgTypesLint;
 pub use self::attribute_case::AttributeCaseLint;
 pub use self::comment_contents::CommentContentsLint;
 pub use self::compatibility::CompatibilityLint;
+pub use self::const_redefinition::ConstRedefinitionLint;
 pub use self::include::IncludeLint;
 pub use self::incorrect_section::IncorrectSectionLint;
+pub use self::percent_chance::PercentChanceLint;
 pub use self::unknown_attribute::UnknownAttributeLint;
+pub use self::unused_declaration::UnusedDeclarationLint;
@@ -0,0 +1,327 @@
+use crate::diagnostic::{ByteIndex, Diagnostic, FileId, Label, SourceLocation};
+use crate::random::{RandomStatement, RandomTree};
+use crate::{Atom, AtomKind, Lint, Node, ParseState, SyntaxTree, Word};
+use std::collections::HashMap;
+
+#[derive(Default, Clone)]
+pub struct PercentChanceLint {
+    /// The locations of the `start_random` atoms, by the file and offset where they start.
+    starts: HashMap<(FileId, ByteIndex), SourceLocation>,
+    /// The values of chances that use `#const` names, resolved where they are used.
+    chances: HashMap<SourceLocation, i32>,
+}
+
+impl PercentChanceLint {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Check the total of a list of `percent_chance` branches. `start` is the `start_random`
+    /// statement they belong to, and `head` is the `if` branch that contains them, if any.
+    fn check_chances(
+        &self,
+        start: SourceLocation,
+        head: Option<SourceLocation>,
+        chances: &[(SourceLocation, Option<i32>)],
+        warnings: &mut Vec<Diagnostic>,
+    ) {
+        let what = if head.is_some() {
+            "this branch of the `if` in this `start_random`"
+        } else {
+            "this `start_random`"
+        };
+
+        // Chances that could not be resolved are skipped, so the total is a lower bound.
+        let mut total = 0i64;
+        let mut unresolved = false;
+        for &(location, chance) in chances {
+            match chance {
+                Some(chance) => total += i64::from(chance),
+                None => unresolved = true,
+            }
+            if chance.is_some() && total > 100 {
+                warnings.push(
+                    Diagnostic::warning(
+                        location,
+                        format_args!(
+                            "The total chance in {} is over 100% ({}{}%), so this branch is partially or entirely unreachable",
+                            what,
+                            if unresolved { "at least " } else { "" },
+                            total
+                        ),
+                    )
+                    .add_label(Label::new(start, "Statement starts here")),
+                );
+                break;
+            }
+        }
+
+        if !chances.is_empty() && !unresolved && total < 100 {
+            warnings.push(Diagnostic::hint(
+                head.unwrap_or(start),
+                format_args!(
+                    "The total chance in {} is {}%, so there is a {}% chance that none of the branches run",
+                    what,
+                    total,
+                    100 - total
+                ),
+            ));
+        }
+    }
+
+    fn check_statement(&self, statement: &RandomStatement, warnings: &mut Vec<Diagnostic>) {
+        let key = (statement.location.file(), statement.location.start());
+        let start = self.starts.get(&key).copied().unwrap_or(statement.location);
+        let chances: Vec<_> = statement
+            .outcomes
+            .iter()
+            .map(|outcome| (outcome.location, outcome.chance))
+            .collect();
+        self.check_chances(start, None, &chances, warnings);
+
+        for outcome in &statement.outcomes {
+            for child in &outcome.children {
+                self.check_statement(child, warnings);
+            }
+        }
+    }
+
+    /// Check `percent_chance` branches inside `if` statements at the start of a `start_random`.
+    /// Only one branch of the `if` runs, so the total of each branch is checked separately.
+    ///
+    /// `start` is the innermost `start_random` whose first `percent_chance` has not been seen yet.
+    fn check_conditions(
+        &self,
+        nodes: &[Node<'_>],
+        start: Option<SourceLocation>,
+        warnings: &mut Vec<Diagnostic>,
+    ) {
+        for node in nodes {
+            match node {
+                Node::Section(section) => self.check_conditions(&section.children, None, warnings),
+                Node::Command(command) => {
+                    if let Some(block) = &command.block {
+                        self.check_conditions(&block.children, None, warnings);
+                    }
+                }
+                Node::Block(block) => self.check_conditions(&block.children, None, warnings),
+                Node::Condition(condition) => {
+                    for branch in &condition.branches {
+                        if let Some(start) = start {
+                            let chances: Vec<_> = branch
+                                .children
+                                .iter()
+                                .filter_map(|node| match node {
+                                    Node::Atom(token) => match token.kind() {
+                                        AtomKind::PercentChance { chance, .. } => {
+                                            Some((token.location(), self.resolve(chance)))
+                                        }
+                                        _ => None,
+                                    },
+                                    _ => None,
+                                })
+                                .collect();
+                            self.check_chances(
+                                start,
+                                Some(branch.head.location()),
+                                &chances,
+                                warnings,
+                            );
+                        }
+                        self.check_conditions(&branch.children, start, warnings);
+                    }
+                }
+                Node::Random(random) => {
+                    self.check_conditions(
+                        &random.children,
+                        Some(random.start.location()),
+                        warnings,
+                    );
+                    for branch in &random.branches {
+                        self.check_conditions(&branch.children, None, warnings);
+                    }
+                }
+                Node::Atom(_) => (),
+            }
+        }
+    }
+
+    fn resolve(&self, chance: &Word<'_>) -> Option<i32> {
+        chance
+            .value
+            .parse()
+            .ok()
+            .or_else(|| self.chances.get(&chance.location).copied())
+    }
+}
+
+impl Lint for PercentChanceLint {
+    fn name(&self) -> &'static str {
+        "percent-chance"
+    }
+
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
+    fn lint_atom(&mut self, state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
+        match &atom.kind {
+            AtomKind::StartRandom { .. } => {
+                let key = (atom.location.file(), atom.location.start());
+                self.starts.insert(key, atom.location);
+            }
+            // `#const` values can change throughout the script, so chances are resolved where
+            // they are used.
+            AtomKind::PercentChance { chance, .. } => {
+                if let Some(value) = state.resolve_number(chance.value) {
+                    self.chances.insert(chance.location, value);
+                }
+            }
+            _ => (),
+        }
+        vec![]
+    }
+    fn lint_end(&mut self, state: &mut ParseState<'_>) -> Vec<Diagnostic> {
+        let mut warnings = vec![];
+        for file in state.rms.script_files() {
+            let tree = SyntaxTree::parse(file, state.rms.source(file));
+            let random = RandomTree::from_syntax_tree_with(&tree, |word| {
+                self.chances.get(&word.location).copied()
+            });
+            for statement in &random.statements {
+                self.check_statement(statement, &mut warnings);
+            }
+            self.check_conditions(&tree.nodes, None, &mut warnings);
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RMSCheck, RMSFile, Severity};
+
+    #[test]
+    fn percent_chance() {
+        let file = RMSFile::from_string(
+            "percent_chance.rms",
+            "#const HALF 50
+            start_random
+              percent_chance 60
+              percent_chance 30
+              percent_chance 20
+              percent_chance 10
+            end_random
+            start_random
+              percent_chance HALF
+              percent_chance 40
+            end_random
+            start_random
+              percent_chance HALF
+              percent_chance HALF
+            end_random
+            start_random
+              percent_chance 50
+              percent_chance UNKNOWN
+            end_random
+            start_random
+            end_random",
+        );
+        let result = RMSCheck::new()
+            .with_lint(Box::new(PercentChanceLint::new()))
+            .check(&file);
+        let mut warnings = result.iter();
+
+        let first = warnings.next().unwrap();
+        assert_eq!(first.severity(), Severity::Warning);
+        assert_eq!(first.code(), Some("percent-chance"));
+        assert_eq!(first.message(), "The total chance in this `start_random` is over 100% (110%), so this branch is partially or entirely unreachable");
+        assert_eq!(
+            &file.main_source()
+                [first.location().range().start.into()..first.location().range().end.into()],
+            "percent_chance 20"
+        );
+        let label = first.labels().next().unwrap();
+        assert_eq!(
+            &file.main_source()
+                [label.location().range().start.into()..label.location().range().end.into()],
+            "start_random"
+        );
+        let second = warnings.next().unwrap();
+        assert_eq!(second.severity(), Severity::Hint);
+        assert_eq!(second.code(), Some("percent-chance"));
+        assert_eq!(second.message(), "The total chance in this `start_random` is 90%, so there is a 10% chance that none of the branches run");
+        assert!(warnings.next().is_none());
+    }
+
+    #[test]
+    fn unresolved_and_nested() {
+        let file = RMSFile::from_string(
+            "percent_chance.rms",
+            "start_random
+              percent_chance UNKNOWN
+              percent_chance 60
+              percent_chance 50
+            end_random
+            start_random
+              percent_chance 100
+                start_random percent_chance 30 end_random
+            end_random",
+        );
+        let result = RMSCheck::new()
+            .with_lint(Box::new(PercentChanceLint::new()))
+            .check(&file);
+        let mut warnings = result.iter();
+
+        let first = warnings.next().unwrap();
+        assert_eq!(first.severity(), Severity::Warning);
+        assert_eq!(first.message(), "The total chance in this `start_random` is over 100% (at least 110%), so this branch is partially or entirely unreachable");
+        let second = warnings.next().unwrap();
+        assert_eq!(second.severity(), Severity::Hint);
+        assert_eq!(second.message(), "The total chance in this `start_random` is 30%, so there is a 70% chance that none of the branches run");
+        assert!(warnings.next().is_none());
+    }
+
+    #[test]
+    fn if_inside_start_random() {
+        // Only one of the branches of the `if` runs, so each branch has its own total.
+        let file = RMSFile::from_string(
+            "percent_chance.rms",
+            "start_random
+              if TINY_MAP
+                percent_chance 60 #define SMALL_LAKE
+                percent_chance 40 #define NO_LAKE
+              elseif SMALL_MAP
+                percent_chance 30 #define SMALL_LAKE
+              else
+                percent_chance 60 #define BIG_LAKE
+                percent_chance 50 #define NO_LAKE
+              endif
+            end_random",
+        );
+        let result = RMSCheck::new()
+            .with_lint(Box::new(PercentChanceLint::new()))
+            .check(&file);
+        let mut warnings = result
+            .iter()
+            .filter(|diagnostic| diagnostic.code() == Some("percent-chance"));
+
+        let first = warnings.next().unwrap();
+        assert_eq!(first.severity(), Severity::Hint);
+        assert_eq!(first.message(), "The total chance in this branch of the `if` in this `start_random` is 30%, so there is a 70% chance that none of the branches run");
+        assert_eq!(
+            &file.main_source()
+                [first.location().range().start.into()..first.location().range().end.into()],
+            "elseif SMALL_MAP"
+        );
+        let second = warnings.next().unwrap();
+        assert_eq!(second.severity(), Severity::Warning);
+        assert_eq!(second.message(), "The total chance in this branch of the `if` in this `start_random` is over 100% (110%), so this branch is partially or entirely unreachable");
+        assert_eq!(
+            &file.main_source()
+                [second.location().range().start.into()..second.location().range().end.into()],
+            "percent_chance 50"
+        );
+        assert!(warnings.next().is_none());
+    }
+}
@@ -3,11 +3,16 @@ use crate::{Atom, AtomKind, Lint, ParseState, TOKENS};
 use strsim::jaro_winkler;
 
 #[allow(unused)]
+#[derive(Clone)]
 pub struct UnknownAttributeLint {}
 impl Lint for UnknownAttributeLint {
     fn name(&self) -> &'static str {
         "unknown-attribute"
     }
+
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
     fn lint_atom(&mut self, _state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
         match atom.kind {
             // Treat unrecognised tokens as attributes, if they are not numbers
@@ -0,0 +1,158 @@
+use crate::diagnostic::{ByteIndex, Diagnostic, Fix, SourceLocation};
+use crate::{Atom, AtomKind, Lint, ParseState, PositionEncoding};
+use std::collections::HashSet;
+
+#[derive(Clone)]
+struct Declaration {
+    kind: &'static str,
+    name: String,
+    name_location: SourceLocation,
+    /// The location of the whole declaration atom.
+    location: SourceLocation,
+}
+
+#[derive(Default, Clone)]
+pub struct UnusedDeclarationLint {
+    declarations: Vec<Declaration>,
+    references: HashSet<String>,
+}
+
+impl UnusedDeclarationLint {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn reference(&mut self, value: &str) {
+        // Arguments can contain expressions like `rnd(1,MAX_SIZE)`.
+        for name in value.split(|c: char| !c.is_ascii_alphanumeric() && c != '_') {
+            if !name.is_empty() {
+                self.references.insert(name.to_string());
+            }
+        }
+    }
+}
+
+/// Get the location of the whole line containing `location`, including the line break, if the
+/// line contains nothing else.
+fn line_location(state: &ParseState<'_>, location: SourceLocation) -> SourceLocation {
+    let rms = state.rms;
+    let file = location.file();
+    let source = rms.source(file);
+    let line = match rms.get_location(file, location.start(), PositionEncoding::Utf8) {
+        Some((line, _)) => line,
+        None => return location,
+    };
+    let start = rms
+        .get_byte_index(file, line, 0, PositionEncoding::Utf8)
+        .unwrap_or_else(|| location.start());
+    let end = rms
+        .get_byte_index(file, line + 1, 0, PositionEncoding::Utf8)
+        .unwrap_or_else(|| ByteIndex::from(source.len()));
+
+    let before = &source[usize::from(start)..usize::from(location.start())];
+    let after = &source[usize::from(location.end())..usize::from(end)];
+    if before.trim().is_empty() && after.trim().is_empty() {
+        SourceLocation::new(file, start..end)
+    } else {
+        location
+    }
+}
+
+impl Lint for UnusedDeclarationLint {
+    fn name(&self) -> &'static str {
+        "unused-declaration"
+    }
+
+    fn clone_lint(&self) -> Option<Box<dyn Lint + Send>> {
+        Some(Box::new(self.clone()))
+    }
+    fn lint_atom(&mut self, state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
+        // Only report declarations in the map's own scripts, not in included definitions files.
+        let is_script = state
+            .rms
+            .script_files()
+            .any(|file| file == atom.location.file());
+        match &atom.kind {
+            AtomKind::Const { name, .. } if is_script => self.declarations.push(Declaration {
+                kind: "#const",
+                name: name.value.to_string(),
+                name_location: name.location,
+                location: atom.location,
+            }),
+            AtomKind::Define { name, .. } if is_script => self.declarations.push(Declaration {
+                kind: "#define",
+                name: name.value.to_string(),
+                name_location: name.location,
+                location: atom.location,
+            }),
+            AtomKind::If { condition, .. } | AtomKind::ElseIf { condition, .. } => {
+                self.reference(condition.value);
+            }
+            AtomKind::PercentChance { chance, .. } => self.reference(chance.value),
+            AtomKind::Command { arguments, .. } => {
+                for argument in arguments {
+                    self.reference(argument.value);
+                }
+            }
+            _ => (),
+        }
+        vec![]
+    }
+    fn lint_end(&mut self, state: &mut ParseState<'_>) -> Vec<Diagnostic> {
+        self.declarations
+            .iter()
+            .filter(|declaration| !self.references.contains(&declaration.name))
+            .map(|declaration| {
+                Diagnostic::warning(
+                    declaration.name_location,
+                    format_args!("`{} {}` is never used", declaration.kind, declaration.name),
+                )
+                .suggest(
+                    Fix::new(
+                        line_location(state, declaration.location),
+                        "Remove the unused declaration",
+                    )
+                    .replace(""),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{RMSCheck, RMSFile, Severity};
+
+    #[test]
+    fn unused_declaration() {
+        let file = RMSFile::from_string(
+            "unused_declaration.rms",
+            "#const USED_CONST 1\n#const UNUSED_CONST 2\n#define USED_DEFINE\n#define UNUSED_DEFINE #const CHANCE 50\nif USED_DEFINE\n  create_object USED_CONST { number_of_objects rnd(1,MAX) }\nendif\nstart_random percent_chance CHANCE end_random\n#const MAX 3\n",
+        );
+        let result = RMSCheck::new()
+            .with_lint(Box::new(UnusedDeclarationLint::new()))
+            .check(&file);
+        let mut warnings = result.iter();
+
+        let first = warnings.next().unwrap();
+        assert_eq!(first.severity(), Severity::Warning);
+        assert_eq!(first.code(), Some("unused-declaration"));
+        assert_eq!(first.message(), "`#const UNUSED_CONST` is never used");
+        let fix = first.suggestions().next().unwrap();
+        assert_eq!(fix.replacement(), Some(""));
+        assert_eq!(
+            fix.location(),
+            SourceLocation::new(file.file_id(), ByteIndex::from(20)..ByteIndex::from(42))
+        );
+
+        let second = warnings.next().unwrap();
+        assert_eq!(second.message(), "`#define UNUSED_DEFINE` is never used");
+        let fix = second.suggestions().next().unwrap();
+        assert_eq!(
+            fix.location(),
+            SourceLocation::new(file.file_id(), ByteIndex::from(62)..ByteIndex::from(83))
+        );
+        assert!(warnings.next().is_none());
+    }
+}
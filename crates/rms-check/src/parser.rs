@@ -177,6 +177,16 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Create a new parser that starts at byte offset `start` in the given source code. `start`
+    /// should be the beginning of an atom; the locations of parsed `Atom`s are still relative to
+    /// the start of `source`.
+    pub fn starting_at(file_id: FileId, source: &'a str, start: ByteIndex) -> Self {
+        Parser {
+            source,
+            iter: itertools::multipeek(Tokenizer::starting_at(file_id, source, start)),
+        }
+    }
+
     /// Take a slice of the source code.
     fn slice(&self, range: impl RangeBounds<ByteIndex>) -> String {
         use std::ops::Bound::*;
@@ -186,7 +196,7 @@ impl<'a> Parser<'a> {
             Excluded(n) => *n + 1,
         };
         let end = match range.end_bound() {
-            Unbounded => ByteIndex::from(self.source.as_bytes().len()),
+            Unbounded => ByteIndex::from(self.source.len()),
             Included(n) => *n,
             Excluded(n) => *n - 1,
         };
@@ -195,10 +205,7 @@ impl<'a> Parser<'a> {
 
     /// Check if the next word could be a command argument. If yes, return it; else return None.
     fn peek_arg(&mut self) -> Option<&Word<'a>> {
-        let token = match self.iter.peek() {
-            Some(token) => token,
-            None => return None,
-        };
+        let token = self.iter.peek()?;
 
         // Things that should never be args
         match token.value {
@@ -314,10 +321,7 @@ impl<'a> Parser<'a> {
 impl<'a> Iterator for Parser<'a> {
     type Item = (Atom<'a>, Vec<ParseError>);
     fn next(&mut self) -> Option<Self::Item> {
-        let word = match self.iter.next() {
-            Some(word) => word,
-            None => return None,
-        };
+        let word = self.iter.next()?;
 
         let t = |atom| Some((atom, vec![]));
 
@@ -507,7 +511,7 @@ mod tests {
             assert_eq!(value.unwrap().value, "B");
             assert!(warnings.is_empty());
         } else {
-            assert!(false);
+            unreachable!();
         }
     }
 
@@ -523,7 +527,7 @@ mod tests {
             assert_eq!(warnings.len(), 1);
             assert_eq!(warnings[0].kind, ParseErrorKind::MissingConstValue);
         } else {
-            assert!(false);
+            unreachable!();
         }
     }
 
@@ -537,7 +541,7 @@ mod tests {
             assert_eq!(warnings.len(), 1);
             assert_eq!(warnings[0].kind, ParseErrorKind::MissingConstName);
         } else {
-            assert!(false);
+            unreachable!();
         }
     }
 
@@ -551,7 +555,7 @@ mod tests {
             assert_eq!(name.value, "B");
             assert!(warnings.is_empty());
         } else {
-            assert!(false);
+            unreachable!();
         }
     }
 
@@ -565,7 +569,7 @@ mod tests {
             assert_eq!(warnings.len(), 1);
             assert_eq!(warnings[0].kind, ParseErrorKind::MissingDefineName);
         } else {
-            assert!(false);
+            unreachable!();
         }
     }
 
@@ -580,7 +584,7 @@ mod tests {
             assert!(arguments.is_empty());
             assert!(warnings.is_empty());
         } else {
-            assert!(false);
+            unreachable!();
         }
     }
 
@@ -596,14 +600,14 @@ mod tests {
             assert_eq!(arguments[0].value, "10");
             assert!(warnings.is_empty());
         } else {
-            assert!(false);
+            unreachable!();
         }
         if let (AtomKind::Command { name, arguments }, warnings) = &atoms[1] {
             assert_eq!(name.value, "grouped_by_team");
             assert!(arguments.is_empty());
             assert!(warnings.is_empty());
         } else {
-            assert!(false);
+            unreachable!();
         }
     }
 
@@ -620,14 +624,14 @@ mod tests {
             assert_eq!(arguments[0].value, "10");
             assert!(warnings.is_empty());
         } else {
-            assert!(false);
+            unreachable!();
         }
         if let (AtomKind::Command { name, arguments }, warnings) = &atoms[1] {
             assert_eq!(name.value, "grouped_BY_team");
             assert!(arguments.is_empty());
             assert!(warnings.is_empty());
         } else {
-            assert!(false);
+            unreachable!();
         }
     }
 
@@ -642,24 +646,24 @@ mod tests {
             assert_eq!(arguments.len(), 1);
             assert_eq!(arguments[0].value, "SNOW");
         } else {
-            assert!(false)
+            unreachable!()
         }
         if let (AtomKind::OpenBlock { head }, _) = atoms.remove(0) {
             assert_eq!(head.value, "{");
         } else {
-            assert!(false)
+            unreachable!()
         }
         if let (AtomKind::Command { name, arguments }, _) = atoms.remove(0) {
             assert_eq!(name.value, "base_size");
             assert_eq!(arguments.len(), 1);
             assert_eq!(arguments[0].value, "15");
         } else {
-            assert!(false)
+            unreachable!()
         }
         if let (AtomKind::CloseBlock { head }, _) = atoms.remove(0) {
             assert_eq!(head.value, "}");
         } else {
-            assert!(false)
+            unreachable!()
         }
     }
 
@@ -675,7 +679,7 @@ mod tests {
         if let (AtomKind::Command { .. }, _) = atoms.remove(0) {
             // ok
         } else {
-            assert!(false)
+            unreachable!()
         }
         if let (
             AtomKind::Comment {
@@ -690,17 +694,17 @@ mod tests {
             assert_eq!(content, " this is a comment ");
             assert_eq!(close.unwrap().value, "*/");
         } else {
-            assert!(false)
+            unreachable!()
         }
         if let (AtomKind::OpenBlock { head }, _) = atoms.remove(0) {
             assert_eq!(head.value, "{");
         } else {
-            assert!(false)
+            unreachable!()
         }
         if let (AtomKind::CloseBlock { head }, _) = atoms.remove(0) {
             assert_eq!(head.value, "}");
         } else {
-            assert!(false)
+            unreachable!()
         }
     }
 
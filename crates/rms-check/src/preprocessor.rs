@@ -0,0 +1,403 @@
+//! Evaluates `#define`, `#undefine` and `if` statements for a specific lobby setup.
+
+use crate::checker::is_lobby_define;
+use crate::diagnostic::{ByteIndex, SourceLocation};
+use crate::parser::AtomKind;
+use crate::syntax_tree::{BlockNode, ConditionNode, Node, SyntaxTree, Token, Trivia};
+use crate::{Compatibility, RMSFile};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// Whether a `#define` name is set at some point in the script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Defined {
+    Yes,
+    No,
+    /// The name depends on something we can't resolve ahead of time, like a `start_random`.
+    Maybe,
+}
+
+/// Maps a range of preprocessed source code to the original source code it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMapping {
+    /// The byte range in the preprocessed source.
+    pub output: Range<ByteIndex>,
+    /// The location in the original script.
+    pub original: SourceLocation,
+}
+
+/// A script with only the branches that are live for a specific lobby setup.
+#[derive(Debug, Clone)]
+pub struct PreprocessedScript {
+    source: String,
+    mappings: Vec<SourceMapping>,
+}
+
+impl PreprocessedScript {
+    /// Get the preprocessed source code.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Get the source mappings, ordered by their position in the preprocessed source.
+    pub fn mappings(&self) -> &[SourceMapping] {
+        &self.mappings
+    }
+
+    /// Find the location in the original script that a byte index in the preprocessed source
+    /// came from.
+    pub fn original_location(&self, index: ByteIndex) -> Option<SourceLocation> {
+        let position = self
+            .mappings
+            .partition_point(|mapping| mapping.output.end <= index);
+        let mapping = self.mappings.get(position)?;
+        if index < mapping.output.start {
+            return None;
+        }
+
+        let offset = usize::from(index) - usize::from(mapping.output.start);
+        let start = (mapping.original.start() + offset as isize).min(mapping.original.end());
+        Some(SourceLocation::new(
+            mapping.original.file(),
+            start..mapping.original.end(),
+        ))
+    }
+}
+
+/// Resolves `if` branches in a script for a given set of active lobby defines, like the ones
+/// the game sets for the map size (`LARGE_MAP`), player count (`4_PLAYER_GAME`), teams
+/// (`TEAM1_SIZE2`) and UserPatch support (`UP_EXTENSION`).
+///
+/// `start_random` statements are kept as is. If a condition depends on a `#define` that happens
+/// inside a `start_random` statement, the `if` statement is kept too.
+///
+/// ## Example
+/// ```rust
+/// use rms_check::{Preprocessor, RMSFile};
+/// let file = RMSFile::from_string("sizes.rms", "if TINY_MAP #const SIZE 1 elseif LARGE_MAP #const SIZE 4 endif");
+/// let result = Preprocessor::new().define("LARGE_MAP").preprocess(&file);
+/// assert_eq!(result.source(), " #const SIZE 4");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Preprocessor {
+    defines: HashSet<String>,
+}
+
+impl Preprocessor {
+    /// Create a preprocessor without any active defines.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an active `#define` name.
+    pub fn define(mut self, name: impl ToString) -> Self {
+        self.defines.insert(name.to_string());
+        self
+    }
+
+    /// Add several active `#define` names.
+    pub fn defines(mut self, names: impl IntoIterator<Item = impl ToString>) -> Self {
+        self.defines
+            .extend(names.into_iter().map(|name| name.to_string()));
+        self
+    }
+
+    /// Add the defines that the game sets for a compatibility level, like `UP_EXTENSION` for
+    /// UserPatch 1.5.
+    pub fn compatibility(self, compatibility: Compatibility) -> Self {
+        let defines: &[&str] = match compatibility {
+            Compatibility::UserPatch14 => &["UP_AVAILABLE"],
+            Compatibility::UserPatch15 | Compatibility::WololoKingdoms => {
+                &["UP_AVAILABLE", "UP_EXTENSION"]
+            }
+            _ => &[],
+        };
+        self.defines(defines)
+    }
+
+    /// Add the define that the game sets for the number of players in the lobby, like
+    /// `4_PLAYER_GAME`.
+    pub fn players(self, players: u8) -> Self {
+        self.define(format!("{}_PLAYER_GAME", players))
+    }
+
+    /// Get the active `#define` names that the game never sets for a lobby, like a misspelled map
+    /// size. They are still used when preprocessing, but are probably mistakes.
+    pub fn unknown_defines(&self) -> Vec<&str> {
+        let mut unknown = self
+            .defines
+            .iter()
+            .map(String::as_str)
+            .filter(|name| !is_lobby_define(name))
+            .collect::<Vec<_>>();
+        unknown.sort_unstable();
+        unknown
+    }
+
+    /// Resolve the main script in `rms`.
+    pub fn preprocess(&self, rms: &RMSFile<'_>) -> PreprocessedScript {
+        let tree = SyntaxTree::parse(rms.file_id(), rms.main_source());
+        let mut evaluator = Evaluator {
+            defines: self
+                .defines
+                .iter()
+                .map(|name| (name.clone(), Defined::Yes))
+                .collect(),
+            source: String::new(),
+            mappings: vec![],
+        };
+        evaluator.nodes(&tree.nodes, true);
+        for trivia in &tree.trailing_trivia {
+            evaluator.trivia(trivia);
+        }
+
+        PreprocessedScript {
+            source: evaluator.source,
+            mappings: evaluator.mappings,
+        }
+    }
+}
+
+struct Evaluator {
+    defines: HashMap<String, Defined>,
+    source: String,
+    mappings: Vec<SourceMapping>,
+}
+
+impl Evaluator {
+    fn text(&mut self, text: &str, original: SourceLocation) {
+        let start = ByteIndex::from(self.source.len());
+        self.source.push_str(text);
+        let end = ByteIndex::from(self.source.len());
+        self.mappings.push(SourceMapping {
+            output: start..end,
+            original,
+        });
+    }
+
+    fn trivia(&mut self, trivia: &Trivia<'_>) {
+        self.text(trivia.text(), trivia.location());
+    }
+
+    fn token(&mut self, token: &Token<'_>) {
+        for trivia in &token.trivia {
+            self.trivia(trivia);
+        }
+        self.text(token.text, token.location());
+    }
+
+    fn lookup(&self, name: &str) -> Defined {
+        self.defines.get(name).copied().unwrap_or(Defined::No)
+    }
+
+    /// Set a name to `value`. If the current code may not run, the name may or may not have the
+    /// new value afterwards.
+    fn set(&mut self, name: &str, value: Defined, certain: bool) {
+        let value = if certain || self.lookup(name) == value {
+            value
+        } else {
+            Defined::Maybe
+        };
+        self.defines.insert(name.to_string(), value);
+    }
+
+    fn nodes(&mut self, nodes: &[Node<'_>], certain: bool) {
+        for node in nodes {
+            self.node(node, certain);
+        }
+    }
+
+    fn block(&mut self, block: &BlockNode<'_>, certain: bool) {
+        self.token(&block.open);
+        self.nodes(&block.children, certain);
+        if let Some(close) = &block.close {
+            self.token(close);
+        }
+    }
+
+    fn node(&mut self, node: &Node<'_>, certain: bool) {
+        match node {
+            Node::Section(section) => {
+                self.token(&section.header);
+                self.nodes(&section.children, certain);
+            }
+            Node::Command(command) => {
+                self.token(&command.command);
+                if let Some(block) = &command.block {
+                    self.block(block, certain);
+                }
+            }
+            Node::Block(block) => self.block(block, certain),
+            Node::Condition(condition) => self.condition(condition, certain),
+            Node::Random(random) => {
+                self.token(&random.start);
+                self.nodes(&random.children, false);
+                for branch in &random.branches {
+                    self.token(&branch.head);
+                    self.nodes(&branch.children, false);
+                }
+                if let Some(end) = &random.end {
+                    self.token(end);
+                }
+            }
+            Node::Atom(token) => {
+                match token.kind() {
+                    AtomKind::Define { name, .. } => self.set(name.value, Defined::Yes, certain),
+                    AtomKind::Undefine { name, .. } => self.set(name.value, Defined::No, certain),
+                    _ => (),
+                }
+                self.token(token);
+            }
+        }
+    }
+
+    fn condition(&mut self, condition: &ConditionNode<'_>, certain: bool) {
+        // Keep the whitespace and comments before the statement even if all of it is dropped.
+        for trivia in &condition.branches[0].head.trivia {
+            self.trivia(trivia);
+        }
+
+        // Branches that may be taken, up to the first one that will definitely be taken.
+        let mut kept = vec![];
+        for branch in &condition.branches {
+            let value = match branch.head.kind() {
+                AtomKind::If { condition, .. } | AtomKind::ElseIf { condition, .. } => {
+                    self.lookup(condition.value)
+                }
+                _ => Defined::Yes,
+            };
+            match value {
+                Defined::No => continue,
+                Defined::Yes if kept.is_empty() => {
+                    // Only this branch is live, so we can drop the `if` statement entirely.
+                    self.nodes(&branch.children, certain);
+                    return;
+                }
+                Defined::Yes => {
+                    kept.push(branch);
+                    break;
+                }
+                Defined::Maybe => kept.push(branch),
+            }
+        }
+
+        if kept.is_empty() {
+            return;
+        }
+
+        for (index, branch) in kept.into_iter().enumerate() {
+            match branch.head.kind() {
+                AtomKind::ElseIf { condition, .. } if index == 0 => {
+                    // The preceding branches were dropped, so this one has to start the statement.
+                    self.text(&format!("if {}", condition.value), branch.head.location());
+                }
+                _ if index == 0 => self.text(branch.head.text, branch.head.location()),
+                _ => self.token(&branch.head),
+            }
+            self.nodes(&branch.children, false);
+        }
+        if let Some(end) = &condition.end {
+            self.token(end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocess(source: &str, defines: &[&str]) -> PreprocessedScript {
+        let file = RMSFile::from_string("test.rms", source);
+        Preprocessor::new().defines(defines).preprocess(&file)
+    }
+
+    #[test]
+    fn resolve_branches() {
+        let source = "if TINY_MAP\n  #const A 1\nelseif LARGE_MAP\n  #const A 2\nelse\n  #const A 3\nendif\n";
+        assert_eq!(preprocess(source, &[]).source(), "\n  #const A 3\n");
+        assert_eq!(
+            preprocess(source, &["LARGE_MAP"]).source(),
+            "\n  #const A 2\n"
+        );
+        assert_eq!(
+            preprocess(source, &["TINY_MAP", "LARGE_MAP"]).source(),
+            "\n  #const A 1\n"
+        );
+        assert_eq!(preprocess("if A #const X 1 endif", &[]).source(), "");
+    }
+
+    #[test]
+    fn lobby_defines() {
+        let file = RMSFile::from_string(
+            "test.rms",
+            "if UP_EXTENSION #const A 1 endif if 2_PLAYER_GAME #const B 2 endif",
+        );
+        let result = Preprocessor::new()
+            .compatibility(Compatibility::UserPatch15)
+            .players(2)
+            .preprocess(&file);
+        assert_eq!(result.source(), " #const A 1  #const B 2");
+        let result = Preprocessor::new()
+            .compatibility(Compatibility::Conquerors)
+            .players(8)
+            .preprocess(&file);
+        assert_eq!(result.source(), " ");
+    }
+
+    #[test]
+    fn script_defines() {
+        let source = "#define MY_MAP\nif UP_EXTENSION #undefine MY_MAP endif\nif MY_MAP random_placement endif";
+        assert_eq!(
+            preprocess(source, &[]).source(),
+            "#define MY_MAP\n\n random_placement"
+        );
+        assert_eq!(
+            preprocess(source, &["UP_EXTENSION"]).source(),
+            "#define MY_MAP\n #undefine MY_MAP\n"
+        );
+    }
+
+    #[test]
+    fn random_defines() {
+        let source = "start_random percent_chance 50 #define A end_random\nif TINY_MAP #define B elseif A #define C else #define D endif";
+        assert_eq!(
+            preprocess(source, &[]).source(),
+            "start_random percent_chance 50 #define A end_random\nif A #define C else #define D endif"
+        );
+        assert_eq!(
+            preprocess(source, &["TINY_MAP"]).source(),
+            "start_random percent_chance 50 #define A end_random\n #define B"
+        );
+    }
+
+    #[test]
+    fn source_mapping() {
+        let source = "if TINY_MAP\n#const A 1\nelse\n#const A 2\nendif";
+        let result = preprocess(source, &[]);
+        assert_eq!(result.source(), "\n#const A 2");
+        let location = result.original_location(ByteIndex::from(8)).unwrap();
+        assert_eq!(usize::from(location.start()), 35);
+        assert_eq!(&source[35..36], "A");
+        assert!(result
+            .original_location(ByteIndex::from(result.source().len()))
+            .is_none());
+    }
+
+    #[test]
+    fn unknown_defines() {
+        let preprocessor = Preprocessor::new()
+            .compatibility(Compatibility::UserPatch15)
+            .players(4)
+            .defines([
+                "LARGE_MAP",
+                "TEAM1_SIZE2",
+                "PLAYER3_TEAM1",
+                "ENORMOUS_MAP",
+                "MY_DEFINE",
+            ]);
+        assert_eq!(
+            preprocessor.unknown_defines(),
+            vec!["ENORMOUS_MAP", "MY_DEFINE"]
+        );
+    }
+}
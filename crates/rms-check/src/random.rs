@@ -0,0 +1,328 @@
+//! Works out how likely each outcome of the `start_random` statements in a script is.
+
+use crate::diagnostic::SourceLocation;
+use crate::parser::AtomKind;
+use crate::syntax_tree::{Node, RandomNode, SyntaxTree};
+use crate::tokenizer::Word;
+use crate::RMSFile;
+
+/// Resolves chances that are not numbers, like `#const` names.
+type Resolve<'r> = &'r dyn Fn(&Word<'_>) -> Option<i32>;
+
+/// A `start_random` statement and its possible outcomes.
+#[derive(Debug, Clone)]
+pub struct RandomStatement {
+    /// The location of the whole statement, from `start_random` to `end_random`.
+    pub location: SourceLocation,
+    /// The probability that this statement is reached at all, from 0 to 1. This only accounts
+    /// for surrounding `start_random` statements: `if` conditions are assumed to always pass.
+    ///
+    /// This is `None` if a surrounding chance could not be resolved.
+    pub probability: Option<f64>,
+    /// The `percent_chance` branches.
+    pub outcomes: Vec<RandomOutcome>,
+}
+
+/// A `percent_chance` branch in a `start_random` statement.
+#[derive(Debug, Clone)]
+pub struct RandomOutcome {
+    /// The location of the `percent_chance` command.
+    pub location: SourceLocation,
+    /// The chance in percent. This is `None` if it is not a number and could not be resolved.
+    pub chance: Option<i32>,
+    /// The probability that this branch is taken when the script runs, from 0 to 1. This
+    /// includes the probability of reaching the `start_random` statement.
+    ///
+    /// The game picks a branch by rolling a number from 0 to 99, so branches that start past a
+    /// total of 100 are never taken, and the chance of a branch that crosses 100 is cut off.
+    ///
+    /// This is `None` if this or a previous chance in the statement could not be resolved.
+    pub probability: Option<f64>,
+    /// `start_random` statements nested inside this branch.
+    pub children: Vec<RandomStatement>,
+}
+
+impl RandomStatement {
+    /// Get the sum of all the chances in this statement. This is `None` if any chance could not
+    /// be resolved.
+    ///
+    /// Negative chances count as 0, like they do in the game. The sum is an `i64`, so adding up
+    /// large chances cannot overflow.
+    pub fn total_chance(&self) -> Option<i64> {
+        self.outcomes
+            .iter()
+            .map(|outcome| outcome.chance.map(|chance| i64::from(chance.max(0))))
+            .sum()
+    }
+
+    /// Get the probability that none of the branches are taken when the script runs, from 0 to 1.
+    pub fn probability_of_nothing(&self) -> Option<f64> {
+        let total = self.total_chance()?;
+        let remaining = (100 - total).max(0) as f64 / 100.0;
+        self.probability.map(|probability| probability * remaining)
+    }
+}
+
+/// The `start_random` statements in a script.
+#[derive(Debug, Clone, Default)]
+pub struct RandomTree {
+    /// The outermost `start_random` statements, in source order.
+    pub statements: Vec<RandomStatement>,
+}
+
+impl RandomTree {
+    /// Find all `start_random` statements in the main script in `rms`.
+    pub fn from_file(rms: &RMSFile<'_>) -> Self {
+        Self::from_file_with(rms, |_| None)
+    }
+
+    /// Find all `start_random` statements in the main script in `rms`, using `resolve` to get
+    /// the value of chances that are not numbers, like `#const` names.
+    pub fn from_file_with(rms: &RMSFile<'_>, resolve: impl Fn(&Word<'_>) -> Option<i32>) -> Self {
+        let tree = SyntaxTree::parse(rms.file_id(), rms.main_source());
+        Self::from_syntax_tree_with(&tree, resolve)
+    }
+
+    /// Find all `start_random` statements in a syntax tree.
+    pub fn from_syntax_tree(tree: &SyntaxTree<'_>) -> Self {
+        Self::from_syntax_tree_with(tree, |_| None)
+    }
+
+    /// Find all `start_random` statements in a syntax tree, using `resolve` to get the value of
+    /// chances that are not numbers, like `#const` names.
+    pub fn from_syntax_tree_with(
+        tree: &SyntaxTree<'_>,
+        resolve: impl Fn(&Word<'_>) -> Option<i32>,
+    ) -> Self {
+        let mut statements = vec![];
+        collect(&tree.nodes, Some(1.0), &resolve, &mut statements);
+        Self { statements }
+    }
+
+    /// Call `f` for every outcome in the tree, including nested ones, in source order.
+    pub fn visit_outcomes<F>(&self, f: &mut F)
+    where
+        F: FnMut(&RandomStatement, &RandomOutcome),
+    {
+        fn visit_all<F>(statements: &[RandomStatement], f: &mut F)
+        where
+            F: FnMut(&RandomStatement, &RandomOutcome),
+        {
+            for statement in statements {
+                for outcome in &statement.outcomes {
+                    f(statement, outcome);
+                    visit_all(&outcome.children, f);
+                }
+            }
+        }
+
+        visit_all(&self.statements, f);
+    }
+}
+
+fn collect(
+    nodes: &[Node<'_>],
+    probability: Option<f64>,
+    resolve: Resolve<'_>,
+    statements: &mut Vec<RandomStatement>,
+) {
+    for node in nodes {
+        match node {
+            Node::Section(section) => collect(&section.children, probability, resolve, statements),
+            Node::Command(command) => {
+                if let Some(block) = &command.block {
+                    collect(&block.children, probability, resolve, statements);
+                }
+            }
+            Node::Block(block) => collect(&block.children, probability, resolve, statements),
+            Node::Condition(condition) => {
+                for branch in &condition.branches {
+                    collect(&branch.children, probability, resolve, statements);
+                }
+            }
+            Node::Random(random) => {
+                statements.push(statement(random, node.location(), probability, resolve));
+            }
+            Node::Atom(_) => (),
+        }
+    }
+}
+
+fn statement(
+    random: &RandomNode<'_>,
+    location: SourceLocation,
+    probability: Option<f64>,
+    resolve: Resolve<'_>,
+) -> RandomStatement {
+    // Code before the first `percent_chance` never runs, so it is not included.
+    let mut total = Some(0i64);
+    let outcomes = random
+        .branches
+        .iter()
+        .map(|branch| {
+            let chance = match branch.head.kind() {
+                AtomKind::PercentChance { chance, .. } => {
+                    chance.value.parse::<i32>().ok().or_else(|| resolve(chance))
+                }
+                _ => None,
+            };
+            let outcome_probability = match (probability, total, chance) {
+                (Some(probability), Some(previous), Some(chance)) => {
+                    let effective = i64::from(chance.max(0)).min((100 - previous).max(0));
+                    Some(probability * effective as f64 / 100.0)
+                }
+                _ => None,
+            };
+            total = total
+                .zip(chance)
+                .map(|(total, chance)| total + i64::from(chance.max(0)));
+
+            let mut children = vec![];
+            collect(
+                &branch.children,
+                outcome_probability,
+                resolve,
+                &mut children,
+            );
+            RandomOutcome {
+                location: branch.head.location(),
+                chance,
+                probability: outcome_probability,
+                children,
+            }
+        })
+        .collect();
+
+    RandomStatement {
+        location,
+        probability,
+        outcomes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyze(source: &str) -> RandomTree {
+        RandomTree::from_file(&RMSFile::from_string("test.rms", source))
+    }
+
+    fn probabilities(statement: &RandomStatement) -> Vec<Option<f64>> {
+        statement
+            .outcomes
+            .iter()
+            .map(|outcome| outcome.probability)
+            .collect()
+    }
+
+    #[test]
+    fn flat() {
+        let tree = analyze(
+            "start_random percent_chance 30 #define A percent_chance 50 #define B end_random",
+        );
+        assert_eq!(tree.statements.len(), 1);
+        let statement = &tree.statements[0];
+        assert_eq!(statement.total_chance(), Some(80));
+        assert_eq!(probabilities(statement), vec![Some(0.3), Some(0.5)]);
+        assert!((statement.probability_of_nothing().unwrap() - 0.2).abs() < 1e-9);
+        assert_eq!(usize::from(statement.outcomes[1].location.start()), 41);
+    }
+
+    #[test]
+    fn over_100() {
+        let tree = analyze(
+            "start_random percent_chance 60 percent_chance 60 percent_chance 10 end_random",
+        );
+        let statement = &tree.statements[0];
+        assert_eq!(statement.total_chance(), Some(130));
+        assert_eq!(
+            probabilities(statement),
+            vec![Some(0.6), Some(0.4), Some(0.0)]
+        );
+        assert_eq!(statement.probability_of_nothing(), Some(0.0));
+    }
+
+    #[test]
+    fn huge_chances() {
+        let tree = analyze(
+            "start_random percent_chance 2147483647 percent_chance 2147483647 percent_chance 10 end_random",
+        );
+        let statement = &tree.statements[0];
+        assert_eq!(statement.total_chance(), Some(2 * 2147483647 + 10));
+        assert_eq!(
+            probabilities(statement),
+            vec![Some(1.0), Some(0.0), Some(0.0)]
+        );
+        assert_eq!(statement.probability_of_nothing(), Some(0.0));
+    }
+
+    #[test]
+    fn negative_chance() {
+        let tree = analyze(
+            "start_random percent_chance -20 percent_chance 50 percent_chance 30 end_random",
+        );
+        let statement = &tree.statements[0];
+        assert_eq!(statement.total_chance(), Some(80));
+        assert_eq!(
+            probabilities(statement),
+            vec![Some(0.0), Some(0.5), Some(0.3)]
+        );
+        assert!((statement.probability_of_nothing().unwrap() - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nested() {
+        let tree = analyze(
+            "<PLAYER_SETUP>
+            start_random
+              percent_chance 50
+                if TINY_MAP
+                  start_random percent_chance 20 #define A percent_chance 80 #define B end_random
+                endif
+              percent_chance 50 #define C
+            end_random",
+        );
+        let statement = &tree.statements[0];
+        assert_eq!(probabilities(statement), vec![Some(0.5), Some(0.5)]);
+        let nested = &statement.outcomes[0].children[0];
+        assert_eq!(nested.probability, Some(0.5));
+        assert_eq!(probabilities(nested), vec![Some(0.1), Some(0.4)]);
+
+        let mut count = 0;
+        tree.visit_outcomes(&mut |_, _| count += 1);
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn unresolved_chance() {
+        let tree = analyze(
+            "start_random percent_chance 10 percent_chance CHANCE percent_chance 10 end_random",
+        );
+        let statement = &tree.statements[0];
+        assert_eq!(statement.total_chance(), None);
+        assert_eq!(probabilities(statement), vec![Some(0.1), None, None]);
+        assert_eq!(statement.outcomes[1].chance, None);
+    }
+
+    #[test]
+    fn resolved_chance() {
+        let file = RMSFile::from_string(
+            "test.rms",
+            "start_random percent_chance 10 percent_chance CHANCE percent_chance 10 end_random",
+        );
+        let tree = RandomTree::from_file_with(&file, |word| {
+            if word.value == "CHANCE" {
+                Some(60)
+            } else {
+                None
+            }
+        });
+        let statement = &tree.statements[0];
+        assert_eq!(statement.total_chance(), Some(80));
+        assert_eq!(
+            probabilities(statement),
+            vec![Some(0.1), Some(0.6), Some(0.1)]
+        );
+    }
+}
@@ -7,12 +7,14 @@ use crate::tokens::TokenType;
 use crate::RMSFile;
 use cow_utils::CowUtils;
 use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
 use std::str::FromStr;
 
 /// The target compatibility for a map script.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd)]
 pub enum Compatibility {
     /// The Conquerors.
+    #[default]
     Conquerors = 1,
     /// Target UserPatch 1.4, accept the features it added.
     UserPatch14 = 3,
@@ -29,9 +31,36 @@ pub enum Compatibility {
     All = 0,
 }
 
-impl Default for Compatibility {
-    fn default() -> Compatibility {
-        Compatibility::Conquerors
+impl FromStr for Compatibility {
+    type Err = ();
+
+    /// Parse a compatibility name, as used in `Compatibility:` header comments.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let lower_name = name.cow_to_ascii_lowercase();
+        match lower_name.trim() {
+            "hd edition" | "hd" => Ok(Compatibility::HDEdition),
+            "conquerors" | "aoc" => Ok(Compatibility::Conquerors),
+            "userpatch 1.5" | "up 1.5" => Ok(Compatibility::UserPatch15),
+            "userpatch 1.4" | "up 1.4" | "userpatch" | "up" => Ok(Compatibility::UserPatch14),
+            "wololokingdoms" | "wk" => Ok(Compatibility::WololoKingdoms),
+            "definitive edition" | "de" => Ok(Compatibility::DefinitiveEdition),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Compatibility {
+    /// Format the compatibility name as it would be written in a `Compatibility:` header comment.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compatibility::Conquerors => "Conquerors",
+            Compatibility::UserPatch14 => "UserPatch 1.4",
+            Compatibility::UserPatch15 => "UserPatch 1.5",
+            Compatibility::WololoKingdoms => "WololoKingdoms",
+            Compatibility::HDEdition => "HD Edition",
+            Compatibility::DefinitiveEdition => "Definitive Edition",
+            Compatibility::All => "All",
+        })
     }
 }
 
@@ -52,9 +81,37 @@ pub enum Nesting<'a> {
     Brace(Atom<'a>),
 }
 
+impl<'a> Nesting<'a> {
+    /// Get the atom that introduced this nested context.
+    pub const fn atom(&self) -> &Atom<'a> {
+        match self {
+            Nesting::If(atom)
+            | Nesting::ElseIf(atom)
+            | Nesting::Else(atom)
+            | Nesting::StartRandom(atom)
+            | Nesting::PercentChance(atom)
+            | Nesting::Brace(atom) => atom,
+        }
+    }
+
+    /// Get the nested context that an atom introduces, if any.
+    fn from_atom(atom: Atom<'a>) -> Option<Self> {
+        match atom.kind {
+            AtomKind::If { .. } => Some(Nesting::If(atom)),
+            AtomKind::ElseIf { .. } => Some(Nesting::ElseIf(atom)),
+            AtomKind::Else { .. } => Some(Nesting::Else(atom)),
+            AtomKind::StartRandom { .. } => Some(Nesting::StartRandom(atom)),
+            AtomKind::PercentChance { .. } => Some(Nesting::PercentChance(atom)),
+            AtomKind::OpenBlock { .. } => Some(Nesting::Brace(atom)),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
 enum HeaderName {
     Compatibility,
+    BuiltinMap,
 }
 
 impl FromStr for HeaderName {
@@ -64,6 +121,7 @@ impl FromStr for HeaderName {
         let lower_name = name.cow_to_ascii_lowercase();
         match lower_name.trim() {
             "compatibility" => Ok(HeaderName::Compatibility),
+            "builtin map" => Ok(HeaderName::BuiltinMap),
             _ => Err(()),
         }
     }
@@ -73,7 +131,9 @@ impl FromStr for HeaderName {
 pub struct ConstDefinition<'a> {
     atom: Atom<'a>,
     value: Option<Word<'a>>,
-    // depends_on: Vec<ConstDependency>,
+    /// The locations of the `if`, `elseif`, `else` and `percent_chance` branches this definition
+    /// is nested in.
+    scope: Vec<SourceLocation>,
 }
 
 impl<'a> ConstDefinition<'a> {
@@ -86,12 +146,40 @@ impl<'a> ConstDefinition<'a> {
         }
     }
 
+    /// Get the value this const is defined as. `#define`s do not have a value.
+    pub const fn value(&self) -> Option<Word<'a>> {
+        self.value
+    }
+
+    /// Get the numeric value this const is defined as, if it is a number.
+    pub fn resolved_value(&self) -> Option<i32> {
+        self.value.and_then(|value| value.value.parse().ok())
+    }
+
     /// Get the location where this const is defined.
     pub fn location(&self) -> SourceLocation {
         self.atom.location
     }
 }
 
+/// A copy of a [`ParseState`] that does not borrow the source code, so it can be kept across
+/// edits. Atoms are stored by location and parsed again when the snapshot is restored.
+#[derive(Debug, Clone)]
+pub(crate) struct ParseStateSnapshot {
+    compatibility: Compatibility,
+    is_builtin_map: bool,
+    nesting: Vec<SourceLocation>,
+    current_token: Option<&'static TokenType>,
+    current_section: Option<SourceLocation>,
+    /// The locations of the #const definitions, with their scopes.
+    consts: Vec<(SourceLocation, Vec<SourceLocation>)>,
+    /// The locations of the #define definitions, with their scopes.
+    defines: Vec<(SourceLocation, Vec<SourceLocation>)>,
+    option_defines: HashSet<String>,
+    end_of_headers: bool,
+    has_definitions: bool,
+}
+
 #[derive(Debug)]
 pub struct ParseState<'a> {
     /// The files.
@@ -106,8 +194,8 @@ pub struct ParseState<'a> {
     pub current_token: Option<&'static TokenType>,
     /// The current <SECTION>, as well as its opening token.
     pub current_section: Option<Atom<'a>>,
-    /// List of builtin #const definitions.
-    builtin_consts: HashSet<String>,
+    /// List of builtin #const definitions, with their numeric values.
+    builtin_consts: HashMap<String, Option<i32>>,
     /// List of builtin #define definitions.
     builtin_defines: HashSet<String>,
     /// List of user-mode #const definitions we've seen so far.
@@ -118,6 +206,8 @@ pub struct ParseState<'a> {
     pub option_defines: HashSet<String>,
     /// Are we still parsing header comments?
     end_of_headers: bool,
+    /// Did a builtin map include the bundled definitions file?
+    has_definitions: bool,
 }
 
 impl<'a> ParseState<'a> {
@@ -129,17 +219,103 @@ impl<'a> ParseState<'a> {
             nesting: vec![],
             current_token: None,
             current_section: None,
-            builtin_consts: HashSet::new(),
+            builtin_consts: HashMap::new(),
             builtin_defines: HashSet::new(),
             consts: HashMap::new(),
             defines: HashMap::new(),
             option_defines: HashSet::new(),
             end_of_headers: false,
+            has_definitions: false,
         };
         state.set_compatibility(compatibility);
         state
     }
 
+    /// Copy the state so it can be restored after the source code after this point changed.
+    pub(crate) fn snapshot(&self) -> ParseStateSnapshot {
+        let definitions = |map: &HashMap<&'a str, ConstDefinition<'a>>| {
+            map.values()
+                .map(|definition| (definition.location(), definition.scope.clone()))
+                .collect()
+        };
+        ParseStateSnapshot {
+            compatibility: self.compatibility,
+            is_builtin_map: self.is_builtin_map,
+            nesting: self
+                .nesting
+                .iter()
+                .map(|nesting| nesting.atom().location)
+                .collect(),
+            current_token: self.current_token,
+            current_section: self.current_section.as_ref().map(|atom| atom.location),
+            consts: definitions(&self.consts),
+            defines: definitions(&self.defines),
+            option_defines: self.option_defines.clone(),
+            end_of_headers: self.end_of_headers,
+            has_definitions: self.has_definitions,
+        }
+    }
+
+    /// Restore a snapshot for a map whose source code is unchanged up to the point where the
+    /// snapshot was taken. Returns `None` if an atom in the snapshot can no longer be parsed from
+    /// `rms`.
+    pub(crate) fn restore(rms: &'a RMSFile<'a>, snapshot: &ParseStateSnapshot) -> Option<Self> {
+        let atom_at = |location: SourceLocation| {
+            let file = location.file();
+            let source = rms.source(file);
+            if usize::from(location.end()) > source.len() {
+                return None;
+            }
+            Parser::starting_at(file, source, location.start())
+                .next()
+                .map(|(atom, _)| atom)
+                .filter(|atom| atom.location == location)
+        };
+        let definitions = |list: &[(SourceLocation, Vec<SourceLocation>)]| {
+            list.iter()
+                .map(|(location, scope)| {
+                    let atom = atom_at(*location)?;
+                    let value = match atom.kind {
+                        AtomKind::Const { value, .. } => value,
+                        AtomKind::Define { .. } => None,
+                        _ => return None,
+                    };
+                    let definition = ConstDefinition {
+                        atom,
+                        value,
+                        scope: scope.clone(),
+                    };
+                    Some((definition.name(), definition))
+                })
+                .collect::<Option<HashMap<_, _>>>()
+        };
+
+        let mut state = Self {
+            rms,
+            compatibility: snapshot.compatibility,
+            is_builtin_map: snapshot.is_builtin_map,
+            nesting: snapshot
+                .nesting
+                .iter()
+                .map(|location| atom_at(*location).and_then(Nesting::from_atom))
+                .collect::<Option<_>>()?,
+            current_token: snapshot.current_token,
+            current_section: match snapshot.current_section {
+                Some(location) => Some(atom_at(location)?),
+                None => None,
+            },
+            builtin_consts: HashMap::new(),
+            builtin_defines: HashSet::new(),
+            consts: definitions(&snapshot.consts)?,
+            defines: definitions(&snapshot.defines)?,
+            option_defines: snapshot.option_defines.clone(),
+            end_of_headers: snapshot.end_of_headers,
+            has_definitions: snapshot.has_definitions,
+        };
+        state.set_compatibility(state.compatibility);
+        Some(state)
+    }
+
     /// Track that a `#define` name may or may not exist from this point.
     ///
     /// These defines are valid in `if` statements, but not in commands, for example.
@@ -164,14 +340,14 @@ impl<'a> ParseState<'a> {
     }
     /// Does a given `#const` name exist?
     pub fn has_const(&self, name: &str) -> bool {
-        self.consts.contains_key(name) || self.builtin_consts.contains(name)
+        self.consts.contains_key(name) || self.builtin_consts.contains_key(name)
     }
     /// List all the `#const` names that are currently available.
     pub fn consts(&self) -> impl Iterator<Item = &str> {
         self.consts
             .keys()
             .copied()
-            .chain(self.builtin_consts.iter().map(|string| string.as_ref()))
+            .chain(self.builtin_consts.keys().map(|string| string.as_ref()))
     }
     /// List all the `#define` names that are currently available.
     pub fn defines(&self) -> impl Iterator<Item = &str> {
@@ -189,11 +365,64 @@ impl<'a> ParseState<'a> {
         self.consts.get(name)
     }
 
+    /// Get the numeric value of a `#const` name at this point, including builtin consts.
+    pub fn resolve_const(&self, name: &str) -> Option<i32> {
+        match self.consts.get(name) {
+            Some(definition) => definition.resolved_value(),
+            None => self.builtin_consts.get(name).copied().flatten(),
+        }
+    }
+
+    /// Get the numeric value of a `#const` name from the def files, if it is builtin.
+    pub fn resolve_builtin_const(&self, name: &str) -> Option<i32> {
+        self.builtin_consts.get(name).copied().flatten()
+    }
+
+    /// Does a given `#const` name come from the def files?
+    pub fn is_builtin_const(&self, name: &str) -> bool {
+        self.builtin_consts.contains_key(name)
+    }
+
+    /// Resolve a command argument to a number: either a numeric literal or a `#const` name.
+    pub fn resolve_number(&self, value: &str) -> Option<i32> {
+        value.parse().ok().or_else(|| self.resolve_const(value))
+    }
+
+    /// Does `definition` always run before code at the current position? This is not the case
+    /// if it is inside a different branch of an `if` or `start_random` statement.
+    pub fn is_in_scope(&self, definition: &ConstDefinition<'_>) -> bool {
+        let scope = self.current_scope();
+        scope.starts_with(&definition.scope)
+    }
+
+    /// Get the locations of the branches the current position is nested in.
+    fn current_scope(&self) -> Vec<SourceLocation> {
+        self.nesting
+            .iter()
+            .filter_map(|nesting| match nesting {
+                Nesting::If(atom)
+                | Nesting::ElseIf(atom)
+                | Nesting::Else(atom)
+                | Nesting::PercentChance(atom) => Some(atom.location),
+                Nesting::StartRandom(_) | Nesting::Brace(_) => None,
+            })
+            .collect()
+    }
+
     /// Get the compatibility mode the parser runs in.
     pub const fn compatibility(&self) -> Compatibility {
         self.compatibility
     }
 
+    /// Set whether this map should be treated as a builtin map.
+    ///
+    /// Builtin maps do not get the builtin `#define` and `#const` names automatically: they have
+    /// to `#include_drs random_map.def` first.
+    pub fn set_builtin_map(&mut self, is_builtin_map: bool) {
+        self.is_builtin_map = is_builtin_map;
+        self.set_compatibility(self.compatibility);
+    }
+
     /// Set the compatibility mode the parser should run in.
     ///
     /// This affects the available builtin `#define` and `#const` names.
@@ -203,12 +432,19 @@ impl<'a> ParseState<'a> {
         self.builtin_consts.clear();
         self.builtin_defines.clear();
 
+        if self.is_builtin_map && !self.has_definitions {
+            return;
+        }
+
         let (file_id, content) = self.rms.definitions(compatibility);
 
         for (atom, _) in Parser::new(file_id, content) {
             match atom.kind {
-                AtomKind::Const { name, .. } => {
-                    self.builtin_consts.insert(name.value.to_string());
+                AtomKind::Const { name, value, .. } => {
+                    self.builtin_consts.insert(
+                        name.value.to_string(),
+                        value.and_then(|value| value.value.parse().ok()),
+                    );
                 }
                 AtomKind::Define { name, .. } => {
                     self.builtin_defines.insert(name.value.to_string());
@@ -222,7 +458,7 @@ impl<'a> ParseState<'a> {
     pub(crate) fn update(&mut self, atom: &Atom<'a>) {
         self.update_headers(atom);
 
-        match atom.kind {
+        match &atom.kind {
             AtomKind::Section { .. } => {
                 self.current_section = Some(atom.clone());
             }
@@ -230,14 +466,28 @@ impl<'a> ParseState<'a> {
                 self.define(ConstDefinition {
                     atom: atom.clone(),
                     value: None,
+                    scope: self.current_scope(),
                 });
             }
             AtomKind::Const { value, .. } => {
                 self.define_const(ConstDefinition {
                     atom: atom.clone(),
-                    value,
+                    value: *value,
+                    scope: self.current_scope(),
                 });
             }
+            // Included files are checked separately, but builtin maps can also use the bundled
+            // definitions file.
+            AtomKind::Command {
+                name, arguments, ..
+            } if self.is_builtin_map
+                && name.value == "#include_drs"
+                && arguments.first().map(|file| file.value) == Some("random_map.def")
+                && self.rms.find_include("random_map.def").is_none() =>
+            {
+                self.has_definitions = true;
+                self.set_compatibility(self.compatibility);
+            }
             _ => (),
         }
     }
@@ -245,17 +495,17 @@ impl<'a> ParseState<'a> {
     fn set_header(&mut self, name: HeaderName, value: &str) {
         match name {
             HeaderName::Compatibility => {
+                if let Ok(compat) = value.parse() {
+                    self.set_compatibility(compat);
+                }
+            }
+            HeaderName::BuiltinMap => {
                 let lower_value = value.cow_to_ascii_lowercase();
-                let compat = match lower_value.trim() {
-                    "hd edition" | "hd" => Compatibility::HDEdition,
-                    "conquerors" | "aoc" => Compatibility::Conquerors,
-                    "userpatch 1.5" | "up 1.5" => Compatibility::UserPatch15,
-                    "userpatch 1.4" | "up 1.4" | "userpatch" | "up" => Compatibility::UserPatch14,
-                    "wololokingdoms" | "wk" => Compatibility::WololoKingdoms,
-                    "definitive edition" | "de" => Compatibility::DefinitiveEdition,
-                    _ => return,
-                };
-                self.set_compatibility(compat);
+                match lower_value.trim() {
+                    "yes" | "true" => self.set_builtin_map(true),
+                    "no" | "false" => self.set_builtin_map(false),
+                    _ => (),
+                }
             }
         }
     }
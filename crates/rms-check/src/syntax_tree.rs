@@ -0,0 +1,620 @@
+//! A lossless concrete syntax tree for AoE2 random map scripts, built on top of the `Atom` stream.
+//!
+//! The tree groups atoms into sections, command blocks, `if` statements and `start_random`
+//! statements. Whitespace and comments are kept as trivia on the token that follows them, so the
+//! original source can be printed back byte-for-byte.
+
+use crate::diagnostic::{ByteIndex, FileId, SourceLocation};
+use crate::parser::{Atom, AtomKind, Parser};
+use crate::tokenizer::Word;
+use std::fmt::{self, Display};
+use std::iter::Peekable;
+
+/// Source code that does not affect the structure of the script.
+#[derive(Debug, Clone)]
+pub enum Trivia<'a> {
+    /// A run of whitespace.
+    Whitespace(Word<'a>),
+    /// A comment. The word spans the entire comment, including the `/*` and `*/` delimiters.
+    Comment(Word<'a>, Atom<'a>),
+}
+
+impl<'a> Trivia<'a> {
+    /// Get the source text of this trivia.
+    pub const fn text(&self) -> &'a str {
+        match self {
+            Trivia::Whitespace(word) | Trivia::Comment(word, _) => word.value,
+        }
+    }
+
+    /// Get the source code location of this trivia.
+    pub const fn location(&self) -> SourceLocation {
+        match self {
+            Trivia::Whitespace(word) | Trivia::Comment(word, _) => word.location,
+        }
+    }
+}
+
+/// A single atom in the tree, with the trivia that precedes it.
+#[derive(Debug, Clone)]
+pub struct Token<'a> {
+    /// Whitespace and comments between the previous token and this one.
+    pub trivia: Vec<Trivia<'a>>,
+    /// The atom.
+    pub atom: Atom<'a>,
+    /// The source text of the atom.
+    pub text: &'a str,
+}
+
+impl<'a> Token<'a> {
+    /// Get the kind of atom this token contains.
+    pub const fn kind(&self) -> &AtomKind<'a> {
+        &self.atom.kind
+    }
+
+    /// Get the source code location of the atom, excluding trivia.
+    pub const fn location(&self) -> SourceLocation {
+        self.atom.location
+    }
+
+    /// Iterate over the comments that precede this token.
+    pub fn comments(&self) -> impl Iterator<Item = &Atom<'a>> {
+        self.trivia.iter().filter_map(|trivia| match trivia {
+            Trivia::Comment(_, atom) => Some(atom),
+            Trivia::Whitespace(_) => None,
+        })
+    }
+}
+
+impl Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for trivia in &self.trivia {
+            f.write_str(trivia.text())?;
+        }
+        f.write_str(self.text)
+    }
+}
+
+/// A `<SECTION>` header, and everything up to the next section header.
+#[derive(Debug, Clone)]
+pub struct SectionNode<'a> {
+    /// The `<SECTION>` token.
+    pub header: Token<'a>,
+    /// The contents of the section.
+    pub children: Vec<Node<'a>>,
+}
+
+/// A command, with its attribute block if it has one.
+#[derive(Debug, Clone)]
+pub struct CommandNode<'a> {
+    /// The command token.
+    pub command: Token<'a>,
+    /// The `{ }` block directly following the command.
+    pub block: Option<BlockNode<'a>>,
+}
+
+/// A `{ }` block containing attributes.
+#[derive(Debug, Clone)]
+pub struct BlockNode<'a> {
+    /// The `{` token.
+    pub open: Token<'a>,
+    /// The contents of the block.
+    pub children: Vec<Node<'a>>,
+    /// The `}` token, if the block was closed.
+    pub close: Option<Token<'a>>,
+}
+
+/// A branch of an `if` or `start_random` statement.
+#[derive(Debug, Clone)]
+pub struct Branch<'a> {
+    /// The `if`, `elseif`, `else` or `percent_chance` token that starts this branch.
+    pub head: Token<'a>,
+    /// The contents of the branch.
+    pub children: Vec<Node<'a>>,
+}
+
+/// An `if` statement, with its `elseif` and `else` branches.
+#[derive(Debug, Clone)]
+pub struct ConditionNode<'a> {
+    /// The branches. The first branch always starts with `if`.
+    pub branches: Vec<Branch<'a>>,
+    /// The `endif` token, if the statement was closed.
+    pub end: Option<Token<'a>>,
+}
+
+/// A `start_random` statement, with its `percent_chance` branches.
+#[derive(Debug, Clone)]
+pub struct RandomNode<'a> {
+    /// The `start_random` token.
+    pub start: Token<'a>,
+    /// Anything between `start_random` and the first `percent_chance`.
+    pub children: Vec<Node<'a>>,
+    /// The `percent_chance` branches.
+    pub branches: Vec<Branch<'a>>,
+    /// The `end_random` token, if the statement was closed.
+    pub end: Option<Token<'a>>,
+}
+
+/// A node in the syntax tree.
+#[derive(Debug, Clone)]
+pub enum Node<'a> {
+    /// A `<SECTION>`. Sections only appear at the top level of the tree.
+    Section(SectionNode<'a>),
+    /// A command, with an optional block.
+    Command(CommandNode<'a>),
+    /// A block that does not directly follow a command, like in
+    /// `if A create_object X else create_object Y endif { }`.
+    Block(BlockNode<'a>),
+    /// An `if` statement.
+    Condition(ConditionNode<'a>),
+    /// A `start_random` statement.
+    Random(RandomNode<'a>),
+    /// Any other atom: `#const`, `#define`, unrecognised words, and control flow tokens that do
+    /// not match an open statement.
+    Atom(Token<'a>),
+}
+
+impl<'a> Node<'a> {
+    /// Call `f` for every token in this node, in source order.
+    pub fn visit_tokens<'n, F>(&'n self, f: &mut F)
+    where
+        F: FnMut(&'n Token<'a>),
+    {
+        fn visit_all<'n, 'a, F>(nodes: &'n [Node<'a>], f: &mut F)
+        where
+            F: FnMut(&'n Token<'a>),
+        {
+            for node in nodes {
+                node.visit_tokens(f);
+            }
+        }
+
+        fn visit_block<'n, 'a, F>(block: &'n BlockNode<'a>, f: &mut F)
+        where
+            F: FnMut(&'n Token<'a>),
+        {
+            f(&block.open);
+            visit_all(&block.children, f);
+            if let Some(close) = &block.close {
+                f(close);
+            }
+        }
+
+        match self {
+            Node::Section(section) => {
+                f(&section.header);
+                visit_all(&section.children, f);
+            }
+            Node::Command(command) => {
+                f(&command.command);
+                if let Some(block) = &command.block {
+                    visit_block(block, f);
+                }
+            }
+            Node::Block(block) => visit_block(block, f),
+            Node::Condition(condition) => {
+                for branch in &condition.branches {
+                    f(&branch.head);
+                    visit_all(&branch.children, f);
+                }
+                if let Some(end) = &condition.end {
+                    f(end);
+                }
+            }
+            Node::Random(random) => {
+                f(&random.start);
+                visit_all(&random.children, f);
+                for branch in &random.branches {
+                    f(&branch.head);
+                    visit_all(&branch.children, f);
+                }
+                if let Some(end) = &random.end {
+                    f(end);
+                }
+            }
+            Node::Atom(token) => f(token),
+        }
+    }
+
+    /// Get the first token in this node.
+    pub fn first_token(&self) -> &Token<'a> {
+        match self {
+            Node::Section(section) => &section.header,
+            Node::Command(command) => &command.command,
+            Node::Block(block) => &block.open,
+            Node::Condition(condition) => &condition.branches[0].head,
+            Node::Random(random) => &random.start,
+            Node::Atom(token) => token,
+        }
+    }
+
+    /// Get the last token in this node.
+    pub fn last_token(&self) -> &Token<'a> {
+        let mut last = self.first_token();
+        self.visit_tokens(&mut |token| last = token);
+        last
+    }
+
+    /// Get the source code location of this node, excluding leading trivia.
+    pub fn location(&self) -> SourceLocation {
+        let start = self.first_token().location();
+        let end = self.last_token().location();
+        SourceLocation::new(start.file(), start.start()..end.end())
+    }
+}
+
+impl Display for Node<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut result = Ok(());
+        self.visit_tokens(&mut |token| {
+            if result.is_ok() {
+                result = token.fmt(f);
+            }
+        });
+        result
+    }
+}
+
+/// A lossless syntax tree for a random map script.
+///
+/// ## Example
+/// ```rust
+/// use rms_check::{Node, RMSFile, SyntaxTree};
+/// let source = "<OBJECTS_GENERATION>\ncreate_object SCOUT { number_of_objects 1 }\n";
+/// let file = RMSFile::from_string("scout.rms", source);
+/// let tree = SyntaxTree::parse(file.file_id(), file.main_source());
+/// assert_eq!(tree.to_string(), source);
+/// assert!(matches!(tree.nodes[0], Node::Section(_)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SyntaxTree<'a> {
+    /// The top-level nodes.
+    pub nodes: Vec<Node<'a>>,
+    /// Whitespace and comments at the end of the file.
+    pub trailing_trivia: Vec<Trivia<'a>>,
+}
+
+impl<'a> SyntaxTree<'a> {
+    /// Parse a source string into a syntax tree.
+    pub fn parse(file_id: FileId, source: &'a str) -> Self {
+        let mut trivia = vec![];
+        let mut tokens = vec![];
+        let mut last_end = ByteIndex::from(0);
+        let whitespace = |start: ByteIndex, end: ByteIndex| {
+            Trivia::Whitespace(Word {
+                value: &source[start.into()..end.into()],
+                location: SourceLocation::new(file_id, start..end),
+            })
+        };
+
+        for (atom, _) in Parser::new(file_id, source) {
+            let range = atom.range();
+            if range.start > last_end {
+                trivia.push(whitespace(last_end, range.start));
+            }
+            last_end = range.end;
+
+            let text = &source[range.start.into()..range.end.into()];
+            if let AtomKind::Comment { .. } = atom.kind {
+                let word = Word {
+                    value: text,
+                    location: atom.location,
+                };
+                trivia.push(Trivia::Comment(word, atom));
+            } else {
+                tokens.push(Token {
+                    trivia: std::mem::take(&mut trivia),
+                    atom,
+                    text,
+                });
+            }
+        }
+
+        let end = ByteIndex::from(source.len());
+        if end > last_end {
+            trivia.push(whitespace(last_end, end));
+        }
+
+        let mut builder = TreeBuilder {
+            tokens: tokens.into_iter().peekable(),
+        };
+        Self {
+            nodes: builder.nodes(Context::TopLevel),
+            trailing_trivia: trivia,
+        }
+    }
+
+    /// Call `f` for every token in the tree, in source order.
+    pub fn visit_tokens<'n, F>(&'n self, mut f: F)
+    where
+        F: FnMut(&'n Token<'a>),
+    {
+        for node in &self.nodes {
+            node.visit_tokens(&mut f);
+        }
+    }
+}
+
+impl Display for SyntaxTree<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.nodes {
+            node.fmt(f)?;
+        }
+        for trivia in &self.trailing_trivia {
+            f.write_str(trivia.text())?;
+        }
+        Ok(())
+    }
+}
+
+/// The kind of node whose children are being read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    TopLevel,
+    Section,
+    Block,
+    Condition,
+    Random,
+}
+
+impl Context {
+    /// Does this atom end the current list of children?
+    fn is_end(self, kind: &AtomKind<'_>) -> bool {
+        match self {
+            Context::TopLevel => false,
+            Context::Section => matches!(kind, AtomKind::Section { .. }),
+            Context::Block => matches!(kind, AtomKind::CloseBlock { .. }),
+            Context::Condition => matches!(
+                kind,
+                AtomKind::ElseIf { .. } | AtomKind::Else { .. } | AtomKind::EndIf { .. }
+            ),
+            Context::Random => matches!(
+                kind,
+                AtomKind::PercentChance { .. } | AtomKind::EndRandom { .. }
+            ),
+        }
+    }
+}
+
+/// Groups a flat list of tokens into nodes.
+///
+/// Only tokens that close the innermost open node are treated as closing tokens. Others, like a
+/// `}` inside an `if` statement, become `Node::Atom`s, just like `ParseState::update_nesting` does
+/// not pop unbalanced nestings.
+struct TreeBuilder<'a> {
+    tokens: Peekable<std::vec::IntoIter<Token<'a>>>,
+}
+
+impl<'a> TreeBuilder<'a> {
+    /// Take the next token if it has the given kind.
+    fn next_if(&mut self, predicate: impl FnOnce(&AtomKind<'a>) -> bool) -> Option<Token<'a>> {
+        self.tokens.next_if(|token| predicate(token.kind()))
+    }
+
+    /// Read nodes until a token that ends the given context.
+    fn nodes(&mut self, context: Context) -> Vec<Node<'a>> {
+        let mut nodes = vec![];
+        while let Some(token) = self.next_if(|kind| !context.is_end(kind)) {
+            nodes.push(self.node(token, context));
+        }
+        nodes
+    }
+
+    fn node(&mut self, token: Token<'a>, context: Context) -> Node<'a> {
+        match token.kind() {
+            AtomKind::Section { .. } if context == Context::TopLevel => {
+                Node::Section(SectionNode {
+                    header: token,
+                    children: self.nodes(Context::Section),
+                })
+            }
+            AtomKind::Command { .. } => {
+                let block = self
+                    .next_if(|kind| matches!(kind, AtomKind::OpenBlock { .. }))
+                    .map(|open| self.block(open));
+                Node::Command(CommandNode {
+                    command: token,
+                    block,
+                })
+            }
+            AtomKind::OpenBlock { .. } => Node::Block(self.block(token)),
+            AtomKind::If { .. } => Node::Condition(self.condition(token)),
+            AtomKind::StartRandom { .. } => Node::Random(self.random(token)),
+            _ => Node::Atom(token),
+        }
+    }
+
+    fn block(&mut self, open: Token<'a>) -> BlockNode<'a> {
+        let children = self.nodes(Context::Block);
+        let close = self.next_if(|kind| matches!(kind, AtomKind::CloseBlock { .. }));
+        BlockNode {
+            open,
+            children,
+            close,
+        }
+    }
+
+    fn condition(&mut self, head: Token<'a>) -> ConditionNode<'a> {
+        let mut branches = vec![Branch {
+            children: self.nodes(Context::Condition),
+            head,
+        }];
+        while let Some(head) =
+            self.next_if(|kind| matches!(kind, AtomKind::ElseIf { .. } | AtomKind::Else { .. }))
+        {
+            branches.push(Branch {
+                children: self.nodes(Context::Condition),
+                head,
+            });
+        }
+        let end = self.next_if(|kind| matches!(kind, AtomKind::EndIf { .. }));
+        ConditionNode { branches, end }
+    }
+
+    fn random(&mut self, start: Token<'a>) -> RandomNode<'a> {
+        let children = self.nodes(Context::Random);
+        let mut branches = vec![];
+        while let Some(head) = self.next_if(|kind| matches!(kind, AtomKind::PercentChance { .. })) {
+            branches.push(Branch {
+                children: self.nodes(Context::Random),
+                head,
+            });
+        }
+        let end = self.next_if(|kind| matches!(kind, AtomKind::EndRandom { .. }));
+        RandomNode {
+            start,
+            children,
+            branches,
+            end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> SyntaxTree<'_> {
+        SyntaxTree::parse(FileId::new(0), source)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let sources = [
+            "",
+            "   \r\n",
+            "/* only a comment */",
+            "<PLAYER_SETUP>\r\n  random_placement /* trailing */\r\n",
+            "if A\n\n#define X\n\n\n\nelse\n\n\n\n\n\n\nendif",
+            "create_object X { number_of_objects 1 /* unclosed",
+            "} endif end_random percent_chance 10 {",
+            "/****/ <LAND_GENERATION>\tcreate_land{base_size 1}  ",
+        ];
+        for source in &sources {
+            assert_eq!(parse(source).to_string(), *source);
+        }
+
+        for filename in &["tests/rms/Dry Arabia.rms", "tests/rms/CM_Houseboat_v2.rms"] {
+            let bytes = std::fs::read(filename).unwrap();
+            let source = String::from_utf8_lossy(&bytes);
+            assert_eq!(parse(&source).to_string(), source);
+        }
+    }
+
+    #[test]
+    fn sections_and_blocks() {
+        let tree = parse(
+            "#const X 1 <PLAYER_SETUP> random_placement <OBJECTS_GENERATION> create_object X { number_of_objects 1 }",
+        );
+        assert_eq!(tree.nodes.len(), 3);
+        assert!(matches!(tree.nodes[0], Node::Atom(_)));
+        let setup = match &tree.nodes[1] {
+            Node::Section(section) => section,
+            node => panic!("expected a section, got {:?}", node),
+        };
+        assert_eq!(setup.header.text, "<PLAYER_SETUP>");
+        assert_eq!(setup.children.len(), 1);
+        let objects = match &tree.nodes[2] {
+            Node::Section(section) => section,
+            node => panic!("expected a section, got {:?}", node),
+        };
+        let command = match &objects.children[0] {
+            Node::Command(command) => command,
+            node => panic!("expected a command, got {:?}", node),
+        };
+        assert_eq!(command.command.text, "create_object X");
+        let block = command.block.as_ref().unwrap();
+        assert_eq!(block.children.len(), 1);
+        assert_eq!(block.close.as_ref().unwrap().text, "}");
+    }
+
+    #[test]
+    fn conditions() {
+        let tree = parse("if A #define X elseif B if C #define Y endif else #define Z endif");
+        assert_eq!(tree.nodes.len(), 1);
+        let condition = match &tree.nodes[0] {
+            Node::Condition(condition) => condition,
+            node => panic!("expected a condition, got {:?}", node),
+        };
+        assert_eq!(condition.branches.len(), 3);
+        assert_eq!(condition.branches[0].head.text, "if A");
+        assert_eq!(condition.branches[1].head.text, "elseif B");
+        assert_eq!(condition.branches[2].head.text, "else");
+        assert!(matches!(
+            condition.branches[1].children[0],
+            Node::Condition(_)
+        ));
+        assert!(condition.end.is_some());
+    }
+
+    #[test]
+    fn random() {
+        let tree = parse(
+            "start_random percent_chance 30 #define A percent_chance 70 start_random percent_chance 50 #define B end_random end_random",
+        );
+        let random = match &tree.nodes[0] {
+            Node::Random(random) => random,
+            node => panic!("expected a random statement, got {:?}", node),
+        };
+        assert!(random.children.is_empty());
+        assert_eq!(random.branches.len(), 2);
+        assert_eq!(random.branches[0].head.text, "percent_chance 30");
+        let nested = match &random.branches[1].children[0] {
+            Node::Random(random) => random,
+            node => panic!("expected a random statement, got {:?}", node),
+        };
+        assert_eq!(nested.branches.len(), 1);
+        assert!(nested.end.is_some());
+        assert!(random.end.is_some());
+    }
+
+    #[test]
+    fn trivia() {
+        let tree = parse("/* a */ #define A /* b */\n");
+        let token = match &tree.nodes[0] {
+            Node::Atom(token) => token,
+            node => panic!("expected an atom, got {:?}", node),
+        };
+        assert_eq!(token.comments().count(), 1);
+        assert_eq!(token.trivia.len(), 2);
+        assert_eq!(tree.trailing_trivia.len(), 3);
+        assert_eq!(tree.trailing_trivia[1].text(), "/* b */");
+    }
+
+    #[test]
+    fn unbalanced() {
+        let tree = parse("create_object X { if A } endif }");
+        let block = match &tree.nodes[0] {
+            Node::Command(CommandNode {
+                block: Some(block), ..
+            }) => block,
+            node => panic!("expected a command with a block, got {:?}", node),
+        };
+        // The `}` inside the `if` does not close the block.
+        let condition = match &block.children[0] {
+            Node::Condition(condition) => condition,
+            node => panic!("expected a condition, got {:?}", node),
+        };
+        assert!(matches!(condition.branches[0].children[0], Node::Atom(_)));
+        assert!(block.close.is_some());
+
+        let tree = parse("endif <PLAYER_SETUP> if A <LAND_GENERATION> endif");
+        assert!(matches!(tree.nodes[0], Node::Atom(_)));
+        let section = match &tree.nodes[1] {
+            Node::Section(section) => section,
+            node => panic!("expected a section, got {:?}", node),
+        };
+        // Sections inside an `if` do not end the current section.
+        assert_eq!(section.children.len(), 1);
+        assert_eq!(tree.nodes.len(), 2);
+    }
+
+    #[test]
+    fn node_location() {
+        let tree = parse("  create_object X {\n  number_of_objects 1\n}\n");
+        assert_eq!(
+            tree.nodes[0].location(),
+            SourceLocation::new(FileId::new(0), ByteIndex::from(2)..ByteIndex::from(43))
+        );
+    }
+}
@@ -29,16 +29,26 @@ impl<'a> Word<'a> {
 pub struct Tokenizer<'a> {
     file: FileId,
     source: &'a str,
+    /// The byte offset in `source` that `chars` started at.
+    offset: usize,
     chars: CharIndices<'a>,
 }
 
 impl<'a> Tokenizer<'a> {
     /// Create an iterator over the `source` string's words.
     pub fn new(file_id: FileId, source: &'a str) -> Self {
+        Self::starting_at(file_id, source, ByteIndex::from(0))
+    }
+
+    /// Create an iterator over the `source` string's words, starting at byte offset `start`.
+    /// Word locations are still relative to the start of `source`.
+    pub fn starting_at(file_id: FileId, source: &'a str, start: ByteIndex) -> Self {
+        let offset = usize::from(start);
         Tokenizer {
             file: file_id,
             source,
-            chars: source.char_indices(),
+            offset,
+            chars: source[offset..].char_indices(),
         }
     }
 }
@@ -52,7 +62,7 @@ impl<'a> Iterator for Tokenizer<'a> {
         let mut end = ByteIndex::from(self.source.len());
         let mut saw_word = false;
         for (index, c) in self.chars.by_ref() {
-            let index = ByteIndex::from(index);
+            let index = ByteIndex::from(self.offset + index);
             if !saw_word {
                 if !c.is_ascii_whitespace() {
                     saw_word = true;
@@ -104,6 +114,21 @@ mod tests {
         assert_eq!(word.end(), ByteIndex::from(17));
     }
 
+    #[test]
+    fn split_words_starting_at() {
+        let (file_id, source) = file("simple test words");
+        let mut tokenizer = Tokenizer::starting_at(file_id, source, ByteIndex::from(7));
+        let word = tokenizer.next().unwrap();
+        assert_eq!(word.value, "test");
+        assert_eq!(word.start(), ByteIndex::from(7));
+        assert_eq!(word.end(), ByteIndex::from(11));
+        let word = tokenizer.next().unwrap();
+        assert_eq!(word.value, "words");
+        assert_eq!(word.start(), ByteIndex::from(12));
+        assert_eq!(word.end(), ByteIndex::from(17));
+        assert!(tokenizer.next().is_none());
+    }
+
     #[test]
     fn split_words_with_chars() {
         let (file_id, source) = file("n/*ot \n \t  a*/comment");
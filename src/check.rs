@@ -1,89 +1,613 @@
-use crate::cli_reporter::report as cli_report;
-use anyhow::{bail, Result};
+use crate::report_formats::{report, OutputFormat};
+use anyhow::{bail, Context, Result};
 use multisplice::Multisplice;
-use rms_check::{Compatibility, RMSCheck, RMSFile};
+use notify::RecursiveMode;
+use rms_check::{
+    AtomKind, Compatibility, Config, Parser, PositionEncoding, RMSCheck, RMSCheckResult, RMSFile,
+    CONFIG_FILE_NAME,
+};
+use std::collections::HashSet;
 use std::fs::{remove_file, write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct CheckArgs {
-    /// Path to the RMS file.
-    pub file: PathBuf,
-    /// Compatibility level to use when checking the script.
-    pub compatibility: Compatibility,
+    /// Paths to the RMS files. These can also be directories or glob patterns.
+    pub files: Vec<PathBuf>,
+    /// Compatibility level to use when checking the script. If not set, the compatibility from
+    /// the configuration file is used.
+    pub compatibility: Option<Compatibility>,
+    /// Whether to treat the script as a builtin map.
+    pub builtin_map: bool,
+    /// Directory to load included files from.
+    pub include_dir: Option<PathBuf>,
     /// Do not a actually apply fixes.
     pub dry_run: bool,
+    /// How to print the results.
+    pub format: OutputFormat,
+}
+
+/// Is this a file that `check` should pick up when recursing into directories?
+fn is_script_path(path: &Path) -> bool {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false,
+    };
+    name.ends_with(".rms")
+        || name.ends_with(".rms2")
+        || (name.starts_with("ZR@") && name.ends_with(".zip"))
+}
+
+/// Is this a ZR@ map archive?
+fn is_zip_map(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with("ZR@"))
+}
+
+fn collect_dir(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("could not read directory {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_dir(&path, files)?;
+        } else if is_script_path(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Expand directories and glob patterns into a list of script files.
+fn collect_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            collect_dir(path, &mut files)?;
+        } else if path.exists() {
+            files.push(path.clone());
+        } else {
+            let pattern = path.to_string_lossy();
+            let mut matched = false;
+            for entry in glob::glob(&pattern)? {
+                let entry = entry?;
+                matched = true;
+                if entry.is_dir() {
+                    collect_dir(&entry, &mut files)?;
+                } else if is_script_path(&entry) {
+                    files.push(entry);
+                }
+            }
+            if !matched {
+                bail!("{}: no such file or directory", path.display());
+            }
+        }
+    }
+    // The same file can be given more than once, through overlapping globs or different spellings
+    // of its path, so compare canonical paths and keep the first occurrence.
+    let mut seen = HashSet::new();
+    files.retain(|path| seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())));
+    Ok(files)
+}
+
+/// Get the paths to watch for changes in order to notice changes to the given files.
+///
+/// Files are watched through the directory that contains them, because many editors save a file
+/// by renaming a new file over it, which would end a watch on the file itself. Directories are
+/// watched recursively, and for glob patterns this is the closest existing parent directory. The
+/// directories that contain the configuration files for the scripts and the directory that
+/// included files are loaded from are watched too.
+pub fn watch_paths(paths: &[PathBuf], include_dir: Option<&Path>) -> Vec<(PathBuf, RecursiveMode)> {
+    let mut watch = vec![];
+    for path in paths {
+        if path.is_file() {
+            watch.push((parent_dir(path), RecursiveMode::NonRecursive));
+        } else {
+            let ancestor = path
+                .ancestors()
+                .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+                .unwrap_or_else(|| Path::new("."));
+            watch.push((ancestor.to_path_buf(), RecursiveMode::Recursive));
+        }
+    }
+    for script in collect_files(paths).unwrap_or_default() {
+        if let Some(config) = Config::find_path(&script) {
+            watch.push((parent_dir(&config), RecursiveMode::NonRecursive));
+        }
+    }
+    if let Some(include_dir) = include_dir {
+        watch.push((include_dir.to_path_buf(), RecursiveMode::Recursive));
+    }
+    watch.sort();
+    watch.dedup();
+    watch
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    path.parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+        .to_path_buf()
+}
+
+/// Get the canonical form of a path that may not exist (anymore), by canonicalizing its
+/// directory instead.
+fn canonical_path(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+    match (parent_dir(path).canonicalize(), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Get the paths of the files that a script loads from the include directory with `#include` or
+/// `#include_drs`, including the ones that do not exist yet.
+fn include_paths(file: &RMSFile<'_>, include_dir: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    for id in file.script_files().chain(file.included_files()) {
+        for (atom, _) in Parser::new(id, file.source(id)) {
+            match atom.kind {
+                AtomKind::Command { name, arguments }
+                    if (name.value == "#include" || name.value == "#include_drs")
+                        && !arguments.is_empty() =>
+                {
+                    paths.push(canonical_path(&include_dir.join(arguments[0].value)));
+                }
+                _ => (),
+            }
+        }
+    }
+    paths
+}
+
+/// The results of `check --watch`. Only the scripts affected by a change are checked again, but
+/// the results for all scripts are printed after every change.
+pub struct WatchedCheck {
+    args: CheckArgs,
+    /// The scripts that were checked successfully, in the same order as `results`.
+    paths: Vec<PathBuf>,
+    results: Vec<(RMSFile<'static>, RMSCheckResult)>,
+    /// The scripts that could not be checked.
+    errors: Vec<(PathBuf, anyhow::Error)>,
+}
+
+impl WatchedCheck {
+    pub const fn new(args: CheckArgs) -> Self {
+        Self {
+            args,
+            paths: vec![],
+            results: vec![],
+            errors: vec![],
+        }
+    }
+
+    /// Get the scripts that should be checked again after a change to the `changed` path, or
+    /// `None` if the change does not affect any of the scripts.
+    fn changed_scripts(&self, scripts: &[PathBuf], changed: &Path) -> Option<Vec<PathBuf>> {
+        let changed_canonical = canonical_path(changed);
+        if let Some(script) = scripts
+            .iter()
+            .find(|script| canonical_path(script) == changed_canonical)
+        {
+            return Some(vec![script.clone()]);
+        }
+
+        // A configuration file can change the results for every script, and a removed script
+        // should disappear from the results.
+        if changed.file_name() == Some(CONFIG_FILE_NAME.as_ref()) {
+            return Some(scripts.to_vec());
+        }
+        if is_script_path(changed) && !changed.exists() {
+            return Some(vec![]);
+        }
+
+        let include_dir = self.args.include_dir.as_ref()?;
+        let including: Vec<PathBuf> = self
+            .paths
+            .iter()
+            .zip(&self.results)
+            .filter(|(_, (file, _))| include_paths(file, include_dir).contains(&changed_canonical))
+            .map(|(path, _)| path.clone())
+            .collect();
+        if including.is_empty() {
+            None
+        } else {
+            Some(including)
+        }
+    }
+
+    /// Check the scripts that are affected by a change to the `changed` path, or all scripts if it
+    /// is `None`. Returns false if no script was affected.
+    pub fn update(&mut self, changed: Option<&Path>) -> Result<bool> {
+        let scripts = collect_files(&self.args.files)?;
+        let outdated = match changed {
+            Some(changed) => match self.changed_scripts(&scripts, changed) {
+                Some(outdated) => outdated,
+                None => return Ok(false),
+            },
+            None => scripts.clone(),
+        };
+
+        let canonical_scripts: Vec<PathBuf> =
+            scripts.iter().map(|path| canonical_path(path)).collect();
+        let canonical_outdated: Vec<PathBuf> =
+            outdated.iter().map(|path| canonical_path(path)).collect();
+        // Forget the old results of the outdated scripts and of scripts that no longer exist.
+        let keep = |path: &PathBuf| {
+            let path = canonical_path(path);
+            canonical_scripts.contains(&path) && !canonical_outdated.contains(&path)
+        };
+        let mut entries: Vec<(PathBuf, (RMSFile<'static>, RMSCheckResult))> = self
+            .paths
+            .drain(..)
+            .zip(self.results.drain(..))
+            .filter(|(path, _)| keep(path))
+            .collect();
+        self.errors.retain(|(path, _)| keep(path));
+
+        let outdated: Vec<PathBuf> = outdated
+            .into_iter()
+            .filter(|path| canonical_scripts.contains(&canonical_path(path)))
+            .collect();
+        for (path, result) in outdated.iter().zip(check_all(&outdated, &self.args)) {
+            match result {
+                Ok(result) => entries.push((path.clone(), result)),
+                Err(err) => self.errors.push((path.clone(), err)),
+            }
+        }
+
+        let position = |path: &PathBuf| {
+            let path = canonical_path(path);
+            canonical_scripts.iter().position(|script| *script == path)
+        };
+        entries.sort_by_key(|(path, _)| position(path));
+        self.errors.sort_by_key(|(path, _)| position(path));
+        (self.paths, self.results) = entries.into_iter().unzip();
+        Ok(true)
+    }
+
+    /// Print the results for all scripts.
+    pub fn report(&self) -> Result<()> {
+        warn_unknown_lints(&self.paths);
+        for (_, err) in &self.errors {
+            eprintln!("{:#}", err);
+        }
+        report_results(self.args.format, &self.results, self.errors.len())
+    }
+}
+
+fn load_file(path: &Path, args: &CheckArgs) -> Result<RMSFile<'static>> {
+    let mut file =
+        RMSFile::from_path(path).with_context(|| format!("could not read {}", path.display()))?;
+    if let Some(include_dir) = &args.include_dir {
+        file.load_includes(include_dir)?;
+    }
+    Ok(file)
+}
+
+/// Warn about lint names in the configuration files for the scripts that do not belong to any
+/// lint, like misspelled names.
+fn warn_unknown_lints(paths: &[PathBuf]) {
+    let mut configs: Vec<PathBuf> = paths.iter().filter_map(Config::find_path).collect();
+    configs.sort();
+    configs.dedup();
+    let checker = RMSCheck::default();
+    for path in configs {
+        // Configuration files that can not be loaded are reported by the check itself.
+        if let Ok(config) = Config::from_path(&path) {
+            for name in config.unknown_lints(checker.lint_names()) {
+                eprintln!("warning: {}: unknown lint `{}`", path.display(), name);
+            }
+        }
+    }
+}
+
+/// Create a checker using the configuration file for the script and the CLI flags.
+fn create_checker(path: &Path, args: &CheckArgs) -> Result<RMSCheck> {
+    let config = Config::for_script(path)?;
+    let checker = RMSCheck::default()
+        .compatibility(Compatibility::All)
+        .with_config(config)
+        .builtin_map(args.builtin_map);
+    Ok(match args.compatibility {
+        Some(compatibility) => checker.compatibility(compatibility),
+        None => checker,
+    })
+}
+
+fn check_one(path: &Path, args: &CheckArgs) -> Result<(RMSFile<'static>, RMSCheckResult)> {
+    let file = load_file(path, args)?;
+    let result = create_checker(path, args)?.check(&file);
+    Ok((file, result))
+}
+
+/// Check many files in parallel. The results are returned in the same order as the paths.
+fn check_all(
+    paths: &[PathBuf],
+    args: &CheckArgs,
+) -> Vec<Result<(RMSFile<'static>, RMSCheckResult)>> {
+    let results = Mutex::new((0..paths.len()).map(|_| None).collect::<Vec<_>>());
+    let next = AtomicUsize::new(0);
+    let num_threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(paths.len());
+
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let path = match paths.get(index) {
+                    Some(path) => path,
+                    None => break,
+                };
+                let result = check_one(path, args);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every file should have been checked"))
+        .collect()
 }
 
 pub fn cli_check(args: CheckArgs) -> Result<()> {
-    let file = RMSFile::from_path(args.file)?;
-    let checker = RMSCheck::default().compatibility(args.compatibility);
-    let result = checker.check(&file);
-    let has_warnings = result.has_warnings();
+    let paths = collect_files(&args.files)?;
+    if paths.is_empty() {
+        bail!("No files to check");
+    }
 
-    cli_report(&file, result);
+    warn_unknown_lints(&paths);
+    let mut results = vec![];
+    let mut failed = 0;
+    for result in check_all(&paths, &args) {
+        match result {
+            Ok(result) => results.push(result),
+            Err(err) => {
+                eprintln!("{:#}", err);
+                failed += 1;
+            }
+        }
+    }
+    report_results(args.format, &results, failed)
+}
+
+/// Print the results of a check, and fail if there were warnings or `failed` files could not be
+/// checked.
+fn report_results(
+    format: OutputFormat,
+    results: &[(RMSFile<'_>, RMSCheckResult)],
+    failed: usize,
+) -> Result<()> {
+    let has_warnings = results.iter().any(|(_, result)| result.has_warnings());
 
+    report(format, results)?;
+
+    if failed > 0 {
+        bail!("Could not check {} files", failed);
+    }
     if has_warnings {
         bail!("There were warnings");
     }
     Ok(())
 }
 
-pub fn cli_fix(args: CheckArgs) -> Result<()> {
-    let file = RMSFile::from_path(&args.file)?;
-
-    let checker = RMSCheck::default().compatibility(args.compatibility);
-    let result = checker.check(&file);
+fn fix_file(path: &Path, args: &CheckArgs) -> Result<Option<String>> {
+    let (file, result) = check_one(path, args)?;
 
     let mut splicer = Multisplice::new(file.main_source());
 
     if !result.has_warnings() {
         // All good!
-        return Ok(());
+        return Ok(None);
     }
 
     for diagnostic in result.iter() {
         for fix in diagnostic.fixes() {
-            let replacement = match fix.replacement() {
-                Some(replacement) => replacement,
-                None => continue,
-            };
+            // Only the main script is rewritten.
+            if fix
+                .edits()
+                .iter()
+                .any(|edit| edit.location().file() != file.file_id())
+            {
+                continue;
+            }
 
-            let location = fix.location();
-            let start = file
-                .get_location(location.file(), location.start())
-                .unwrap();
-            let end = file.get_location(location.file(), location.end()).unwrap();
-            eprintln!(
-                "autofix {}:{} → {}:{} to {}",
-                start.0 + 1,
-                start.1,
-                end.0 + 1,
-                end.1,
-                replacement
-            );
-            splicer.splice(
-                usize::from(location.start()),
-                usize::from(location.end()),
-                replacement,
-            );
+            for edit in fix.edits() {
+                let location = edit.location();
+                let start = file
+                    .get_location(location.file(), location.start(), PositionEncoding::Utf8)
+                    .unwrap();
+                let end = file
+                    .get_location(location.file(), location.end(), PositionEncoding::Utf8)
+                    .unwrap();
+                eprintln!(
+                    "autofix {}:{} → {}:{} to {}",
+                    start.0 + 1,
+                    start.1,
+                    end.0 + 1,
+                    end.1,
+                    edit.replacement()
+                );
+                splicer.splice(
+                    usize::from(location.start()),
+                    usize::from(location.end()),
+                    edit.replacement(),
+                );
+            }
         }
     }
 
+    Ok(Some(splicer.to_string()))
+}
+
+/// Get the files that `fix` can rewrite. ZR@ maps are skipped, because only the main script
+/// would be written back, replacing the whole archive.
+fn fixable_files(paths: Vec<PathBuf>) -> Vec<PathBuf> {
+    paths
+        .into_iter()
+        .filter(|path| {
+            if is_zip_map(path) {
+                eprintln!("{}: skipping, cannot fix ZR@ maps", path.display());
+                false
+            } else {
+                true
+            }
+        })
+        .collect()
+}
+
+pub fn cli_fix(args: CheckArgs) -> Result<()> {
+    let paths = fixable_files(collect_files(&args.files)?);
+
     if args.dry_run {
-        let temp = format!("{}.tmp", args.file.to_string_lossy());
-        write(&temp, &splicer.to_string())?;
+        let mut temps = vec![];
+        for path in &paths {
+            let fixed = match fix_file(path, &args)? {
+                Some(fixed) => fixed,
+                None => continue,
+            };
+            let temp = PathBuf::from(format!("{}.tmp", path.to_string_lossy()));
+            write(&temp, fixed)?;
+            temps.push(temp);
+        }
+        if temps.is_empty() {
+            return Ok(());
+        }
         let check_result = cli_check(CheckArgs {
-            file: temp.clone().into(),
+            files: temps.clone(),
             ..args
         });
-        remove_file(&temp)?;
+        for temp in temps {
+            remove_file(&temp)?;
+        }
         check_result
     } else {
-        let backup = format!("{}.bak", args.file.to_string_lossy());
-        write(&backup, file.main_source())?;
-        write(&args.file, &splicer.to_string())?;
-        remove_file(&backup)?;
-        cli_check(args)
+        let mut fixed_any = false;
+        for path in &paths {
+            let fixed = match fix_file(path, &args)? {
+                Some(fixed) => fixed,
+                None => continue,
+            };
+            let backup = format!("{}.bak", path.to_string_lossy());
+            std::fs::copy(path, &backup)?;
+            write(path, fixed)?;
+            remove_file(&backup)?;
+            fixed_any = true;
+        }
+        if !fixed_any {
+            return Ok(());
+        }
+        cli_check(CheckArgs {
+            files: paths,
+            ..args
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect_files_once() {
+        let dir = std::env::temp_dir().join(format!("rms-check-collect-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.rms");
+        let b = dir.join("b.rms");
+        write(&a, "").unwrap();
+        write(&b, "").unwrap();
+
+        let files = collect_files(&[
+            a.clone(),
+            dir.join("*.rms"),
+            dir.clone(),
+            dir.join(".").join("b.rms"),
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files.unwrap(), vec![a, b]);
+    }
+
+    #[test]
+    fn fix_skips_zip_maps() {
+        let dir = std::env::temp_dir().join(format!("rms-check-fix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("a.rms");
+        let map = dir.join("ZR@a.zip");
+        write(&script, "").unwrap();
+        write(&map, "").unwrap();
+
+        let files = fixable_files(collect_files(std::slice::from_ref(&dir)).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(files, vec![script]);
+    }
+
+    #[test]
+    fn watch_changed_scripts() {
+        let dir = std::env::temp_dir().join(format!("rms-check-watch-{}", std::process::id()));
+        let include_dir = dir.join("includes");
+        std::fs::create_dir_all(&include_dir).unwrap();
+        let a = dir.join("a.rms");
+        let b = dir.join("b.rms");
+        let shared = include_dir.join("shared.inc");
+        write(&a, "#include shared.inc\n").unwrap();
+        write(&b, "#include_drs missing.inc\n").unwrap();
+        write(&shared, "#const SHARED 1\n").unwrap();
+
+        // A file is watched through its directory, so the watch survives it being replaced.
+        assert_eq!(
+            watch_paths(std::slice::from_ref(&a), None),
+            vec![(dir.clone(), RecursiveMode::NonRecursive)]
+        );
+        assert_eq!(
+            watch_paths(&[dir.join("*.rms")], Some(&include_dir)),
+            vec![
+                (dir.clone(), RecursiveMode::Recursive),
+                (include_dir.clone(), RecursiveMode::Recursive),
+            ]
+        );
+
+        let glob = vec![dir.join("*.rms")];
+        let mut watched = WatchedCheck::new(CheckArgs {
+            files: glob.clone(),
+            builtin_map: true,
+            include_dir: Some(include_dir.clone()),
+            ..Default::default()
+        });
+        assert!(watched.update(None).unwrap());
+        assert_eq!(watched.paths, vec![a.clone(), b.clone()]);
+
+        let scripts = collect_files(&glob).unwrap();
+        let changed = |path: &Path| watched.changed_scripts(&scripts, path);
+        assert_eq!(changed(&dir.join(".").join("b.rms")), Some(vec![b.clone()]));
+        assert_eq!(changed(&dir.join(".a.rms.swp")), None);
+        assert_eq!(changed(&dir.join(CONFIG_FILE_NAME)), Some(scripts.clone()));
+        assert_eq!(changed(&shared), Some(vec![a.clone()]));
+        assert_eq!(
+            changed(&include_dir.join("missing.inc")),
+            Some(vec![b.clone()])
+        );
+        assert_eq!(changed(&include_dir.join("other.inc")), None);
+
+        // Every script stays in the results after one of them is checked again.
+        assert!(watched.update(Some(&b)).unwrap());
+        assert_eq!(watched.paths, vec![a.clone(), b.clone()]);
+        assert!(!watched.update(Some(&dir.join(".a.rms.swp"))).unwrap());
+
+        remove_file(&b).unwrap();
+        let removed = watched.update(Some(&b)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(removed);
+        assert_eq!(watched.paths, vec![a]);
     }
 }
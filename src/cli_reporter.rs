@@ -3,7 +3,7 @@ use ansi_term::Style;
 use codespan_reporting::diagnostic::{Diagnostic, Label, LabelStyle, Severity};
 use codespan_reporting::files::{Error, Files};
 use codespan_reporting::term::{emit, Config};
-use rms_check::{ByteIndex, FileId, RMSCheckResult, RMSFile};
+use rms_check::{ByteIndex, FileId, PositionEncoding, RMSCheckResult, RMSFile};
 use std::ops::Range;
 use termcolor::{ColorChoice, StandardStream};
 
@@ -24,11 +24,11 @@ impl<'a> Files<'a> for Adapter<'a> {
     fn line_range(&'a self, id: Self::FileId, line: usize) -> Result<Range<usize>, Error> {
         let start_of_line = self
             .0
-            .get_byte_index(id, line as u32, 0)
+            .get_byte_index(id, line as u32, 0, PositionEncoding::Utf8)
             .ok_or(Error::FileMissing)?;
         let end_of_line = self
             .0
-            .get_byte_index(id, line as u32 + 1, 0)
+            .get_byte_index(id, line as u32 + 1, 0, PositionEncoding::Utf8)
             .unwrap_or_else(|| ByteIndex::from(self.0.source(id).len()));
         Ok(usize::from(start_of_line)..usize::from(end_of_line))
     }
@@ -36,15 +36,15 @@ impl<'a> Files<'a> for Adapter<'a> {
     fn line_index(&'a self, id: Self::FileId, byte_index: usize) -> Result<usize, Error> {
         let (line, _) = self
             .0
-            .get_location(id, ByteIndex::from(byte_index))
+            .get_location(id, ByteIndex::from(byte_index), PositionEncoding::Utf8)
             .ok_or(Error::FileMissing)?;
         // let start_of_line = self.0.get_byte_index(id, line, 0)?;
         Ok(line as usize)
     }
 }
 
-/// Print rms-check results to standard output.
-pub fn report(file: &RMSFile<'_>, result: RMSCheckResult) {
+/// Print rms-check results for one or more files to standard output.
+pub fn report(results: &[(RMSFile<'_>, RMSCheckResult)]) {
     let mut num_warnings = 0;
     let mut num_errors = 0;
     let mut fixable_warnings = 0;
@@ -82,12 +82,15 @@ pub fn report(file: &RMSFile<'_>, result: RMSCheckResult) {
 
     let config = Config::default();
     let mut stream = StandardStream::stdout(ColorChoice::Auto);
-    for diagnostic in result {
+    for (file, diagnostic) in results
+        .iter()
+        .flat_map(|(file, result)| result.iter().map(move |diagnostic| (file, diagnostic)))
+    {
         emit(
             &mut stream,
             &config,
             &Adapter(file),
-            &to_codespan_diagnostic(&diagnostic),
+            &to_codespan_diagnostic(diagnostic),
         )
         .unwrap();
 
@@ -97,7 +100,7 @@ pub fn report(file: &RMSFile<'_>, result: RMSCheckResult) {
             _ => (),
         }
 
-        if diagnostic.fixes().any(|s| s.replacement().is_some()) {
+        if diagnostic.fixes().any(|fix| !fix.edits().is_empty()) {
             match diagnostic.severity() {
                 rms_check::Severity::ParseError | rms_check::Severity::Error => fixable_errors += 1,
                 rms_check::Severity::Warning => fixable_warnings += 1,
@@ -120,7 +123,16 @@ pub fn report(file: &RMSFile<'_>, result: RMSCheckResult) {
     }
 
     println!();
-    println!("{} errors, {} warnings found.", num_errors, num_warnings);
+    if results.len() > 1 {
+        println!(
+            "{} errors, {} warnings found in {} files.",
+            num_errors,
+            num_warnings,
+            results.len()
+        );
+    } else {
+        println!("{} errors, {} warnings found.", num_errors, num_warnings);
+    }
     if fixable_errors > 0 || fixable_warnings > 0 {
         println!(
             "{} errors, {} warnings fixable using --fix",
@@ -7,14 +7,16 @@
 mod check;
 mod cli_reporter;
 mod language_server;
+mod report_formats;
 mod zip_rms;
 
-use crate::check::{cli_check, cli_fix, CheckArgs};
+use crate::check::{cli_check, cli_fix, watch_paths, CheckArgs, WatchedCheck};
 use crate::language_server::cli_server;
+use crate::report_formats::{OutputFormat, OUTPUT_FORMATS};
 use crate::zip_rms::{cli_pack, cli_unpack};
 use anyhow::Result;
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
-use rms_check::{Compatibility, FormatOptions};
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use rms_check::{Compatibility, Config, FormatOptions, Preprocessor, RMSFile};
 use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc;
@@ -51,31 +53,62 @@ struct CliCompat {
 }
 
 impl CliCompat {
-    fn to_compatibility(&self) -> Compatibility {
+    /// Get the selected compatibility, or `None` if no flag was given.
+    fn to_compatibility(&self) -> Option<Compatibility> {
         if self.definitive_edition {
-            Compatibility::DefinitiveEdition
+            Some(Compatibility::DefinitiveEdition)
         } else if self.wololo_kingdoms {
-            Compatibility::WololoKingdoms
+            Some(Compatibility::WololoKingdoms)
         } else if self.hd_edition {
-            Compatibility::HDEdition
+            Some(Compatibility::HDEdition)
         } else if self.userpatch14 {
-            Compatibility::UserPatch14
+            Some(Compatibility::UserPatch14)
         } else if self.userpatch15 {
-            Compatibility::UserPatch15
+            Some(Compatibility::UserPatch15)
         } else if self.aoc {
-            Compatibility::Conquerors
+            Some(Compatibility::Conquerors)
         } else {
-            Compatibility::All
+            None
         }
     }
 }
 
+/// Map sizes that can be selected in the lobby. Each sets a `<SIZE>_MAP` define.
+const MAP_SIZES: [&str; 7] = [
+    "tiny", "small", "medium", "large", "huge", "gigantic", "ludikris",
+];
+
+/// Player counts that can be selected in the lobby. Each sets a `<N>_PLAYER_GAME` define.
+const PLAYER_COUNTS: [&str; 8] = ["1", "2", "3", "4", "5", "6", "7", "8"];
+
+// CLI flags for checking builtin maps.
+#[derive(Debug, StructOpt)]
+struct CliBuiltin {
+    /// Treat the script as a builtin map, which can use `#include_drs` and `#include`. Scripts can
+    /// also enable this using a `/* Builtin map: yes */` comment.
+    #[structopt(long = "builtin")]
+    builtin_map: bool,
+    /// Directory to load files used by `#include_drs` and `#include` from.
+    #[structopt(long = "include-dir")]
+    include_dir: Option<PathBuf>,
+}
+
 #[derive(Debug, StructOpt)]
 struct CliCheck {
-    /// The file to check.
-    file: PathBuf,
+    /// The files to check. Directories are searched for `.rms`, `.rms2` and `ZR@*.zip` files.
+    /// Glob patterns like `maps/**/*.rms` are also supported.
+    #[structopt(required = true, min_values = 1)]
+    files: Vec<PathBuf>,
     #[structopt(flatten)]
     compat_flags: CliCompat,
+    #[structopt(flatten)]
+    builtin_flags: CliBuiltin,
+    /// The output format.
+    #[structopt(long = "format", default_value = "text", possible_values = &OUTPUT_FORMATS)]
+    format: OutputFormat,
+    /// Check the files again whenever they change.
+    #[structopt(long, short = "w")]
+    watch: bool,
 }
 
 #[derive(Debug, StructOpt)]
@@ -96,16 +129,20 @@ enum CliCommand {
         outdir: PathBuf,
         input: PathBuf,
     },
-    /// Auto-fix problems with a random map script.
+    /// Auto-fix problems with random map scripts.
     #[structopt(name = "fix")]
     Fix {
         /// Don't write the results.
         #[structopt(long = "dry-run")]
         dry_run: bool,
-        /// The file to check.
-        file: PathBuf,
+        /// The files to fix. Directories are searched for `.rms` and `.rms2` files. Glob patterns
+        /// like `maps/**/*.rms` are also supported. ZR@ maps are skipped.
+        #[structopt(required = true, min_values = 1)]
+        files: Vec<PathBuf>,
         #[structopt(flatten)]
         compat_flags: CliCompat,
+        #[structopt(flatten)]
+        builtin_flags: CliBuiltin,
     },
     /// Format the given file.
     #[structopt(name = "format")]
@@ -122,6 +159,24 @@ enum CliCommand {
         #[structopt(long = "no-align-arguments")]
         no_align_arguments: bool,
     },
+    /// Print the script as the game sees it for a lobby configuration, with only the live `if`
+    /// branches.
+    #[structopt(name = "preprocess")]
+    Preprocess {
+        /// The file to preprocess.
+        file: PathBuf,
+        /// Set a `#define` name, like `TEAM1_SIZE2`. Can be given multiple times.
+        #[structopt(long = "define", number_of_values = 1)]
+        defines: Vec<String>,
+        /// The number of players in the lobby, from 1 to 8.
+        #[structopt(long = "players", possible_values = &PLAYER_COUNTS)]
+        players: Option<u8>,
+        /// The map size selected in the lobby.
+        #[structopt(long = "map-size", possible_values = &MAP_SIZES, case_insensitive = true)]
+        map_size: Option<String>,
+        #[structopt(flatten)]
+        compat_flags: CliCompat,
+    },
     /// Syntax check and lint a random map script.
     #[structopt(name = "check")]
     Check(CliCheck),
@@ -139,6 +194,12 @@ pub struct Cli {
     // Compatibility flags for implicit `check`, when not using any subcommand.
     #[structopt(flatten)]
     compat_flags: CliCompat,
+    // Builtin map flags for implicit `check`, when not using any subcommand.
+    #[structopt(flatten)]
+    builtin_flags: CliBuiltin,
+    /// The output format for implicit `check`, when not using any subcommand.
+    #[structopt(long = "format", default_value = "text", possible_values = &OUTPUT_FORMATS)]
+    format: OutputFormat,
     /// The file to check, when not using any subcommand.
     file: Option<String>,
 }
@@ -154,15 +215,37 @@ fn read_input(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
     }
 }
 
-/// Watch a directory for changes, and call the `callback` when something changes.
-fn cli_watch(indir: impl AsRef<Path>, callback: impl Fn() -> Result<()>) -> Result<()> {
-    callback()?;
+/// Watch files or directories for changes, and call the `callback` when something changes. The
+/// `callback` receives the path that changed, or `None` when everything should be updated.
+fn cli_watch(
+    paths: &[(PathBuf, RecursiveMode)],
+    mut callback: impl FnMut(Option<&Path>) -> Result<()>,
+) -> Result<()> {
+    callback(None)?;
     let (tx, rx) = mpsc::channel();
     let mut watcher: RecommendedWatcher = Watcher::new(tx, Duration::from_millis(500))?;
-    watcher.watch(indir.as_ref(), RecursiveMode::Recursive)?;
+    for (path, mode) in paths {
+        watcher.watch(path, *mode)?;
+    }
 
-    while let Ok(_event) = rx.recv() {
-        match callback() {
+    while let Ok(event) = rx.recv() {
+        let changed = match &event {
+            DebouncedEvent::Create(path)
+            | DebouncedEvent::Write(path)
+            | DebouncedEvent::Remove(path)
+            | DebouncedEvent::Rename(_, path) => Some(path.as_path()),
+            DebouncedEvent::Rescan => None,
+            DebouncedEvent::Error(err, _) => {
+                eprintln!("{}", err);
+                continue;
+            }
+            // The debounced watcher sends notices before the actual events; only act on the
+            // latter.
+            DebouncedEvent::NoticeWrite(_)
+            | DebouncedEvent::NoticeRemove(_)
+            | DebouncedEvent::Chmod(_) => continue,
+        };
+        match callback(changed) {
             Ok(_) => (),
             Err(err) => {
                 eprintln!("{}", err);
@@ -184,7 +267,7 @@ fn main() -> Result<()> {
             watch,
         }) => {
             if watch {
-                cli_watch(&indir, || {
+                cli_watch(&[(indir.clone(), RecursiveMode::Recursive)], |_| {
                     cli_pack(&indir, &output)?;
                     println!("wrote {:?}", output);
                     Ok(())
@@ -195,12 +278,16 @@ fn main() -> Result<()> {
         }
         Some(CliCommand::Fix {
             dry_run,
-            file,
+            files,
             compat_flags,
+            builtin_flags,
         }) => cli_fix(CheckArgs {
             compatibility: compat_flags.to_compatibility(),
-            file,
+            builtin_map: builtin_flags.builtin_map,
+            include_dir: builtin_flags.include_dir,
+            files,
             dry_run,
+            ..Default::default()
         }),
         Some(CliCommand::Format {
             file,
@@ -218,20 +305,78 @@ fn main() -> Result<()> {
             println!("{}", rms_check::format(string, options));
             Ok(())
         }
+        Some(CliCommand::Preprocess {
+            file,
+            defines,
+            players,
+            map_size,
+            compat_flags,
+        }) => {
+            let compatibility = match compat_flags.to_compatibility() {
+                Some(compatibility) => compatibility,
+                None => Config::for_script(&file)?
+                    .get_compatibility()
+                    .unwrap_or(Compatibility::All),
+            };
+            let file = RMSFile::from_path(file)?;
+            let mut preprocessor = Preprocessor::new()
+                .compatibility(compatibility)
+                .defines(defines);
+            if let Some(players) = players {
+                preprocessor = preprocessor.players(players);
+            }
+            if let Some(map_size) = map_size {
+                preprocessor = preprocessor.define(format!("{}_MAP", map_size.to_uppercase()));
+            }
+            for name in preprocessor.unknown_defines() {
+                eprintln!("warning: `{}` is not set by the game in any lobby", name);
+            }
+            print!("{}", preprocessor.preprocess(&file).source());
+            Ok(())
+        }
         Some(CliCommand::Server) => {
             cli_server();
             unreachable!();
         }
-        Some(CliCommand::Check(args)) => cli_check(CheckArgs {
-            compatibility: args.compat_flags.to_compatibility(),
-            file: args.file,
-            ..Default::default()
-        }),
+        Some(CliCommand::Check(args)) => {
+            let check_args = CheckArgs {
+                compatibility: args.compat_flags.to_compatibility(),
+                builtin_map: args.builtin_flags.builtin_map,
+                include_dir: args.builtin_flags.include_dir,
+                files: args.files,
+                format: args.format,
+                ..Default::default()
+            };
+            if args.watch {
+                let paths = watch_paths(&check_args.files, check_args.include_dir.as_deref());
+                let mut watched = WatchedCheck::new(check_args);
+                cli_watch(&paths, |changed| {
+                    if !watched.update(changed)? {
+                        return Ok(());
+                    }
+                    // Clear the screen so only the latest results are visible.
+                    print!("\x1B[2J\x1B[1;1H");
+                    if let Err(err) = watched.report() {
+                        eprintln!("{}", err);
+                    }
+                    Ok(())
+                })
+            } else {
+                cli_check(check_args)
+            }
+        }
         None => {
-            let args = CliCheck::from_args();
+            // Without a file, this prints the usage error for the implicit `check`.
+            let file = match args.file {
+                Some(file) => PathBuf::from(file),
+                None => CliCheck::from_args().files.remove(0),
+            };
             cli_check(CheckArgs {
                 compatibility: args.compat_flags.to_compatibility(),
-                file: args.file,
+                builtin_map: args.builtin_flags.builtin_map,
+                include_dir: args.builtin_flags.include_dir,
+                files: vec![file],
+                format: args.format,
                 ..Default::default()
             })
         }
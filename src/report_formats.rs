@@ -0,0 +1,502 @@
+//! Machine-readable output formats for check results.
+
+use crate::cli_reporter::report as cli_report;
+use rms_check::{
+    ByteIndex, Diagnostic, FileId, Fix, PositionEncoding, RMSCheckResult, RMSFile, SourceLocation,
+};
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Names of the supported output formats, for use in `possible_values`.
+pub const OUTPUT_FORMATS: [&str; 5] = ["text", "json", "sarif", "checkstyle", "github"];
+
+/// How to print check results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Human-readable text with source code snippets.
+    #[default]
+    Text,
+    /// A JSON object with all diagnostics.
+    Json,
+    /// A SARIF 2.1.0 log, for code scanning tools.
+    Sarif,
+    /// Checkstyle XML.
+    Checkstyle,
+    /// GitHub Actions workflow commands, which show up as annotations on pull requests.
+    Github,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "sarif" => Ok(OutputFormat::Sarif),
+            "checkstyle" => Ok(OutputFormat::Checkstyle),
+            "github" => Ok(OutputFormat::Github),
+            _ => Err(format!("unknown output format `{}`", format)),
+        }
+    }
+}
+
+/// A 1-based line and column position. Columns count characters, not bytes.
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    line: u32,
+    column: u32,
+}
+
+fn position(file: &RMSFile<'_>, id: FileId, index: ByteIndex) -> Position {
+    let (line, column) = file
+        .get_location(id, index, PositionEncoding::Utf32)
+        .unwrap_or((0, 0));
+    Position {
+        line: line + 1,
+        column: column + 1,
+    }
+}
+
+fn severity_name(diagnostic: &Diagnostic) -> &'static str {
+    match diagnostic.severity() {
+        rms_check::Severity::ParseError | rms_check::Severity::Error => "error",
+        rms_check::Severity::Warning => "warning",
+        rms_check::Severity::Hint => "hint",
+    }
+}
+
+fn json_location(file: &RMSFile<'_>, location: SourceLocation) -> Value {
+    let start = position(file, location.file(), location.start());
+    let end = position(file, location.file(), location.end());
+    json!({
+        "file": file.name(location.file()),
+        "start": { "line": start.line, "column": start.column, "offset": usize::from(location.start()) },
+        "end": { "line": end.line, "column": end.column, "offset": usize::from(location.end()) },
+    })
+}
+
+fn json_fix(file: &RMSFile<'_>, fix: &Fix) -> Value {
+    json!({
+        "message": fix.message(),
+        "location": json_location(file, fix.location()),
+        "replacement": fix.replacement(),
+        "edits": fix.edits().iter().map(|edit| json!({
+            "location": json_location(file, edit.location()),
+            "replacement": edit.replacement(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn json_diagnostic(file: &RMSFile<'_>, diagnostic: &Diagnostic) -> Value {
+    json!({
+        "severity": severity_name(diagnostic),
+        "code": diagnostic.code(),
+        "message": diagnostic.message(),
+        "location": json_location(file, diagnostic.location()),
+        "labels": diagnostic.labels().map(|label| json!({
+            "message": label.message(),
+            "location": json_location(file, label.location()),
+        })).collect::<Vec<_>>(),
+        "fixes": diagnostic.fixes().map(|fix| json_fix(file, fix)).collect::<Vec<_>>(),
+        "suggestions": diagnostic.suggestions().map(|fix| json_fix(file, fix)).collect::<Vec<_>>(),
+    })
+}
+
+/// The results of checking one or more files.
+type Results<'a> = [(RMSFile<'a>, RMSCheckResult)];
+
+/// Iterate over all diagnostics, with the file they belong to.
+fn diagnostics<'a>(
+    results: &'a Results<'_>,
+) -> impl Iterator<Item = (&'a RMSFile<'a>, &'a Diagnostic)> {
+    results
+        .iter()
+        .flat_map(|(file, result)| result.iter().map(move |diagnostic| (file, diagnostic)))
+}
+
+fn report_json(out: &mut impl Write, results: &Results<'_>) -> io::Result<()> {
+    let count = |severity: &str| {
+        diagnostics(results)
+            .filter(|(_, diagnostic)| severity_name(diagnostic) == severity)
+            .count()
+    };
+    let output = json!({
+        "diagnostics": diagnostics(results).map(|(file, diagnostic)| json_diagnostic(file, diagnostic)).collect::<Vec<_>>(),
+        "files": results.len(),
+        "errors": count("error"),
+        "warnings": count("warning"),
+        "hints": count("hint"),
+    });
+    serde_json::to_writer_pretty(&mut *out, &output)?;
+    writeln!(out)
+}
+
+fn sarif_region(file: &RMSFile<'_>, location: SourceLocation) -> Value {
+    let start = position(file, location.file(), location.start());
+    let end = position(file, location.file(), location.end());
+    json!({
+        "startLine": start.line,
+        "startColumn": start.column,
+        "endLine": end.line,
+        "endColumn": end.column,
+    })
+}
+
+fn sarif_location(file: &RMSFile<'_>, location: SourceLocation) -> Value {
+    json!({
+        "physicalLocation": {
+            "artifactLocation": { "uri": file.name(location.file()) },
+            "region": sarif_region(file, location),
+        },
+    })
+}
+
+fn sarif_result(file: &RMSFile<'_>, diagnostic: &Diagnostic) -> Value {
+    let level = match diagnostic.severity() {
+        rms_check::Severity::ParseError | rms_check::Severity::Error => "error",
+        rms_check::Severity::Warning => "warning",
+        rms_check::Severity::Hint => "note",
+    };
+    let mut result = json!({
+        "level": level,
+        "message": { "text": diagnostic.message() },
+        "locations": [sarif_location(file, diagnostic.location())],
+        "relatedLocations": diagnostic.labels().map(|label| {
+            let mut location = sarif_location(file, label.location());
+            location["message"] = json!({ "text": label.message() });
+            location
+        }).collect::<Vec<_>>(),
+        "fixes": diagnostic
+            .fixes()
+            .chain(diagnostic.suggestions())
+            .filter(|fix| !fix.edits().is_empty())
+            .map(|fix| {
+                // SARIF groups replacements by the file they change.
+                let mut changes: Vec<(FileId, Vec<Value>)> = vec![];
+                for edit in fix.edits() {
+                    let replacement = json!({
+                        "deletedRegion": sarif_region(file, edit.location()),
+                        "insertedContent": { "text": edit.replacement() },
+                    });
+                    let file_id = edit.location().file();
+                    match changes.iter_mut().find(|(id, _)| *id == file_id) {
+                        Some((_, replacements)) => replacements.push(replacement),
+                        None => changes.push((file_id, vec![replacement])),
+                    }
+                }
+                json!({
+                    "description": { "text": fix.message() },
+                    "artifactChanges": changes.into_iter().map(|(file_id, replacements)| json!({
+                        "artifactLocation": { "uri": file.name(file_id) },
+                        "replacements": replacements,
+                    })).collect::<Vec<_>>(),
+                })
+            })
+            .collect::<Vec<_>>(),
+    });
+    if let Some(code) = diagnostic.code() {
+        result["ruleId"] = json!(code);
+    }
+    result
+}
+
+fn report_sarif(out: &mut impl Write, results: &Results<'_>) -> io::Result<()> {
+    let mut rules = diagnostics(results)
+        .filter_map(|(_, diagnostic)| diagnostic.code())
+        .collect::<Vec<_>>();
+    rules.sort_unstable();
+    rules.dedup();
+
+    let output = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "rms-check",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/goto-bus-stop/rms-check",
+                    "rules": rules.iter().map(|id| json!({ "id": id })).collect::<Vec<_>>(),
+                },
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": diagnostics(results).map(|(file, diagnostic)| sarif_result(file, diagnostic)).collect::<Vec<_>>(),
+        }],
+    });
+    serde_json::to_writer_pretty(&mut *out, &output)?;
+    writeln!(out)
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn report_checkstyle(out: &mut impl Write, results: &Results<'_>) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<checkstyle version="4.3">"#)?;
+    for (file, result) in results {
+        report_checkstyle_file(out, file, result)?;
+    }
+    writeln!(out, "</checkstyle>")
+}
+
+fn report_checkstyle_file(
+    out: &mut impl Write,
+    file: &RMSFile<'_>,
+    result: &RMSCheckResult,
+) -> io::Result<()> {
+    let mut files = vec![];
+    for diagnostic in result.iter() {
+        let id = diagnostic.location().file();
+        if !files.contains(&id) {
+            files.push(id);
+        }
+    }
+    for id in files {
+        writeln!(out, r#"  <file name="{}">"#, escape_xml(file.name(id)))?;
+        for diagnostic in result.iter().filter(|d| d.location().file() == id) {
+            let start = position(file, id, diagnostic.location().start());
+            let severity = match diagnostic.severity() {
+                rms_check::Severity::ParseError | rms_check::Severity::Error => "error",
+                rms_check::Severity::Warning => "warning",
+                rms_check::Severity::Hint => "info",
+            };
+            writeln!(
+                out,
+                r#"    <error line="{}" column="{}" severity="{}" message="{}" source="rms-check.{}"/>"#,
+                start.line,
+                start.column,
+                severity,
+                escape_xml(diagnostic.message()),
+                escape_xml(diagnostic.code().unwrap_or("unknown")),
+            )?;
+        }
+        writeln!(out, "  </file>")?;
+    }
+    Ok(())
+}
+
+/// Escape a message for a GitHub Actions workflow command.
+fn escape_github_data(text: &str) -> String {
+    text.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a property value for a GitHub Actions workflow command.
+fn escape_github_property(text: &str) -> String {
+    escape_github_data(text)
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+fn report_github(out: &mut impl Write, results: &Results<'_>) -> io::Result<()> {
+    for (file, diagnostic) in diagnostics(results) {
+        let location = diagnostic.location();
+        let start = position(file, location.file(), location.start());
+        let end = position(file, location.file(), location.end());
+        let command = match diagnostic.severity() {
+            rms_check::Severity::ParseError | rms_check::Severity::Error => "error",
+            rms_check::Severity::Warning => "warning",
+            rms_check::Severity::Hint => "notice",
+        };
+        let title = match diagnostic.code() {
+            Some(code) => format!("rms-check ({})", code),
+            None => "rms-check".to_string(),
+        };
+
+        let mut message = diagnostic.message().to_string();
+        for fix in diagnostic.fixes().chain(diagnostic.suggestions()) {
+            message.push_str("\nsuggestion: ");
+            message.push_str(fix.message());
+        }
+
+        writeln!(
+            out,
+            "::{} file={},line={},col={},endLine={},endColumn={},title={}::{}",
+            command,
+            escape_github_property(file.name(location.file())),
+            start.line,
+            start.column,
+            end.line,
+            end.column,
+            escape_github_property(&title),
+            escape_github_data(&message),
+        )?;
+    }
+    Ok(())
+}
+
+/// Print rms-check results for one or more files to standard output in the given format.
+pub fn report(format: OutputFormat, results: &Results<'_>) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    match format {
+        OutputFormat::Text => {
+            cli_report(results);
+            Ok(())
+        }
+        OutputFormat::Json => report_json(&mut out, results),
+        OutputFormat::Sarif => report_sarif(&mut out, results),
+        OutputFormat::Checkstyle => report_checkstyle(&mut out, results),
+        OutputFormat::Github => report_github(&mut out, results),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rms_check::{Atom, AtomKind, Lint, ParseState, RMSCheck};
+
+    const MESSAGE: &str = "Ünïcode \"quoted\" <tag> & 100%, done: yes";
+
+    /// Reports every section header with a message that needs escaping.
+    struct SectionLint;
+
+    impl Lint for SectionLint {
+        fn name(&self) -> &'static str {
+            "section"
+        }
+        fn lint_atom(&mut self, _state: &mut ParseState<'_>, atom: &Atom<'_>) -> Vec<Diagnostic> {
+            match atom.kind {
+                AtomKind::Section { .. } => vec![Diagnostic::warning(atom.location, MESSAGE)
+                    .suggest(Fix::new(atom.location, "Replace with <X>").replace("<X> & é"))],
+                _ => vec![],
+            }
+        }
+    }
+
+    /// Check a script with a section header that starts at byte 9, but at character 8.
+    fn check() -> Vec<(RMSFile<'static>, RMSCheckResult)> {
+        let file = RMSFile::from_string("maps/a&b, v2.rms", "/* é */ <PLAYER_SETUP>\n");
+        let result = RMSCheck::new()
+            .with_lint(Box::new(SectionLint))
+            .check(&file);
+        vec![(file, result)]
+    }
+
+    fn output(report: fn(&mut Vec<u8>, &Results<'_>) -> io::Result<()>) -> String {
+        let mut out = vec![];
+        report(&mut out, &check()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json() {
+        let output: Value = serde_json::from_str(&output(report_json)).unwrap();
+        assert_eq!(output["warnings"], 1);
+        let diagnostic = &output["diagnostics"][0];
+        assert_eq!(diagnostic["message"], MESSAGE);
+        assert_eq!(diagnostic["code"], "section");
+        assert_eq!(diagnostic["location"]["file"], "maps/a&b, v2.rms");
+        assert_eq!(
+            diagnostic["location"]["start"],
+            json!({ "line": 1, "column": 9, "offset": 9 })
+        );
+        assert_eq!(diagnostic["location"]["end"]["column"], 23);
+        assert_eq!(diagnostic["suggestions"][0]["replacement"], "<X> & é");
+    }
+
+    #[test]
+    fn sarif() {
+        let output: Value = serde_json::from_str(&output(report_sarif)).unwrap();
+        let run = &output["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"], json!([{ "id": "section" }]));
+        let result = &run["results"][0];
+        assert_eq!(result["message"]["text"], MESSAGE);
+        assert_eq!(result["ruleId"], "section");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "maps/a&b, v2.rms");
+        assert_eq!(
+            location["region"],
+            json!({ "startLine": 1, "startColumn": 9, "endLine": 1, "endColumn": 23 })
+        );
+        let change = &result["fixes"][0]["artifactChanges"][0];
+        assert_eq!(
+            change["replacements"][0]["insertedContent"]["text"],
+            "<X> & é"
+        );
+    }
+
+    fn unescape_xml(text: &str) -> String {
+        text.replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&#10;", "\n")
+            .replace("&amp;", "&")
+    }
+
+    /// Get the value of an XML attribute on a line.
+    fn attribute(line: &str, name: &str) -> String {
+        let start = line.find(&format!(" {}=\"", name)).unwrap() + name.len() + 3;
+        let end = start + line[start..].find('"').unwrap();
+        unescape_xml(&line[start..end])
+    }
+
+    #[test]
+    fn checkstyle() {
+        let output = output(report_checkstyle);
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        assert_eq!(attribute(lines[2], "name"), "maps/a&b, v2.rms");
+        assert_eq!(attribute(lines[3], "message"), MESSAGE);
+        assert_eq!(attribute(lines[3], "line"), "1");
+        assert_eq!(attribute(lines[3], "column"), "9");
+        assert_eq!(attribute(lines[3], "source"), "rms-check.section");
+        assert_eq!(lines[5], "</checkstyle>");
+    }
+
+    fn unescape_github(text: &str) -> String {
+        text.replace("%0D", "\r")
+            .replace("%0A", "\n")
+            .replace("%3A", ":")
+            .replace("%2C", ",")
+            .replace("%25", "%")
+    }
+
+    #[test]
+    fn github() {
+        let output = output(report_github);
+        let line = output.strip_suffix('\n').unwrap();
+        assert!(!line.contains('\n'));
+        let line = line.strip_prefix("::warning ").unwrap();
+        let (properties, data) = line.split_at(line.find("::").unwrap());
+        let properties = properties
+            .split(',')
+            .map(|property| {
+                let (name, value) = property.split_at(property.find('=').unwrap());
+                (name, unescape_github(&value[1..]))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            properties,
+            vec![
+                ("file", "maps/a&b, v2.rms".to_string()),
+                ("line", "1".to_string()),
+                ("col", "9".to_string()),
+                ("endLine", "1".to_string()),
+                ("endColumn", "23".to_string()),
+                ("title", "rms-check (section)".to_string()),
+            ]
+        );
+        assert_eq!(
+            unescape_github(&data[2..]),
+            format!("{}\nsuggestion: Replace with <X>", MESSAGE)
+        );
+    }
+}
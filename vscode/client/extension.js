@@ -4,7 +4,7 @@ const { promisify } = require('util')
 const { ZipFile } = require('yazl')
 const zip = require('./store-zip')
 const concat = promisify(require('simple-concat'))
-const { commands, window, workspace, FileSystemError, FileType } = require('vscode')
+const { commands, window, workspace, FileSystemError, FileType, StatusBarAlignment } = require('vscode')
 const { LanguageClient, TransportKind } = require('vscode-languageclient')
 // `path` alias that @zeit/ncc can't detect, so we can refer to truly external paths
 const externalPath = (() => path)()
@@ -76,12 +76,42 @@ async function editZrMap (uri) {
 exports.activate = function activate (context) {
   const serverOptions = useWasm ? getWasmServerOptions() : getNativeServerOptions()
   const clientOptions = {
-    documentSelector: ['aoe2-rms']
+    documentSelector: ['aoe2-rms'],
+    initializationOptions: {
+      defaultCompatibility: globalConfig.defaultCompatibility
+    },
+    synchronize: {
+      configurationSection: 'rmsCheck',
+      fileEvents: workspace.createFileSystemWatcher('**/rms-check.toml')
+    }
   }
 
   client = new LanguageClient('rmsCheck', 'rms-check', serverOptions, clientOptions)
   client.start()
 
+  // Show the compatibility that is in effect for the active file.
+  const compatibilityItem = window.createStatusBarItem(StatusBarAlignment.Right)
+  compatibilityItem.tooltip = 'rms-check compatibility'
+  context.subscriptions.push(compatibilityItem)
+  const compatibilities = new Map()
+  const updateCompatibilityItem = () => {
+    const editor = window.activeTextEditor
+    const compatibility = editor && compatibilities.get(editor.document.uri.toString())
+    if (compatibility) {
+      compatibilityItem.text = `RMS: ${compatibility}`
+      compatibilityItem.show()
+    } else {
+      compatibilityItem.hide()
+    }
+  }
+  client.onReady().then(() => {
+    client.onNotification('rms-check/compatibility', ({ uri, compatibility }) => {
+      compatibilities.set(uri, compatibility)
+      updateCompatibilityItem()
+    })
+  })
+  context.subscriptions.push(window.onDidChangeActiveTextEditor(updateCompatibilityItem))
+
   context.subscriptions.push(commands.registerCommand('rms-check.edit-zr-map', async (uri) => {
     try {
       await editZrMap(uri)